@@ -0,0 +1,192 @@
+//! `--anomaly-threshold`: results much slower than their URL's historical median, taken
+//! from the `--sqlite` database or the `--baseline` report.
+
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{paint, test_id, truncate_string, Args, Config, UrlTestResult, COLOR_YELLOW};
+
+type HistoryResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Checks the flags before anything is sent, so a run isn't wasted on a missing history.
+pub fn validate_args(args: &Args) -> Result<(), String> {
+    let Some(threshold) = args.anomaly_threshold else {
+        return Ok(());
+    };
+    if !(threshold > 0.0 && threshold.is_finite()) {
+        return Err("--anomaly-threshold must be a positive number".to_string());
+    }
+    if args.sqlite.is_none() && args.baseline.is_none() {
+        return Err(
+            "--anomaly-threshold needs history to compare against: --sqlite or --baseline"
+                .to_string(),
+        );
+    }
+    if args.anomaly_history == 0 || args.anomaly_min_samples == 0 {
+        return Err("--anomaly-history and --anomaly-min-samples must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+/// Durations of each test's last passing results, oldest first, keyed by test_id.
+struct History {
+    source: String,
+    durations: HashMap<String, Vec<f64>>,
+}
+
+/// Reads the history from the `--sqlite` database if given (its earlier runs), else from
+/// the `--baseline` report. A database that doesn't exist yet has no history.
+fn load_history(args: &Args, config: &Config) -> HistoryResult<History> {
+    let keep = args.anomaly_history;
+    if let Some(path) = &args.sqlite {
+        let mut durations: HashMap<String, Vec<f64>> = HashMap::new();
+        if std::path::Path::new(path).exists() {
+            let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .map_err(|e| format!("Failed to open SQLite history '{}': {}", path, e))?;
+            let mut query = conn.prepare(
+                "SELECT test_id, duration_secs FROM (
+                     SELECT test_id, duration_secs, run_id,
+                            ROW_NUMBER() OVER (PARTITION BY test_id ORDER BY run_id DESC) AS age
+                     FROM results WHERE passed = 1
+                 ) WHERE age <= ?1 ORDER BY run_id",
+            )?;
+            let rows = query.query_map(params![keep as i64], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+            })?;
+            for row in rows {
+                let (key, duration) = row?;
+                durations.entry(key).or_default().push(duration);
+            }
+        }
+        return Ok(History {
+            source: path.clone(),
+            durations,
+        });
+    }
+
+    let path = args.baseline.as_deref().unwrap_or_default();
+    let mut reader = csv::Reader::from_path(path)
+        .map_err(|e| format!("Failed to open baseline '{}': {}", path, e))?;
+    let mut durations: HashMap<String, Vec<f64>> = HashMap::new();
+    for row in reader.deserialize() {
+        let row: HistoryRow = row.map_err(|e| format!("Invalid baseline '{}': {}", path, e))?;
+        let Some(duration) = row.duration_secs.filter(|_| row.passed) else {
+            continue;
+        };
+        let key = row.test_id.filter(|id| !id.is_empty()).unwrap_or_else(|| {
+            test_id(
+                &row.environment_name,
+                &row.url,
+                &config.test_id_ignored_params,
+            )
+        });
+        durations.entry(key).or_default().push(duration);
+    }
+    // A report may hold several runs; only the last ones count, as with SQLite
+    for samples in durations.values_mut() {
+        let excess = samples.len().saturating_sub(keep);
+        samples.drain(..excess);
+    }
+    Ok(History {
+        source: path.to_string(),
+        durations,
+    })
+}
+
+/// A row of a saved CSV report, as far as the history needs it.
+#[derive(Debug, Deserialize)]
+struct HistoryRow {
+    environment_name: String,
+    url: String,
+    #[serde(default)]
+    test_id: Option<String>,
+    passed: bool,
+    #[serde(default)]
+    duration_secs: Option<f64>,
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// How a run's results were checked, for the report section.
+pub struct AnomalyCheck {
+    threshold: f64,
+    source: String,
+    // Results whose URL had too little history
+    skipped: usize,
+}
+
+/// Sets `historical_median_secs` and `anomaly` on every result whose URL has at least
+/// `--anomaly-min-samples` passing results in its history.
+pub fn flag_anomalies(
+    results: &mut [UrlTestResult],
+    args: &Args,
+    config: &Config,
+) -> HistoryResult<Option<AnomalyCheck>> {
+    let Some(threshold) = args.anomaly_threshold else {
+        return Ok(None);
+    };
+    let history = load_history(args, config)?;
+    let mut skipped = 0;
+    for res in results.iter_mut() {
+        let samples = history
+            .durations
+            .get(&res.test_id)
+            .filter(|samples| samples.len() >= args.anomaly_min_samples);
+        match samples {
+            Some(samples) => {
+                let median = median(samples.clone());
+                res.historical_median_secs = Some(median);
+                res.anomaly = Some(res.duration_secs > median * threshold);
+            }
+            None => skipped += 1,
+        }
+    }
+    Ok(Some(AnomalyCheck {
+        threshold,
+        source: history.source,
+        skipped,
+    }))
+}
+
+/// Lists the anomalous results with their ratio to the median.
+pub fn print_anomalies(results: &[UrlTestResult], check: &AnomalyCheck, args: &Args) {
+    let anomalies: Vec<&UrlTestResult> = results
+        .iter()
+        .filter(|res| res.anomaly == Some(true))
+        .collect();
+    println!(
+        "\n--- Latency Anomalies (above {}× the median of {}) ---",
+        check.threshold, check.source
+    );
+    for res in &anomalies {
+        let median = res.historical_median_secs.unwrap_or_default();
+        println!(
+            "{: <10} | {} | {:.3}s vs median {:.3}s ({})",
+            truncate_string(&res.environment_name, 10),
+            res.url,
+            res.duration_secs,
+            median,
+            paint(
+                &format!("{:.1}×", res.duration_secs / median),
+                COLOR_YELLOW,
+                args
+            )
+        );
+    }
+    println!(
+        "{} of {} results anomalous; {} skipped for lack of history (fewer than {} passing results).",
+        anomalies.len(),
+        results.len() - check.skipped,
+        check.skipped,
+        args.anomaly_min_samples
+    );
+}
@@ -0,0 +1,294 @@
+//! `[auth_request]`: one bearer token fetched at startup and sent with every request,
+//! fetched again once it expires.
+
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::{build_request, parse_method, usage};
+
+/// The `[auth_request]` table, e.g. an OAuth client-credentials token request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthRequest {
+    // Absolute URL of the token endpoint
+    pub url: String,
+    #[serde(default = "default_auth_method")]
+    pub method: String,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    // JSON pointer to the token in the response
+    #[serde(default = "default_token_pointer")]
+    pub token_pointer: String,
+    // JSON pointer to the token's lifetime in seconds; without it the token never expires
+    #[serde(default = "default_expires_in_pointer")]
+    pub expires_in_pointer: String,
+    // Fetch a new token this long before the old one expires, so requests in flight don't
+    // carry an expired one
+    #[serde(default = "default_refresh_before_secs")]
+    pub refresh_before_secs: u64,
+}
+
+fn default_auth_method() -> String {
+    "POST".to_string()
+}
+
+fn default_token_pointer() -> String {
+    "/access_token".to_string()
+}
+
+fn default_expires_in_pointer() -> String {
+    "/expires_in".to_string()
+}
+
+fn default_refresh_before_secs() -> u64 {
+    30
+}
+
+/// Checks the parts of `[auth_request]` that can be checked without sending it.
+pub fn validate(request: &AuthRequest) -> Result<(), String> {
+    if !request.url.starts_with("http://") && !request.url.starts_with("https://") {
+        return Err(format!(
+            "auth_request url '{}' must be an absolute http(s) URL",
+            request.url
+        ));
+    }
+    parse_method(&request.method).map_err(|e| format!("auth_request: {}", e))?;
+    for pointer in [&request.token_pointer, &request.expires_in_pointer] {
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            return Err(format!(
+                "auth_request pointer '{}' must be empty or start with '/'",
+                pointer
+            ));
+        }
+    }
+    Ok(())
+}
+
+struct Token {
+    // `Bearer <token>`, ready to send
+    authorization: HeaderValue,
+    // When to fetch a new one
+    refresh_at: Option<Instant>,
+}
+
+// What a refresh hands to every request waiting on it; `None` until it completes
+type RefreshOutcome = Option<Result<HeaderValue, String>>;
+
+struct State {
+    token: Token,
+    // The refresh in flight, if any
+    refresh: Option<watch::Receiver<RefreshOutcome>>,
+}
+
+/// The run's bearer token, shared by every request.
+pub struct BearerToken {
+    request: AuthRequest,
+    client: reqwest::Client,
+    state: Arc<Mutex<State>>,
+}
+
+impl BearerToken {
+    /// Sends the auth request once; the run doesn't start without a token.
+    pub async fn fetch(request: &AuthRequest, client: &reqwest::Client) -> Result<Self, String> {
+        let token = request_token(request, client).await?;
+        Ok(BearerToken {
+            request: request.clone(),
+            client: client.clone(),
+            state: Arc::new(Mutex::new(State {
+                token,
+                refresh: None,
+            })),
+        })
+    }
+
+    /// The `Authorization` header value, refreshing the token first if it is due. Only one
+    /// refresh is sent at a time: requests that find the token due meanwhile wait for it and
+    /// share its token or error, and don't hold up requests whose token is still good. A
+    /// failed refresh is retried by the next request.
+    pub async fn authorization(&self) -> Result<HeaderValue, String> {
+        let mut refresh = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let due = state
+                .token
+                .refresh_at
+                .is_some_and(|refresh_at| Instant::now() >= refresh_at);
+            if !due {
+                return Ok(state.token.authorization.clone());
+            }
+            state
+                .refresh
+                .get_or_insert_with(|| self.start_refresh())
+                .clone()
+        };
+        let outcome = refresh
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| "auth_request: token refresh was abandoned".to_string())?;
+        Option::clone(&outcome).unwrap_or_else(|| Err("auth_request: no token".to_string()))
+    }
+
+    // Runs in its own task, so the refresh still completes for everyone waiting on it when
+    // the request that started it is cancelled
+    fn start_refresh(&self) -> watch::Receiver<RefreshOutcome> {
+        let (outcome_tx, outcome_rx) = watch::channel(None);
+        let request = self.request.clone();
+        let client = self.client.clone();
+        let state = Arc::clone(&self.state);
+        tokio::spawn(async move {
+            let fetched = request_token(&request, &client).await;
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.refresh = None;
+            let outcome = fetched.map(|token| {
+                let authorization = token.authorization.clone();
+                state.token = token;
+                authorization
+            });
+            let _ = outcome_tx.send(Some(outcome));
+        });
+        outcome_rx
+    }
+}
+
+async fn request_token(request: &AuthRequest, client: &reqwest::Client) -> Result<Token, String> {
+    let method = parse_method(&request.method)?;
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &request.headers {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid auth_request header name '{}'", name))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format!("invalid value for auth_request header '{}'", name))?;
+        headers.insert(name, value);
+    }
+    let label = format!("{} {}", method, request.url);
+    usage::count_request();
+    let response = build_request(
+        client,
+        method,
+        &request.url,
+        request.body.as_deref(),
+        &headers,
+    )
+    .send()
+    .await
+    .map_err(|e| format!("{}: {}", label, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{}: HTTP Status Error: {}", label, status));
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| format!("{}: {}", label, e))?;
+    usage::count_bytes(body.len());
+    let json: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| format!("{}: response is not JSON: {}", label, e))?;
+    let token = json
+        .pointer(&request.token_pointer)
+        .and_then(|token| token.as_str())
+        .ok_or_else(|| {
+            format!(
+                "{}: no string at token_pointer '{}'",
+                label, request.token_pointer
+            )
+        })?;
+    let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token))
+        .map_err(|_| format!("{}: token is not a valid header value", label))?;
+    authorization.set_sensitive(true);
+    // Lifetimes are numbers in OAuth, but some servers send them as strings
+    let expires_in = json.pointer(&request.expires_in_pointer).and_then(|value| {
+        value
+            .as_u64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+    });
+    Ok(Token {
+        authorization,
+        refresh_at: expires_in.map(|secs| {
+            Instant::now() + Duration::from_secs(secs.saturating_sub(request.refresh_before_secs))
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // A token endpoint on a random local port whose tokens are due at once. It answers slowly
+    // so concurrent refreshes overlap, and fails every request after the first if `fail_refresh`.
+    async fn token_server(fail_refresh: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                tokio::spawn(async move {
+                    let mut request = [0u8; 4096];
+                    let _ = socket.read(&mut request).await;
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let (status, body) = if fail_refresh && count > 1 {
+                        ("500 Internal Server Error", String::new())
+                    } else {
+                        (
+                            "200 OK",
+                            format!(r#"{{"access_token": "t{}", "expires_in": 0}}"#, count),
+                        )
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    async fn concurrent_authorizations(url: &str) -> Vec<Result<HeaderValue, String>> {
+        let request: AuthRequest = toml::from_str(&format!("url = \"{}\"", url)).unwrap();
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let token = Arc::new(BearerToken::fetch(&request, &client).await.unwrap());
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let token = Arc::clone(&token);
+                tokio::spawn(async move { token.authorization().await })
+            })
+            .collect();
+        let mut outcomes = Vec::new();
+        for task in tasks {
+            outcomes.push(task.await.unwrap());
+        }
+        outcomes
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_refresh() {
+        let (url, requests) = token_server(false).await;
+        let outcomes = concurrent_authorizations(&url).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        for outcome in outcomes {
+            assert_eq!(outcome.unwrap(), "Bearer t2");
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_a_failed_refresh() {
+        let (url, requests) = token_server(true).await;
+        let outcomes = concurrent_authorizations(&url).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        for outcome in outcomes {
+            assert!(outcome.unwrap_err().contains("500"));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::Instant;
+
+// Number of recent outcomes the error rate is computed over
+const WINDOW: usize = 10;
+// Fewest outcomes needed before the limit is cut, so one early error doesn't halve it
+const MIN_SAMPLES: usize = 5;
+
+/// Caps the number of in-flight requests. In adaptive mode the cap is halved when the
+/// recent error rate goes over the threshold and raised by one after each full window of
+/// healthy responses (AIMD), never above the starting limit.
+pub struct ConcurrencyLimit {
+    max: usize,
+    limit: usize,
+    // Error rate (0.0 - 1.0) above which the limit is cut; None keeps the limit fixed
+    threshold: Option<f64>,
+    window: VecDeque<bool>,
+    started: Instant,
+    // (seconds since start, new limit) for every change, starting with the initial limit
+    history: Vec<(f64, usize)>,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize, threshold: Option<f64>) -> Self {
+        let max = max.max(1);
+        ConcurrencyLimit {
+            max,
+            limit: max,
+            threshold,
+            window: VecDeque::with_capacity(WINDOW),
+            started: Instant::now(),
+            history: vec![(0.0, max)],
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Records whether a finished request hit an error that suggests the service is struggling.
+    pub fn record(&mut self, error: bool) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        if self.window.len() == WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(error);
+
+        let errors = self.window.iter().filter(|error| **error).count();
+        let error_rate = errors as f64 / self.window.len() as f64;
+        let new_limit = if self.window.len() >= MIN_SAMPLES && error_rate > threshold {
+            (self.limit / 2).max(1)
+        } else if self.window.len() == WINDOW && error_rate <= threshold {
+            (self.limit + 1).min(self.max)
+        } else {
+            return;
+        };
+        // Each adjustment needs a fresh window of evidence before the next one
+        self.window.clear();
+        if new_limit != self.limit {
+            self.limit = new_limit;
+            self.history
+                .push((self.started.elapsed().as_secs_f64(), new_limit));
+        }
+    }
+
+    /// The limit over time, e.g. "8 -> 4 (at 1.20s) -> 5 (at 3.41s)".
+    pub fn describe_history(&self) -> String {
+        self.history
+            .iter()
+            .enumerate()
+            .map(|(i, (secs, limit))| {
+                if i == 0 {
+                    limit.to_string()
+                } else {
+                    format!("{} (at {:.2}s)", limit, secs)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+}
+
+/// Whether a request only reads: GET and HEAD, the methods a path may use without being
+/// marked destructive. Every other method counts as a write.
+pub fn is_read_method(method: &str) -> bool {
+    method.eq_ignore_ascii_case("GET") || method.eq_ignore_ascii_case("HEAD")
+}
+
+/// Parses a concurrency limit such as `--read-concurrency`, which must be at least 1.
+pub fn parse_limit(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(limit) => Ok(limit),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Separate caps on the reads and the writes in flight, across every environment being
+/// dispatched, so a few slow writes can be kept apart from many cheap reads. A request
+/// holds its slot through its setup, retries and teardown.
+pub struct MethodLimits {
+    read: Option<usize>,
+    write: Option<usize>,
+    reads_in_flight: usize,
+    writes_in_flight: usize,
+}
+
+impl MethodLimits {
+    pub fn new(read: Option<usize>, write: Option<usize>) -> Self {
+        MethodLimits {
+            read,
+            write,
+            reads_in_flight: 0,
+            writes_in_flight: 0,
+        }
+    }
+
+    /// Whether a request with this method may be dispatched now.
+    pub fn has_room(&self, method: &str) -> bool {
+        if is_read_method(method) {
+            self.read.is_none_or(|max| self.reads_in_flight < max)
+        } else {
+            self.write.is_none_or(|max| self.writes_in_flight < max)
+        }
+    }
+
+    pub fn acquire(&mut self, read: bool) {
+        if read {
+            self.reads_in_flight += 1;
+        } else {
+            self.writes_in_flight += 1;
+        }
+    }
+
+    pub fn release(&mut self, read: bool) {
+        if read {
+            self.reads_in_flight -= 1;
+        } else {
+            self.writes_in_flight -= 1;
+        }
+    }
+}
+
+/// The host requests to `base_url` go to, so environments pointing at the same backend share
+/// a `--max-per-host` limit whatever their paths or ports. That's the address the DNS
+/// precheck `resolved` the host to when there is one. Otherwise (--no-dns-precheck, a proxy)
+/// it's the lowercased host name, and names that are aliases of each other count separately.
+pub fn host_key(base_url: &str, resolved: Option<IpAddr>) -> String {
+    if let Some(addr) = resolved {
+        return addr.to_string();
+    }
+    let Some(host) = reqwest::Url::parse(base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+    else {
+        return base_url.to_string();
+    };
+    // "[::1]" and the address "::1" a name resolved to are the same host
+    match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(addr) => addr.to_string(),
+        Err(_) => host,
+    }
+}
+
+/// The `--max-per-host` limit: requests in flight to one host, across every environment
+/// being dispatched, since several environments may point at the same backend.
+pub struct HostLimits {
+    max: Option<usize>,
+    in_flight: HashMap<String, usize>,
+    // Hosts that reached the limit while requests were waiting, reported once each
+    throttled: HashSet<String>,
+}
+
+impl HostLimits {
+    pub fn new(max: Option<usize>) -> Self {
+        HostLimits {
+            max,
+            in_flight: HashMap::new(),
+            throttled: HashSet::new(),
+        }
+    }
+
+    pub fn has_room(&self, host: &str) -> bool {
+        self.max
+            .is_none_or(|max| self.in_flight.get(host).copied().unwrap_or(0) < max)
+    }
+
+    pub fn acquire(&mut self, host: &str) {
+        *self.in_flight.entry(host.to_string()).or_default() += 1;
+    }
+
+    pub fn release(&mut self, host: &str) {
+        if let Some(count) = self.in_flight.get_mut(host) {
+            *count -= 1;
+        }
+    }
+
+    /// Records that `host` is holding requests back. Returns the limit the first time, so
+    /// throttling is reported once per host.
+    pub fn note_throttled(&mut self, host: &str) -> Option<usize> {
+        self.max.filter(|_| self.throttled.insert(host.to_string()))
+    }
+}
+
+/// How many requests an environment actually had in flight, averaged over the time from
+/// its first dispatch to its last completion.
+#[derive(Default)]
+pub struct InFlightStats {
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+    current: usize,
+    peak: usize,
+    // Sum of in-flight count × seconds it lasted
+    weighted_secs: f64,
+}
+
+impl InFlightStats {
+    /// Records that the in-flight count changed to `in_flight`.
+    pub fn set(&mut self, in_flight: usize) {
+        let now = Instant::now();
+        if let Some(last) = self.last_change {
+            self.weighted_secs += self.current as f64 * now.duration_since(last).as_secs_f64();
+        }
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+        self.current = in_flight;
+        self.peak = self.peak.max(in_flight);
+    }
+
+    pub fn average(&self) -> f64 {
+        match (self.first_change, self.last_change) {
+            (Some(first), Some(last)) if last > first => {
+                self.weighted_secs / last.duration_since(first).as_secs_f64()
+            }
+            _ => self.peak as f64,
+        }
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_key_prefers_the_resolved_address() {
+        let addr: IpAddr = "10.0.0.7".parse().unwrap();
+        assert_eq!(
+            host_key("https://api.example.com/v1", Some(addr)),
+            "10.0.0.7"
+        );
+        assert_eq!(
+            host_key("https://alias.example.com:8443", Some(addr)),
+            host_key("https://api.example.com", Some(addr))
+        );
+    }
+
+    #[test]
+    fn host_key_falls_back_to_the_host_name() {
+        assert_eq!(
+            host_key("https://API.Example.com:8443/v1", None),
+            "api.example.com"
+        );
+        assert_eq!(
+            host_key("http://[::1]:8080", None),
+            host_key("http://x", Some("::1".parse().unwrap()))
+        );
+    }
+}
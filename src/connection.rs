@@ -0,0 +1,61 @@
+//! Best-effort detection of whether a request reused a pooled connection.
+//!
+//! reqwest doesn't say which connection served a response, but it only calls its connector
+//! to open a new one. A connector layer flags those calls for the task that made them, so a
+//! request that completes without the flag being set went over a pooled connection. The
+//! rare request that starts connecting and is then handed a connection freed up by another
+//! one is still counted as fresh.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::task::{Context, Poll};
+
+tokio::task_local! {
+    static OPENED_CONNECTION: Cell<bool>;
+}
+
+/// Runs a request future and reports whether it had to open a new connection.
+pub async fn track<F: Future>(request: F) -> (F::Output, bool) {
+    OPENED_CONNECTION
+        .scope(Cell::new(false), async {
+            let output = request.await;
+            (output, OPENED_CONNECTION.with(Cell::get))
+        })
+        .await
+}
+
+/// Connector layer marking the calling task as having opened a connection.
+#[derive(Clone)]
+pub struct TrackConnectsLayer;
+
+impl<S> tower_layer::Layer<S> for TrackConnectsLayer {
+    type Service = TrackConnects<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TrackConnects { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct TrackConnects<S> {
+    inner: S,
+}
+
+impl<S, R> tower_service::Service<R> for TrackConnects<S>
+where
+    S: tower_service::Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        // Requests outside `track`, e.g. warm-up and setup requests, aren't recorded
+        let _ = OPENED_CONNECTION.try_with(|opened| opened.set(true));
+        self.inner.call(request)
+    }
+}
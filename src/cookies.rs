@@ -0,0 +1,143 @@
+//! `expected_cookies`: parsing of `Set-Cookie` response headers and checks on their attributes.
+
+use serde::{Deserialize, Serialize};
+
+/// One `expected_cookies` entry. Attributes left out aren't checked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExpectedCookie {
+    pub name: String,
+    // Exact value; mismatches don't print the actual value, which is often a session token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secure: Option<bool>,
+    // "Strict", "Lax" or "None", compared case-insensitively
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub same_site: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+}
+
+/// A cookie set by the response, as recorded in the result.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SetCookie {
+    pub name: String,
+    // Always masked before the result is recorded
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub expires: Option<String>,
+    pub max_age: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<String>,
+}
+
+/// Parses one `Set-Cookie` header value. Unknown attributes are ignored; a header without
+/// `name=value` yields nothing.
+pub fn parse_set_cookie(header: &str) -> Option<SetCookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut cookie = SetCookie {
+        name: name.to_string(),
+        value: value.trim().trim_matches('"').to_string(),
+        domain: None,
+        path: None,
+        expires: None,
+        max_age: None,
+        secure: false,
+        http_only: false,
+        same_site: None,
+    };
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+            None => (attribute.trim(), None),
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "domain" => cookie.domain = value,
+            "path" => cookie.path = value,
+            "expires" => cookie.expires = value,
+            "max-age" => cookie.max_age = value.and_then(|secs| secs.parse().ok()),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" => cookie.same_site = value,
+            _ => {}
+        }
+    }
+    Some(cookie)
+}
+
+/// Returns one failure message per expected cookie that is missing or differs. When a cookie
+/// is set more than once, the last one wins, as in a browser.
+pub fn check_cookies(set: &[SetCookie], expected: &[ExpectedCookie]) -> Vec<String> {
+    let mut messages = Vec::new();
+    for expected in expected {
+        let Some(actual) = set.iter().rev().find(|cookie| cookie.name == expected.name) else {
+            messages.push(format!("Cookie '{}' was not set", expected.name));
+            continue;
+        };
+        let mut problems = Vec::new();
+        if expected
+            .value
+            .as_ref()
+            .is_some_and(|value| *value != actual.value)
+        {
+            problems.push("has a different value than expected".to_string());
+        }
+        for (flag, wanted, present) in [
+            ("HttpOnly", expected.http_only, actual.http_only),
+            ("Secure", expected.secure, actual.secure),
+        ] {
+            match wanted {
+                Some(true) if !present => problems.push(format!("is missing {}", flag)),
+                Some(false) if present => problems.push(format!("should not be {}", flag)),
+                _ => {}
+            }
+        }
+        let attributes = [
+            ("SameSite", &expected.same_site, &actual.same_site, true),
+            ("Path", &expected.path, &actual.path, false),
+            ("Domain", &expected.domain, &actual.domain, true),
+        ];
+        for (attribute, wanted, present, ignore_case) in attributes {
+            let Some(wanted) = wanted else { continue };
+            // A leading dot on Domain is ignored by browsers
+            let normalize = |value: &str| {
+                let value = if attribute == "Domain" {
+                    value.trim_start_matches('.')
+                } else {
+                    value
+                };
+                if ignore_case {
+                    value.to_ascii_lowercase()
+                } else {
+                    value.to_string()
+                }
+            };
+            match present {
+                Some(value) if normalize(value) == normalize(wanted) => {}
+                Some(value) => {
+                    problems.push(format!("has {}={}, expected {}", attribute, value, wanted))
+                }
+                None => problems.push(format!("has no {}, expected {}", attribute, wanted)),
+            }
+        }
+        if !problems.is_empty() {
+            messages.push(format!(
+                "Cookie '{}' {}",
+                expected.name,
+                problems.join(", ")
+            ));
+        }
+    }
+    messages
+}
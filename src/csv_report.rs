@@ -0,0 +1,203 @@
+//! CSV output for `--output`.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::schema::SCHEMA_VERSION;
+use crate::schema::STATE_SUMMARY_FIELDS;
+use crate::{sorted_buckets, StateRollup, UrlTestResult};
+
+// Result fields that hold maps or lists, which are left out or flattened into dedicated columns
+const NON_SCALAR_FIELDS: &[&str] = &["failures", "server_timing", "annotations", "cookies"];
+
+/// Writes one row per result. The columns are the scalar fields of `UrlTestResult` in
+/// declaration order, then `schema_version`, then one `server_timing_<name>` column per
+/// configured metric and one `annotation_<name>` column per configured annotation.
+pub fn write_csv_report(
+    path: &str,
+    results: &[&UrlTestResult],
+    server_timing_metrics: &[String],
+    annotation_names: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fs::File::create(path)?;
+    let mut wtr = csv::Writer::from_writer(file);
+
+    let mut header: Vec<String> = result_fields(results.first().copied())?;
+    header.retain(|field| !NON_SCALAR_FIELDS.contains(&field.as_str()));
+    let scalar_fields = header.clone();
+    header.push("schema_version".to_string());
+    header.extend(
+        server_timing_metrics
+            .iter()
+            .map(|metric| format!("server_timing_{}", metric)),
+    );
+    header.extend(
+        annotation_names
+            .iter()
+            .map(|name| format!("annotation_{}", name)),
+    );
+    wtr.write_record(&header)?;
+
+    for res in results {
+        let value = serde_json::to_value(res)?;
+        let mut record: Vec<String> = scalar_fields
+            .iter()
+            .map(|field| cell(value.get(field)))
+            .collect();
+        record.push(SCHEMA_VERSION.to_string());
+        for metric in server_timing_metrics {
+            // Missing metrics leave the cell empty
+            record.push(
+                res.server_timing
+                    .get(metric)
+                    .map_or(String::new(), |dur| dur.to_string()),
+            );
+        }
+        for name in annotation_names {
+            record.push(res.annotations.get(name).cloned().unwrap_or_default());
+        }
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// One file written by `--output-per-env`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvReport {
+    pub path: String,
+    pub rows: usize,
+}
+
+/// Writes one CSV per environment into `dir` (created if missing), named after the
+/// environment, with the same columns as the combined report. Fails before writing anything
+/// when two environment names map to the same file name.
+pub fn write_per_env_csv(
+    dir: &str,
+    results: &[&UrlTestResult],
+    server_timing_metrics: &[String],
+    annotation_names: &[String],
+) -> Result<BTreeMap<String, EnvReport>, Box<dyn std::error::Error>> {
+    let mut by_env: BTreeMap<&str, Vec<&UrlTestResult>> = BTreeMap::new();
+    for res in results {
+        by_env.entry(&res.environment_name).or_default().push(res);
+    }
+    // File name -> environment, to catch names that only differ in replaced characters
+    let mut file_names: BTreeMap<String, &str> = BTreeMap::new();
+    for env_name in by_env.keys() {
+        let file_name = format!("{}.csv", file_stem(env_name));
+        if let Some(other) = file_names.insert(file_name.clone(), env_name) {
+            return Err(format!(
+                "Environments '{}' and '{}' would both be written to {}",
+                other, env_name, file_name
+            )
+            .into());
+        }
+    }
+
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create directory '{}': {}", dir, e))?;
+    let mut written = BTreeMap::new();
+    for (file_name, env_name) in file_names {
+        let env_results = &by_env[env_name];
+        let path = std::path::Path::new(dir)
+            .join(&file_name)
+            .to_string_lossy()
+            .into_owned();
+        write_csv_report(&path, env_results, server_timing_metrics, annotation_names)
+            .map_err(|e| format!("Failed to write '{}': {}", path, e))?;
+        written.insert(
+            env_name.to_string(),
+            EnvReport {
+                path,
+                rows: env_results.len(),
+            },
+        );
+    }
+    Ok(written)
+}
+
+// Environment names are free text, so anything outside [A-Za-z0-9_-] becomes '_' (dots too,
+// so a name can't climb out of the directory or hide the file)
+fn file_stem(env_name: &str) -> String {
+    let stem: String = env_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if stem.is_empty() {
+        "_".to_string()
+    } else {
+        stem
+    }
+}
+
+/// Writes the `--status-summary-csv` file: one `env,status,count` row per bucket, most
+/// frequent first within each environment.
+pub fn write_status_summary_csv(
+    path: &str,
+    distribution: &BTreeMap<String, BTreeMap<String, usize>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["env", "status", "count"])?;
+    for (env_name, counts) in distribution {
+        for (bucket, count) in sorted_buckets(counts) {
+            wtr.write_record([env_name.as_str(), bucket, &count.to_string()])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes one row per environment and State, with the columns of `STATE_SUMMARY_FIELDS`.
+pub fn write_state_summary_csv(
+    path: &str,
+    rollups: &BTreeMap<(String, String), StateRollup>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(STATE_SUMMARY_FIELDS.iter().map(|field| field.name))?;
+    for ((env_name, state), rollup) in rollups {
+        wtr.write_record([
+            env_name.clone(),
+            state.clone(),
+            rollup.total.to_string(),
+            rollup.passed.to_string(),
+            rollup.failed.to_string(),
+            rollup.recovered.to_string(),
+            rollup.skipped.to_string(),
+            rollup
+                .worst_status
+                .map_or(String::new(), |status| status.to_string()),
+            rollup
+                .max_duration_secs
+                .map_or(String::new(), |secs| secs.to_string()),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Field names in declaration order; an empty report still gets the full header row
+fn result_fields(sample: Option<&UrlTestResult>) -> Result<Vec<String>, serde_json::Error> {
+    let value = match sample {
+        Some(res) => serde_json::to_value(res)?,
+        None => serde_json::to_value(UrlTestResult::default())?,
+    };
+    Ok(value
+        .as_object()
+        .map(|fields| fields.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+fn cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+    }
+}
@@ -0,0 +1,146 @@
+//! `depends_on`: paths that only make sense after another path passed, such as fetching a
+//! record another path creates. A dependent path is held back until its dependency's
+//! result is in, and skipped when the dependency failed or didn't run. Paths without a
+//! dependency are dispatched as usual.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{PathConfig, TestSpec};
+
+/// Checks that path names are unique, that every `depends_on` names a path, and that the
+/// dependencies don't form a cycle.
+pub fn validate(paths: &[PathConfig]) -> Result<(), String> {
+    let mut names = HashSet::new();
+    for path in paths {
+        let Some(name) = &path.name else {
+            continue;
+        };
+        if name.trim().is_empty() {
+            return Err(format!("Path '{}': name must not be empty", path.path));
+        }
+        if !names.insert(name.as_str()) {
+            return Err(format!("Path name '{}' is used more than once", name));
+        }
+    }
+
+    // Only named paths can be depended on, so only they can be part of a cycle
+    let mut depends_on: BTreeMap<&str, &str> = BTreeMap::new();
+    for path in paths {
+        let Some(dependency) = path.depends_on.as_deref() else {
+            continue;
+        };
+        if !names.contains(dependency) {
+            return Err(format!(
+                "Path '{}': depends_on '{}' isn't the name of a path",
+                path.path, dependency
+            ));
+        }
+        if let Some(name) = path.name.as_deref() {
+            depends_on.insert(name, dependency);
+        }
+    }
+    for &start in depends_on.keys() {
+        let mut chain = vec![start];
+        let mut current = start;
+        while let Some(&next) = depends_on.get(current) {
+            chain.push(next);
+            if next == start {
+                return Err(format!(
+                    "Dependency cycle between paths: {}",
+                    chain.join(" -> ")
+                ));
+            }
+            // A cycle further down the chain is reported when starting from one of its paths
+            if chain[..chain.len() - 1].contains(&next) {
+                break;
+            }
+            current = next;
+        }
+    }
+    Ok(())
+}
+
+/// One environment's tests that are waiting for their dependency's result.
+#[derive(Default)]
+pub struct Waiting {
+    specs: Vec<TestSpec>,
+    // The dependents skipped so far, with why they won't run
+    skipped: Vec<(TestSpec, String)>,
+}
+
+impl Waiting {
+    /// Moves the tests that have a dependency out of `pending`. Those whose dependency isn't
+    /// among the environment's tests (skipped, or not selected for this run) are skipped
+    /// right away.
+    pub fn hold_back(pending: &mut Vec<TestSpec>) -> Self {
+        let scheduled: HashSet<String> = pending
+            .iter()
+            .filter_map(|spec| spec.path.name.clone())
+            .collect();
+        let (specs, ready): (Vec<TestSpec>, Vec<TestSpec>) = pending
+            .drain(..)
+            .partition(|spec| spec.path.depends_on.is_some());
+        *pending = ready;
+        let mut waiting = Waiting {
+            specs,
+            skipped: Vec::new(),
+        };
+        let mut missing: Vec<String> = waiting
+            .specs
+            .iter()
+            .filter_map(|spec| spec.path.depends_on.clone())
+            .filter(|dependency| !scheduled.contains(dependency))
+            .collect();
+        missing.sort();
+        missing.dedup();
+        for dependency in missing {
+            waiting.skip_dependents(&dependency, "dependency not run");
+        }
+        waiting
+    }
+
+    /// Hands back the tests that can be dispatched now that `dependency` has a result, or
+    /// skips them, and their own dependents, when it didn't pass.
+    pub fn resolve(&mut self, dependency: &str, passed: bool) -> Vec<TestSpec> {
+        if !passed {
+            self.skip_dependents(dependency, "dependency failed");
+            return Vec::new();
+        }
+        let (ready, specs) = std::mem::take(&mut self.specs)
+            .into_iter()
+            .partition(|spec| spec.path.depends_on.as_deref() == Some(dependency));
+        self.specs = specs;
+        ready
+    }
+
+    fn skip_dependents(&mut self, dependency: &str, reason: &str) {
+        let (skipped, specs): (Vec<TestSpec>, Vec<TestSpec>) = std::mem::take(&mut self.specs)
+            .into_iter()
+            .partition(|spec| spec.path.depends_on.as_deref() == Some(dependency));
+        self.specs = specs;
+        for spec in skipped {
+            let name = spec.path.name.clone();
+            self.skipped
+                .push((spec, format!("{} ({})", reason, dependency)));
+            if let Some(name) = name {
+                self.skip_dependents(&name, "dependency skipped");
+            }
+        }
+    }
+
+    /// Takes the tests still waiting, e.g. to skip them for another reason.
+    pub fn take_remaining(&mut self) -> Vec<TestSpec> {
+        std::mem::take(&mut self.specs)
+    }
+
+    /// The dependents that didn't run, with the reason. Anything still waiting never got
+    /// its dependency's result.
+    pub fn into_skipped(mut self) -> Vec<(TestSpec, String)> {
+        for spec in std::mem::take(&mut self.specs) {
+            let dependency = spec.path.depends_on.clone().unwrap_or_default();
+            self.skipped
+                .push((spec, format!("dependency not run ({})", dependency)));
+        }
+        self.skipped
+    }
+}
@@ -0,0 +1,98 @@
+//! `--group-errors`: failures grouped by their error message, with the parts that differ
+//! from one request to the next (URLs, IDs, timings) masked.
+
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+
+use crate::UrlTestResult;
+
+// Applied in order; URLs first, so IDs inside them don't leave a partial URL behind
+fn volatile_patterns() -> &'static [(Regex, &'static str)] {
+    static PATTERNS: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            (r"[a-zA-Z][a-zA-Z0-9+.-]*://[^\s)\]'\x22,]+", "<url>"),
+            (
+                r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+                "<id>",
+            ),
+            (r"\b[0-9a-fA-F]{12,}\b", "<id>"),
+            (r"\b\d+\.\d+\b", "<n>"),
+            // Status codes and other short numbers usually are the cause, so they stay
+            (r"\b\d{4,}\b", "<n>"),
+        ]
+        .into_iter()
+        .filter_map(|(pattern, mask)| Regex::new(pattern).ok().map(|re| (re, mask)))
+        .collect()
+    })
+}
+
+/// The message with its volatile parts replaced by placeholders.
+pub fn normalize(message: &str) -> String {
+    volatile_patterns()
+        .iter()
+        .fold(message.to_string(), |message, (re, mask)| {
+            re.replace_all(&message, *mask).into_owned()
+        })
+}
+
+/// Failures sharing one normalized message.
+struct ErrorGroup<'a> {
+    failures: usize,
+    urls: BTreeSet<&'a str>,
+    environments: BTreeSet<&'a str>,
+}
+
+/// Prints the `top` most common normalized failure messages with how often they occurred,
+/// how many URLs they hit and in which environments. A result failing several checks counts
+/// towards each of its messages.
+pub fn print_error_groups(results: &[UrlTestResult], top: usize) {
+    let mut groups: BTreeMap<String, ErrorGroup> = BTreeMap::new();
+    for res in results.iter().filter(|res| !res.passed) {
+        let messages = if res.failures.is_empty() {
+            res.error_message.iter().cloned().collect()
+        } else {
+            res.failures.clone()
+        };
+        for message in messages {
+            let group = groups.entry(normalize(&message)).or_insert(ErrorGroup {
+                failures: 0,
+                urls: BTreeSet::new(),
+                environments: BTreeSet::new(),
+            });
+            group.failures += 1;
+            group.urls.insert(&res.url);
+            group.environments.insert(&res.environment_name);
+        }
+    }
+    if groups.is_empty() {
+        return;
+    }
+    let mut groups: Vec<(String, ErrorGroup)> = groups.into_iter().collect();
+    groups.sort_by(|a, b| b.1.failures.cmp(&a.1.failures).then_with(|| a.0.cmp(&b.0)));
+    let total: usize = groups.iter().map(|(_, group)| group.failures).sum();
+    println!(
+        "\n--- Error Groups ({} distinct causes, {} failures) ---",
+        groups.len(),
+        total
+    );
+    for (message, group) in groups.iter().take(top) {
+        let environments: Vec<&str> = group.environments.iter().copied().collect();
+        println!(
+            "{: >5}× | {: >4} URLs | {} | {}",
+            group.failures,
+            group.urls.len(),
+            environments.join(", "),
+            message
+        );
+    }
+    if groups.len() > top {
+        let rest: usize = groups[top..].iter().map(|(_, group)| group.failures).sum();
+        println!(
+            "… and {} more causes ({} failures)",
+            groups.len() - top,
+            rest
+        );
+    }
+}
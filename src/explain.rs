@@ -0,0 +1,500 @@
+//! `--dry-run` output: the requests a run would send and, with `--explain`, what each of
+//! them checks; `--dry-run-requests` prints them as curl commands instead. Built from the
+//! same `TestSpec`s the run dispatches, so it can't drift from what actually happens, and
+//! printed in a fixed order so two configs can be diffed.
+
+use crate::redact::Redactor;
+use crate::schemes;
+use crate::{
+    destructive_skip_reason, http_version_label, range_header, Config, ConnectionMode,
+    DurationMeasure, ExplainFormat, LocationMatcher, RegressionAction, Severity, SubRequest,
+    TestSpec,
+};
+
+// Mirrors the clients' settings in HttpClients::new
+const CURL_TIMEOUT_SECS: u64 = 10;
+const CURL_MAX_REDIRECTS: u32 = 10;
+
+/// One request's effective settings, as label and lines.
+struct Explanation {
+    title: String,
+    fields: Vec<(&'static str, Vec<String>)>,
+}
+
+/// Prints one line per request, or with `format` a block per request describing its
+/// effective method, headers (redacted) and checks. `specs` must already be in print order.
+pub fn print_dry_run(
+    specs: &[TestSpec],
+    config: &Config,
+    redactor: &Redactor,
+    format: Option<ExplainFormat>,
+    allow_destructive: bool,
+) {
+    let Some(format) = format else {
+        for spec in specs {
+            println!("{}", title(spec, config, redactor, allow_destructive));
+        }
+        return;
+    };
+    for spec in specs {
+        let explanation = explain(spec, config, redactor, allow_destructive);
+        match format {
+            ExplainFormat::Text => print_text(&explanation),
+            ExplainFormat::Markdown => print_markdown(&explanation),
+        }
+    }
+}
+
+/// Prints the curl commands equivalent to each request, with its setup and teardown requests
+/// around it. Sensitive header values and redacted parameters are masked unless `redactor`
+/// is None.
+pub fn print_curl_commands(
+    specs: &[TestSpec],
+    config: &Config,
+    redactor: Option<&Redactor>,
+    allow_destructive: bool,
+) {
+    for spec in specs {
+        println!(
+            "# {}{}",
+            spec.environment_name,
+            destructive_flag(spec, config, allow_destructive)
+        );
+        let http2 = config
+            .environments
+            .get(&spec.environment_name)
+            .is_some_and(|env| env.http2_prior_knowledge);
+        let path = &spec.path;
+        let url = |sub: &SubRequest| {
+            if sub.url.starts_with("http://") || sub.url.starts_with("https://") {
+                sub.url.clone()
+            } else {
+                format!("{}{}", spec.base_url, sub.url)
+            }
+        };
+        if let Some(setup) = &path.setup {
+            let command = curl_command(
+                spec,
+                &setup.method,
+                &url(setup),
+                setup.body.as_deref(),
+                None,
+                http2,
+                redactor,
+            );
+            println!("# setup\n{}", command);
+        }
+        println!(
+            "{}",
+            curl_command(
+                spec,
+                &path.method,
+                &format!("{}{}", spec.base_url, path.path),
+                path.body.as_deref(),
+                path.range.as_deref(),
+                http2,
+                redactor
+            )
+        );
+        if let Some(teardown) = &path.teardown {
+            let command = curl_command(
+                spec,
+                &teardown.method,
+                &url(teardown),
+                teardown.body.as_deref(),
+                None,
+                http2,
+                redactor,
+            );
+            println!("# teardown\n{}", command);
+        }
+        println!();
+    }
+}
+
+fn curl_command(
+    spec: &TestSpec,
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    range: Option<&str>,
+    http2: bool,
+    redactor: Option<&Redactor>,
+) -> String {
+    let redact = |text: &str| match redactor {
+        Some(redactor) => redactor.redact(text),
+        None => text.to_string(),
+    };
+    let mut args = vec!["curl".to_string()];
+    match method.to_uppercase().as_str() {
+        "GET" => {}
+        // -X HEAD would wait for a body that never comes
+        "HEAD" => args.push("--head".to_string()),
+        other => args.push(format!("-X {}", other)),
+    }
+    args.push(format!("--max-time {}", CURL_TIMEOUT_SECS));
+    // Redirects are followed, except on paths asserting on one
+    if spec.path.expected_location.is_none() {
+        args.push(format!("-L --max-redirs {}", CURL_MAX_REDIRECTS));
+    }
+    if http2 {
+        args.push("--http2-prior-knowledge".to_string());
+    }
+
+    let mut headers: Vec<(String, String)> = spec
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match redactor {
+                Some(redactor) if redactor.is_redacted_header(name.as_str()) => "***".to_string(),
+                _ => value.to_str().unwrap_or_default().to_string(),
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+    if let Some(range) = range {
+        headers.push(("range".to_string(), range_header(range)));
+    }
+    if spec.connection_mode == ConnectionMode::Fresh && !http2 {
+        headers.push(("connection".to_string(), "close".to_string()));
+    }
+    // build_request sends bodies that look like JSON with a JSON content type
+    if body.is_some_and(|body| serde_json::from_str::<serde_json::Value>(body).is_ok())
+        && !spec.headers.contains_key(reqwest::header::CONTENT_TYPE)
+    {
+        headers.push(("content-type".to_string(), "application/json".to_string()));
+    }
+    headers.sort();
+    for (name, value) in headers {
+        args.push(format!(
+            "-H {}",
+            shell_quote(&format!("{}: {}", name, value))
+        ));
+    }
+    if let Some(body) = body {
+        args.push(format!("--data-raw {}", shell_quote(&redact(body))));
+    }
+    args.push(shell_quote(&redact(url)));
+    args.join(" \\\n  ")
+}
+
+// Single-quoted for a POSIX shell; a quote inside becomes '\''
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+// Flags destructive paths, and whether a run would skip them
+fn destructive_flag(spec: &TestSpec, config: &Config, allow_destructive: bool) -> String {
+    if !spec.path.destructive {
+        return String::new();
+    }
+    let skip_reason = config
+        .environments
+        .get(&spec.environment_name)
+        .and_then(|env| destructive_skip_reason(&spec.path, env, allow_destructive));
+    match skip_reason {
+        Some(reason) => format!(" [DESTRUCTIVE, SKIPPED: {}]", reason),
+        None => " [DESTRUCTIVE]".to_string(),
+    }
+}
+
+fn title(spec: &TestSpec, config: &Config, redactor: &Redactor, allow_destructive: bool) -> String {
+    // Base URLs were checked when the config was loaded
+    let scheme = schemes::classify(&spec.base_url).map_or("?", schemes::Scheme::as_str);
+    format!(
+        "{}: {} {} [{}]{}",
+        spec.environment_name,
+        spec.path.method.to_uppercase(),
+        redactor.redact(&format!("{}{}", spec.base_url, spec.path.path)),
+        scheme,
+        destructive_flag(spec, config, allow_destructive)
+    )
+}
+
+fn explain(
+    spec: &TestSpec,
+    config: &Config,
+    redactor: &Redactor,
+    allow_destructive: bool,
+) -> Explanation {
+    let path = &spec.path;
+    let mut fields = vec![(
+        "severity",
+        vec![format!(
+            "{}, weight {}",
+            match path.severity {
+                Severity::Critical => "critical",
+                Severity::Normal => "normal",
+            },
+            path.weight
+        )],
+    )];
+
+    let mut headers: Vec<String> = spec
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if redactor.is_redacted_header(name.as_str()) {
+                "***".to_string()
+            } else {
+                value.to_str().unwrap_or("<binary>").to_string()
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect();
+    headers.sort();
+    if !headers.is_empty() {
+        fields.push(("headers", headers));
+    }
+    if let Some(body) = &path.body {
+        fields.push(("body", vec![format!("{} bytes", body.len())]));
+    }
+    if let Some(dependency) = &path.depends_on {
+        fields.push((
+            "depends_on",
+            vec![format!(
+                "{}, sent once it passed, skipped if it fails",
+                dependency
+            )],
+        ));
+    }
+    if let Some(setup) = &path.setup {
+        fields.push(("setup", vec![sub_request(setup, spec, redactor)]));
+    }
+    if let Some(teardown) = &path.teardown {
+        fields.push(("teardown", vec![sub_request(teardown, spec, redactor)]));
+    }
+    fields.push(("checks", checks(spec)));
+
+    let mut measured = vec![match spec.duration_measure {
+        DurationMeasure::Ttfb => "time to first byte".to_string(),
+        DurationMeasure::Total => "until the body is read".to_string(),
+    }];
+    if let Some(sla_ms) = config.sla_ms {
+        measured.push(format!("SLA {}ms, reported as within_sla", sla_ms));
+    }
+    fields.push(("duration", measured));
+    if spec.retries > 0 {
+        fields.push((
+            "retries",
+            vec![format!(
+                "{}, {}s apart{}",
+                spec.retries,
+                spec.retry_delay,
+                if spec.retry_on_assertion {
+                    ", also on assertion failures"
+                } else {
+                    ""
+                }
+            )],
+        ));
+    }
+
+    Explanation {
+        title: title(spec, config, redactor, allow_destructive),
+        fields,
+    }
+}
+
+fn sub_request(sub: &SubRequest, spec: &TestSpec, redactor: &Redactor) -> String {
+    let url = if sub.url.starts_with("http://") || sub.url.starts_with("https://") {
+        sub.url.clone()
+    } else {
+        format!("{}{}", spec.base_url, sub.url)
+    };
+    format!("{} {}", sub.method.to_uppercase(), redactor.redact(&url))
+}
+
+// Every check the response goes through, status first
+fn checks(spec: &TestSpec) -> Vec<String> {
+    let path = &spec.path;
+    let mut checks = Vec::new();
+    // The maintenance page replaces the path's own status and body checks
+    if let Some(maintenance) = &spec.maintenance {
+        checks.push(match &maintenance.body_contains {
+            Some(marker) => format!(
+                "maintenance page: status {} with '{}' in the body",
+                maintenance.status, marker
+            ),
+            None => format!("maintenance page: status {}", maintenance.status),
+        });
+        return checks;
+    }
+    checks.push(match (&path.expected_location, path.expected_status) {
+        (Some(LocationMatcher::Exact(location)), _) => {
+            format!("redirect (3xx) to '{}', not followed", location)
+        }
+        (Some(LocationMatcher::Regex(regex)), _) => {
+            format!(
+                "redirect (3xx) to a Location matching '{}', not followed",
+                regex
+            )
+        }
+        (None, Some(status)) => format!("status {}", status),
+        (None, None) if path.assert_expr.is_some() => "status decided by assert_expr".to_string(),
+        (None, None) => "status 2xx (304 also accepted)".to_string(),
+    });
+
+    if let Some(code) = &spec.app_error_code {
+        checks.push(format!("JSON '{}' is not \"{}\"", spec.app_error_key, code));
+    }
+    if !spec.soft_error_markers.is_empty() {
+        let markers: Vec<String> = spec
+            .soft_error_markers
+            .iter()
+            .map(|marker| format!("\"{}\"", marker))
+            .collect();
+        checks.push(format!("body contains none of {}", markers.join(", ")));
+    }
+    if !spec.fail_on_redirect_to_hosts.is_empty() {
+        checks.push(format!(
+            "not redirected to {}",
+            spec.fail_on_redirect_to_hosts.join(", ")
+        ));
+    }
+    if let Some(min) = path.min_response_bytes {
+        checks.push(format!("body is at least {} bytes", min));
+    }
+    for bound in &path.expect_json_array_min {
+        checks.push(format!(
+            "JSON array '{}' has at least {} items",
+            bound.pointer, bound.min
+        ));
+    }
+    for bound in &path.expect_json_array_max {
+        checks.push(format!(
+            "JSON array '{}' has at most {} items",
+            bound.pointer, bound.max
+        ));
+    }
+    if path.require_valid_json {
+        checks.push("body parses as JSON".to_string());
+    }
+    for (field, expected) in &path.json_equals {
+        checks.push(format!("JSON '{}' equals {}", field, expected));
+    }
+    if !path.require_json_keys.is_empty() {
+        checks.push(format!(
+            "JSON keys present and not null: {}",
+            path.require_json_keys.join(", ")
+        ));
+    }
+    if let Some(streaming) = &path.streaming {
+        checks.push(match &streaming.until {
+            Some(until) => format!(
+                "a streamed line matches '{}' within {}s",
+                until, streaming.max_secs
+            ),
+            None => format!("stream stays healthy for {}s", streaming.max_secs),
+        });
+    }
+    if let Some(range) = &path.range {
+        checks.push(format!(
+            "range '{}' answered with 206 and a Content-Range{}",
+            range,
+            if path.allow_full_response {
+                " (a full 200 response also accepted)"
+            } else {
+                ""
+            }
+        ));
+    }
+    if !spec.forbidden_patterns.is_empty() {
+        let names: Vec<&str> = spec
+            .forbidden_patterns
+            .iter()
+            .map(|pattern| pattern.name.as_str())
+            .collect();
+        checks.push(format!(
+            "body matches no forbidden pattern ({}){}",
+            names.join(", "),
+            if spec.forbidden_warn_only {
+                ", warning only"
+            } else {
+                ""
+            }
+        ));
+    }
+    if let Some(source) = &path.assert_expr {
+        checks.push(format!("assert_expr: {}", source));
+    }
+    let mut server_timing: Vec<(&String, &f64)> = path.server_timing_max.iter().collect();
+    server_timing.sort_by(|a, b| a.0.cmp(b.0));
+    for (metric, max) in server_timing {
+        checks.push(format!("Server-Timing '{}' at most {}ms", metric, max));
+    }
+    if let Some(version) = spec.required_http_version {
+        checks.push(format!("negotiates {}", http_version_label(version)));
+    }
+    for cookie in &path.expected_cookies {
+        let mut attributes = Vec::new();
+        if cookie.value.is_some() {
+            attributes.push("expected value".to_string());
+        }
+        for (flag, wanted) in [("HttpOnly", cookie.http_only), ("Secure", cookie.secure)] {
+            match wanted {
+                Some(true) => attributes.push(flag.to_string()),
+                Some(false) => attributes.push(format!("not {}", flag)),
+                None => {}
+            }
+        }
+        for (attribute, wanted) in [
+            ("SameSite", &cookie.same_site),
+            ("Path", &cookie.path),
+            ("Domain", &cookie.domain),
+        ] {
+            if let Some(wanted) = wanted {
+                attributes.push(format!("{}={}", attribute, wanted));
+            }
+        }
+        checks.push(if attributes.is_empty() {
+            format!("sets cookie '{}'", cookie.name)
+        } else {
+            format!("sets cookie '{}' ({})", cookie.name, attributes.join(", "))
+        });
+    }
+    if let Some(baseline) = path.baseline_duration_secs {
+        checks.push(format!(
+            "duration at most {}s + {}%{}",
+            baseline,
+            path.regression_pct,
+            match path.on_regression {
+                RegressionAction::Fail => "",
+                RegressionAction::Warn => ", warning only",
+            }
+        ));
+    }
+    checks
+}
+
+fn print_text(explanation: &Explanation) {
+    println!("{}", explanation.title);
+    for (label, lines) in &explanation.fields {
+        match lines.as_slice() {
+            [line] => println!("    {}: {}", label, line),
+            lines => {
+                println!("    {}:", label);
+                for line in lines {
+                    println!("        {}", line);
+                }
+            }
+        }
+    }
+    println!();
+}
+
+fn print_markdown(explanation: &Explanation) {
+    println!("### `{}`\n", explanation.title);
+    for (label, lines) in &explanation.fields {
+        match lines.as_slice() {
+            [line] => println!("- **{}**: {}", label, line),
+            lines => {
+                println!("- **{}**:", label);
+                for line in lines {
+                    println!("  - {}", line);
+                }
+            }
+        }
+    }
+    println!();
+}
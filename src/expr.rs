@@ -0,0 +1,638 @@
+//! `assert_expr`: a small expression language for pass/fail rules the declarative checks
+//! can't express, e.g. `len(json("/items")) % 2 == 0 || json("/fallback") == true`.
+//!
+//! Values are JSON values. There are no loops, assignments or user-defined functions, so a
+//! script does a bounded amount of work; a step budget caps it regardless. Scripts are parsed
+//! once, while the config loads.
+
+use serde_json::Value;
+use std::cell::OnceCell;
+
+use crate::{json_type_name, json_values_equal};
+
+// Most nodes a script may have, and most evaluation steps it may take
+const MAX_NODES: usize = 500;
+const MAX_STEPS: usize = 10_000;
+// Deepest nesting of parentheses, unary operators and calls, so parsing can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// A parsed expression, evaluated once per response.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// What a script can see of a response.
+pub struct Response<'a> {
+    pub status: u16,
+    pub duration_ms: f64,
+    pub body: &'a str,
+    pub headers: &'a reqwest::header::HeaderMap,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    Variable(Variable),
+    Call(Function, Vec<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Variable {
+    Status,
+    DurationMs,
+    Body,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    // header(name): the response header's value, or null
+    Header,
+    // json(pointer): the value at a JSON pointer in the body, or null
+    Json,
+    // len(value): characters of a string, items of an array or object
+    Len,
+    // contains(haystack, needle): substring of a string, or item of an array
+    Contains,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+        }
+    }
+}
+
+// Binary operators by precedence, loosest first
+const PRECEDENCE: &[&[BinaryOp]] = &[
+    &[BinaryOp::Or],
+    &[BinaryOp::And],
+    &[BinaryOp::Equal, BinaryOp::NotEqual],
+    &[
+        BinaryOp::Less,
+        BinaryOp::LessEqual,
+        BinaryOp::Greater,
+        BinaryOp::GreaterEqual,
+    ],
+    &[BinaryOp::Add, BinaryOp::Subtract],
+    &[BinaryOp::Multiply, BinaryOp::Divide, BinaryOp::Remainder],
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Op(BinaryOp),
+    Not,
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+impl Expr {
+    /// Parses a script, reporting the first syntax error or unknown name.
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            nodes: 0,
+            depth: 0,
+        };
+        let root = parser.expression(0)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!(
+                "unexpected {} after the expression",
+                describe(token)
+            ));
+        }
+        Ok(Expr {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    /// Evaluates the script against a response. It must come out as a boolean.
+    pub fn evaluate(&self, response: &Response) -> Result<bool, String> {
+        let mut evaluator = Evaluator {
+            response,
+            parsed_body: OnceCell::new(),
+            steps: 0,
+        };
+        match evaluator.eval(&self.root)? {
+            Value::Bool(outcome) => Ok(outcome),
+            other => Err(format!(
+                "expression evaluated to {}, expected true or false",
+                describe_value(&other)
+            )),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let (token, len) = match (c, next) {
+            ('|', Some('|')) => (Token::Op(BinaryOp::Or), 2),
+            ('&', Some('&')) => (Token::Op(BinaryOp::And), 2),
+            ('=', Some('=')) => (Token::Op(BinaryOp::Equal), 2),
+            ('!', Some('=')) => (Token::Op(BinaryOp::NotEqual), 2),
+            ('<', Some('=')) => (Token::Op(BinaryOp::LessEqual), 2),
+            ('>', Some('=')) => (Token::Op(BinaryOp::GreaterEqual), 2),
+            ('<', _) => (Token::Op(BinaryOp::Less), 1),
+            ('>', _) => (Token::Op(BinaryOp::Greater), 1),
+            ('+', _) => (Token::Op(BinaryOp::Add), 1),
+            ('-', _) => (Token::Op(BinaryOp::Subtract), 1),
+            ('*', _) => (Token::Op(BinaryOp::Multiply), 1),
+            ('/', _) => (Token::Op(BinaryOp::Divide), 1),
+            ('%', _) => (Token::Op(BinaryOp::Remainder), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::LeftParen, 1),
+            (')', _) => (Token::RightParen, 1),
+            (',', _) => (Token::Comma, 1),
+            ('"' | '\'', _) => {
+                let mut text = String::new();
+                let mut end = i + 1;
+                loop {
+                    match chars.get(end) {
+                        None => return Err(format!("unterminated string at offset {}", i)),
+                        Some(&quote) if quote == c => break,
+                        Some('\\') => {
+                            let escaped = chars.get(end + 1).ok_or("unterminated string")?;
+                            text.push(match escaped {
+                                'n' => '\n',
+                                't' => '\t',
+                                other => *other,
+                            });
+                            end += 2;
+                        }
+                        Some(&other) => {
+                            text.push(other);
+                            end += 1;
+                        }
+                    }
+                }
+                (Token::Str(text), end + 1 - i)
+            }
+            _ if c.is_ascii_digit() => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit() || **c == '.')
+                    .count();
+                let text: String = chars[i..i + len].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}'", text))?;
+                (Token::Number(number), len)
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                (Token::Ident(chars[i..i + len].iter().collect()), len)
+            }
+            _ => return Err(format!("unexpected character '{}' at offset {}", c, i)),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => format!("number {}", number),
+        Token::Str(text) => format!("string \"{}\"", text),
+        Token::Ident(name) => format!("'{}'", name),
+        Token::Op(op) => format!("'{}'", op.symbol()),
+        Token::Not => "'!'".to_string(),
+        Token::LeftParen => "'('".to_string(),
+        Token::RightParen => "')'".to_string(),
+        Token::Comma => "','".to_string(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    nodes: usize,
+    // Current nesting, see MAX_DEPTH
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!(
+                "expected {}, found {}",
+                describe(&expected),
+                describe(&token)
+            )),
+            None => Err(format!(
+                "expected {}, found the end of the expression",
+                describe(&expected)
+            )),
+        }
+    }
+
+    fn node(&mut self, node: Node) -> Result<Node, String> {
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return Err(format!("expression is too long (over {} terms)", MAX_NODES));
+        }
+        Ok(node)
+    }
+
+    // Runs `parse` one nesting level deeper, failing past MAX_DEPTH before recursing
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!(
+                "expression is nested too deeply (over {} levels)",
+                MAX_DEPTH
+            ));
+        }
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    // Parses operators of precedence `level` and tighter, left-associatively
+    fn expression(&mut self, level: usize) -> Result<Node, String> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.expression(level + 1)?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.position) {
+            let op = *op;
+            if !operators.contains(&op) {
+                break;
+            }
+            self.position += 1;
+            let right = self.expression(level + 1)?;
+            left = self.node(Node::Binary(op, Box::new(left), Box::new(right)))?;
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.tokens.get(self.position) {
+            Some(Token::Not) => {
+                self.position += 1;
+                let operand = self.nested(Self::unary)?;
+                self.node(Node::Not(Box::new(operand)))
+            }
+            Some(Token::Op(BinaryOp::Subtract)) => {
+                self.position += 1;
+                let operand = self.nested(Self::unary)?;
+                self.node(Node::Negate(Box::new(operand)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let node = match self.next() {
+            Some(Token::Number(number)) => Node::Literal(number_value(number)),
+            Some(Token::Str(text)) => Node::Literal(Value::String(text)),
+            Some(Token::LeftParen) => {
+                let inner = self.nested(|parser| parser.expression(0))?;
+                self.expect(Token::RightParen)?;
+                return Ok(inner);
+            }
+            Some(Token::Ident(name)) => {
+                if self.tokens.get(self.position) == Some(&Token::LeftParen) {
+                    self.position += 1;
+                    self.call(&name)?
+                } else {
+                    match name.as_str() {
+                        "true" => Node::Literal(Value::Bool(true)),
+                        "false" => Node::Literal(Value::Bool(false)),
+                        "null" => Node::Literal(Value::Null),
+                        "status" => Node::Variable(Variable::Status),
+                        "duration_ms" => Node::Variable(Variable::DurationMs),
+                        "body" => Node::Variable(Variable::Body),
+                        _ => {
+                            return Err(format!(
+                                "unknown name '{}' (available: status, duration_ms, body)",
+                                name
+                            ))
+                        }
+                    }
+                }
+            }
+            Some(token) => return Err(format!("unexpected {}", describe(&token))),
+            None => return Err("unexpected end of the expression".to_string()),
+        };
+        self.node(node)
+    }
+
+    // Parses the arguments of a call whose opening parenthesis was just read
+    fn call(&mut self, name: &str) -> Result<Node, String> {
+        let (function, arity) = match name {
+            "header" => (Function::Header, 1),
+            "json" => (Function::Json, 1),
+            "len" => (Function::Len, 1),
+            "contains" => (Function::Contains, 2),
+            _ => {
+                return Err(format!(
+                    "unknown function '{}' (available: header, json, len, contains)",
+                    name
+                ))
+            }
+        };
+        let mut arguments = Vec::new();
+        if self.tokens.get(self.position) != Some(&Token::RightParen) {
+            loop {
+                arguments.push(self.nested(|parser| parser.expression(0))?);
+                if self.tokens.get(self.position) != Some(&Token::Comma) {
+                    break;
+                }
+                self.position += 1;
+            }
+        }
+        self.expect(Token::RightParen)?;
+        if arguments.len() != arity {
+            return Err(format!(
+                "{}() takes {} argument{}, got {}",
+                name,
+                arity,
+                if arity == 1 { "" } else { "s" },
+                arguments.len()
+            ));
+        }
+        Ok(Node::Call(function, arguments))
+    }
+}
+
+struct Evaluator<'a, 'r> {
+    response: &'a Response<'r>,
+    // The body parsed as JSON on the first json() call
+    parsed_body: OnceCell<Option<Value>>,
+    steps: usize,
+}
+
+impl Evaluator<'_, '_> {
+    fn eval(&mut self, node: &Node) -> Result<Value, String> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(format!("evaluation stopped after {} steps", MAX_STEPS));
+        }
+        match node {
+            Node::Literal(value) => Ok(value.clone()),
+            Node::Variable(Variable::Status) => Ok(Value::from(self.response.status)),
+            Node::Variable(Variable::DurationMs) => Ok(number_value(self.response.duration_ms)),
+            Node::Variable(Variable::Body) => Ok(Value::String(self.response.body.to_string())),
+            Node::Not(operand) => Ok(Value::Bool(!self.boolean(operand, "!")?)),
+            Node::Negate(operand) => {
+                let value = self.eval(operand)?;
+                Ok(number_value(-number(&value, "-")?))
+            }
+            Node::Binary(BinaryOp::Or, left, right) => Ok(Value::Bool(
+                self.boolean(left, "||")? || self.boolean(right, "||")?,
+            )),
+            Node::Binary(BinaryOp::And, left, right) => Ok(Value::Bool(
+                self.boolean(left, "&&")? && self.boolean(right, "&&")?,
+            )),
+            Node::Binary(op, left, right) => {
+                let (left, right) = (self.eval(left)?, self.eval(right)?);
+                binary(*op, &left, &right)
+            }
+            Node::Call(function, arguments) => {
+                let mut values = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    values.push(self.eval(argument)?);
+                }
+                self.call(*function, &values)
+            }
+        }
+    }
+
+    fn boolean(&mut self, node: &Node, op: &str) -> Result<bool, String> {
+        match self.eval(node)? {
+            Value::Bool(value) => Ok(value),
+            other => Err(format!(
+                "'{}' expects true or false, got {}",
+                op,
+                describe_value(&other)
+            )),
+        }
+    }
+
+    fn call(&mut self, function: Function, values: &[Value]) -> Result<Value, String> {
+        match (function, values) {
+            (Function::Header, [Value::String(name)]) => Ok(self
+                .response
+                .headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map_or(Value::Null, |value| Value::String(value.to_string()))),
+            (Function::Json, [Value::String(pointer)]) => {
+                let body = self
+                    .parsed_body
+                    .get_or_init(|| serde_json::from_str(self.response.body).ok());
+                let Some(body) = body else {
+                    return Err(format!(
+                        "json(\"{}\"): the response body is not JSON",
+                        pointer
+                    ));
+                };
+                if !pointer.is_empty() && !pointer.starts_with('/') {
+                    return Err(format!(
+                        "json(\"{}\"): a JSON pointer must start with '/'",
+                        pointer
+                    ));
+                }
+                Ok(body.pointer(pointer).cloned().unwrap_or(Value::Null))
+            }
+            (Function::Len, [value]) => match value {
+                Value::String(text) => Ok(Value::from(text.chars().count())),
+                Value::Array(items) => Ok(Value::from(items.len())),
+                Value::Object(fields) => Ok(Value::from(fields.len())),
+                other => Err(format!(
+                    "len() expects a string, array or object, got {}",
+                    describe_value(other)
+                )),
+            },
+            (Function::Contains, [Value::String(text), Value::String(needle)]) => {
+                Ok(Value::Bool(text.contains(needle.as_str())))
+            }
+            (Function::Contains, [Value::Array(items), needle]) => Ok(Value::Bool(
+                items.iter().any(|item| json_values_equal(item, needle)),
+            )),
+            (Function::Contains, [haystack, _]) => Err(format!(
+                "contains() expects a string or array, got {}",
+                describe_value(haystack)
+            )),
+            (Function::Header | Function::Json, [other]) => Err(format!(
+                "{}() expects a string, got {}",
+                if matches!(function, Function::Header) {
+                    "header"
+                } else {
+                    "json"
+                },
+                describe_value(other)
+            )),
+            // Arity is checked by the parser
+            _ => Err("wrong number of arguments".to_string()),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    let symbol = op.symbol();
+    match op {
+        BinaryOp::Equal => return Ok(Value::Bool(json_values_equal(left, right))),
+        BinaryOp::NotEqual => return Ok(Value::Bool(!json_values_equal(left, right))),
+        BinaryOp::Add => {
+            if let (Value::String(a), Value::String(b)) = (left, right) {
+                return Ok(Value::String(format!("{}{}", a, b)));
+            }
+        }
+        BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+            let ordering = match (left, right) {
+                (Value::String(a), Value::String(b)) => a.cmp(b),
+                _ => number(left, symbol)?
+                    .partial_cmp(&number(right, symbol)?)
+                    .ok_or_else(|| format!("'{}' can't compare {} and {}", symbol, left, right))?,
+            };
+            return Ok(Value::Bool(match op {
+                BinaryOp::Less => ordering.is_lt(),
+                BinaryOp::LessEqual => ordering.is_le(),
+                BinaryOp::Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }));
+        }
+        _ => {}
+    }
+    let (a, b) = (number(left, symbol)?, number(right, symbol)?);
+    let outcome = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Subtract => a - b,
+        BinaryOp::Multiply => a * b,
+        BinaryOp::Divide | BinaryOp::Remainder if b == 0.0 => {
+            return Err(format!("'{}' by zero", symbol))
+        }
+        BinaryOp::Divide => a / b,
+        _ => a % b,
+    };
+    Ok(number_value(outcome))
+}
+
+fn number(value: &Value, op: &str) -> Result<f64, String> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("'{}' expects numbers, got {}", op, describe_value(value)))
+}
+
+// Whole numbers stay integers, so they print as 4 rather than 4.0
+fn number_value(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < 9e15 {
+        Value::from(number as i64)
+    } else {
+        Value::from(number)
+    }
+}
+
+// e.g. "a number 5", or just "null"
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        other => format!("{} {}", json_type_name(other), other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_nesting_is_rejected_without_overflowing() {
+        for source in [
+            format!("{}true{}", "(".repeat(5000), ")".repeat(5000)),
+            format!("{}true", "!".repeat(5000)),
+            format!("{}1 == -1", "-".repeat(5000)),
+            format!("{}\"/a\"{}", "len(".repeat(5000), ")".repeat(5000)),
+        ] {
+            let error = Expr::parse(&source).unwrap_err();
+            assert!(error.contains("nested too deeply"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn nesting_within_the_limit_parses() {
+        let source = format!(
+            "{}true{}",
+            "(".repeat(MAX_DEPTH - 1),
+            ")".repeat(MAX_DEPTH - 1)
+        );
+        assert!(Expr::parse(&source).is_ok());
+        assert!(Expr::parse("!!(status == 200) && -(-1) == 1").is_ok());
+    }
+}
@@ -0,0 +1,234 @@
+//! Standalone HTML output for `--html`, and the auto-refreshing `--dashboard` page.
+
+use std::fmt::Write;
+use std::fs;
+
+use crate::{summarize_environments, RunSummary, UrlTestResult};
+
+/// Writes a self-contained HTML page with a navigation list of environments (with pass/fail
+/// badges) and one section per environment. Each section has a stable `env-<name>` anchor
+/// so a link can point straight at it.
+pub fn write_html_report(
+    path: &str,
+    summary: &RunSummary,
+    results: &[&UrlTestResult],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let environments = summarize_environments(results.iter().copied());
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let title = if summary.labels.is_empty() {
+        "url_tester report".to_string()
+    } else {
+        format!("url_tester report: {}", escape(&summary.labels.join(", ")))
+    };
+    writeln!(html, "<title>{}</title>", title)?;
+    html.push_str(STYLE);
+    writeln!(html, "</head>\n<body>\n<h1>{}</h1>", title)?;
+    writeln!(
+        html,
+        "<p>{} tests, {} passed, {} failed in {:.2}s (config {})</p>",
+        summary.total,
+        summary.passed,
+        summary.failed,
+        summary.duration_secs,
+        escape(&summary.config_hash)
+    )?;
+
+    html.push_str("<nav>\n<ul>\n");
+    for (name, env) in &environments {
+        let badge = if env.failed > 0 {
+            format!("<span class=\"badge fail\">{} failed</span>", env.failed)
+        } else {
+            "<span class=\"badge pass\">all passed</span>".to_string()
+        };
+        writeln!(
+            html,
+            "<li><a href=\"#{}\">{}</a> {} <span class=\"count\">{}/{}</span></li>",
+            anchor_id(name),
+            escape(name),
+            badge,
+            env.passed,
+            env.total
+        )?;
+    }
+    html.push_str("</ul>\n</nav>\n");
+
+    for name in environments.keys() {
+        let mut env_results: Vec<&UrlTestResult> = results
+            .iter()
+            .copied()
+            .filter(|res| &res.environment_name == name)
+            .collect();
+        env_results.sort_by(|a, b| a.passed.cmp(&b.passed).then_with(|| a.url.cmp(&b.url)));
+
+        writeln!(
+            html,
+            "<section id=\"{}\">\n<h2><a href=\"#{}\">{}</a></h2>",
+            anchor_id(name),
+            anchor_id(name),
+            escape(name)
+        )?;
+        html.push_str("<table>\n<tr><th>URL</th><th>Status</th><th>Result</th><th>Duration</th><th>Error Message</th></tr>\n");
+        for res in env_results {
+            let (class, label) = if res.passed {
+                ("pass", "PASS")
+            } else {
+                ("fail", "FAIL")
+            };
+            writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{:.2}s</td><td>{}</td></tr>",
+                class,
+                escape(&res.url),
+                res.status_code.map_or("N/A".to_string(), |s| s.to_string()),
+                label,
+                res.duration_secs,
+                failure_cell(res)
+            )?;
+        }
+        html.push_str("</table>\n</section>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    fs::write(path, html)?;
+    Ok(())
+}
+
+// Failing URLs listed on a dashboard tile before the rest are summarized as "and N more"
+const DASHBOARD_FAILURES_SHOWN: usize = 5;
+
+/// Writes the `--dashboard` page: one big green or red tile per environment with its pass
+/// count and health score, reloading itself every `refresh_secs`. The page is written to a
+/// temporary file and renamed into place, so a browser never loads a half-written page.
+pub fn write_dashboard(
+    path: &str,
+    summary: &RunSummary,
+    results: &[&UrlTestResult],
+    refresh_secs: u64,
+    updated_at: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(
+        html,
+        "<meta http-equiv=\"refresh\" content=\"{}\">",
+        refresh_secs
+    )?;
+    html.push_str("<title>url_tester status</title>\n");
+    html.push_str(DASHBOARD_STYLE);
+    html.push_str("</head>\n<body>\n<main>\n");
+
+    for (name, env) in summarize_environments(results.iter().copied()) {
+        let class = if env.failed > 0 { "fail" } else { "pass" };
+        let health = summary.health_scores.get(&name).copied().unwrap_or(0.0);
+        writeln!(
+            html,
+            "<section class=\"tile {}\">\n<h2>{}</h2>\n<p class=\"state\">{}</p>\n<p>{}/{} passed, health {:.0}</p>",
+            class,
+            escape(&name),
+            if env.failed > 0 { "DOWN" } else { "UP" },
+            env.passed,
+            env.total,
+            health
+        )?;
+        let failing: Vec<&UrlTestResult> = results
+            .iter()
+            .copied()
+            .filter(|res| res.environment_name == name && !res.passed)
+            .collect();
+        if !failing.is_empty() {
+            html.push_str("<ul>\n");
+            for res in failing.iter().take(DASHBOARD_FAILURES_SHOWN) {
+                writeln!(html, "<li>{}</li>", escape(&res.path))?;
+            }
+            if failing.len() > DASHBOARD_FAILURES_SHOWN {
+                writeln!(
+                    html,
+                    "<li>and {} more</li>",
+                    failing.len() - DASHBOARD_FAILURES_SHOWN
+                )?;
+            }
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</section>\n");
+    }
+    for (name, reason) in &summary.skipped_environments {
+        writeln!(
+            html,
+            "<section class=\"tile skipped\">\n<h2>{}</h2>\n<p class=\"state\">SKIPPED</p>\n<p>{}</p>\n</section>",
+            escape(name),
+            escape(reason)
+        )?;
+    }
+
+    writeln!(
+        html,
+        "</main>\n<footer>Updated {} (config {})</footer>\n</body>\n</html>",
+        escape(updated_at),
+        escape(&summary.config_hash)
+    )?;
+    let temp_path = format!("{}.tmp", path);
+    fs::write(&temp_path, html)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+const DASHBOARD_STYLE: &str = "<style>
+body { font-family: sans-serif; margin: 0; background: #111; color: #fff; }
+main { display: flex; flex-wrap: wrap; gap: 1em; padding: 1em; }
+.tile { flex: 1 1 20em; border-radius: 8px; padding: 1em 1.5em; }
+.tile h2 { margin: 0; font-size: 2em; }
+.tile .state { font-size: 4em; font-weight: bold; margin: 0.2em 0; }
+.tile.pass { background: #2e7d32; }
+.tile.fail { background: #c62828; }
+.tile.skipped { background: #555; }
+footer { padding: 0 1em 1em; color: #aaa; }
+</style>\n";
+
+const STYLE: &str = "<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+tr.fail td { background: #fdecea; }
+.badge { border-radius: 4px; padding: 1px 6px; color: #fff; font-size: 0.85em; }
+.badge.pass { background: #2e7d32; }
+.badge.fail { background: #c62828; }
+</style>\n";
+
+// A single failure is shown as is, several as a numbered list
+fn failure_cell(res: &UrlTestResult) -> String {
+    match res.failures.as_slice() {
+        [] => escape(res.error_message.as_deref().unwrap_or("")),
+        [failure] => escape(failure),
+        failures => {
+            let items: String = failures
+                .iter()
+                .map(|failure| format!("<li>{}</li>", escape(failure)))
+                .collect();
+            format!("<ol>{}</ol>", items)
+        }
+    }
+}
+
+// Environment names are free text, so anything outside [A-Za-z0-9_-] becomes '-'
+fn anchor_id(env_name: &str) -> String {
+    let slug: String = env_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("env-{}", slug)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,49 @@
+//! Ctrl+C during a run: no new requests are dispatched, the ones in flight are awaited, and
+//! the partial run is reported like a complete one. A second Ctrl+C exits at once.
+
+use tokio::sync::watch;
+
+/// Exit code of a run stopped by Ctrl+C, the conventional one for SIGINT.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+/// Whether the run was interrupted. Cheap to clone; every clone sees the same Ctrl+C.
+#[derive(Clone)]
+pub struct Interrupt(watch::Receiver<bool>);
+
+impl Interrupt {
+    /// Starts listening for Ctrl+C.
+    pub fn listen() -> Self {
+        let (interrupted_tx, interrupted) = watch::channel(false);
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            eprintln!(
+                "\nInterrupted: waiting for the requests in flight, then reporting what ran (Ctrl+C again to quit now)."
+            );
+            let _ = interrupted_tx.send(true);
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(i32::from(EXIT_INTERRUPTED));
+            }
+        });
+        Interrupt(interrupted)
+    }
+
+    /// Never fires, for runs that aren't driven from the command line.
+    pub fn never() -> Self {
+        Interrupt(watch::channel(false).1)
+    }
+
+    pub fn is_set(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once Ctrl+C was pressed, or right away if it already was.
+    pub async fn wait(&self) {
+        let mut interrupted = self.0.clone();
+        if interrupted.wait_for(|set| *set).await.is_err() {
+            // Nothing can set it any more
+            std::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! JSON output for `--json`.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::metadata::RunMetadata;
+use crate::schema::SCHEMA_VERSION;
+use crate::{summarize_environments, EnvironmentSummary, JsonShape, RunSummary, UrlTestResult};
+
+/// `--json-shape flat`: the run summary plus one array holding every result.
+#[derive(Serialize)]
+struct FlatReport<'a> {
+    schema_version: u32,
+    metadata: &'a RunMetadata,
+    summary: &'a RunSummary,
+    results: Vec<&'a UrlTestResult>,
+}
+
+/// `--json-shape nested`: results grouped under their environment, each with its own summary.
+#[derive(Serialize)]
+struct NestedReport<'a> {
+    schema_version: u32,
+    metadata: &'a RunMetadata,
+    summary: &'a RunSummary,
+    environments: BTreeMap<String, EnvironmentReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct EnvironmentReport<'a> {
+    summary: EnvironmentSummary,
+    results: Vec<&'a UrlTestResult>,
+}
+
+/// Writes the run metadata, results and run summary to `path` in the requested shape.
+pub fn write_json_report(
+    path: &str,
+    shape: JsonShape,
+    metadata: &RunMetadata,
+    summary: &RunSummary,
+    results: &[&UrlTestResult],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sorted: Vec<&UrlTestResult> = results.to_vec();
+    sorted.sort_by(|a, b| {
+        a.environment_name
+            .cmp(&b.environment_name)
+            .then_with(|| a.url.cmp(&b.url))
+    });
+
+    let json = match shape {
+        JsonShape::Flat => serde_json::to_string_pretty(&FlatReport {
+            schema_version: SCHEMA_VERSION,
+            metadata,
+            summary,
+            results: sorted,
+        })?,
+        JsonShape::Nested => {
+            let mut environments: BTreeMap<String, EnvironmentReport> =
+                summarize_environments(results.iter().copied())
+                    .into_iter()
+                    .map(|(name, summary)| {
+                        let report = EnvironmentReport {
+                            summary,
+                            results: Vec::new(),
+                        };
+                        (name, report)
+                    })
+                    .collect();
+            for res in sorted {
+                if let Some(env) = environments.get_mut(&res.environment_name) {
+                    env.results.push(res);
+                }
+            }
+            serde_json::to_string_pretty(&NestedReport {
+                schema_version: SCHEMA_VERSION,
+                metadata,
+                summary,
+                environments,
+            })?
+        }
+    };
+
+    fs::write(path, json)?;
+    Ok(())
+}
@@ -1,40 +1,572 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
+use std::process::ExitCode;
 use std::time::Instant;
 
+mod anomaly;
+mod auth;
+mod concurrency;
+mod connection;
+mod cookies;
+mod csv_report;
+mod dependencies;
+mod error_groups;
+mod explain;
+mod expr;
+mod html_report;
+mod interrupt;
+mod json_report;
+mod maintenance;
+mod metadata;
+mod openmetrics_report;
+mod rate_limit;
+mod redact;
+mod report_diff;
+mod schema;
+mod schemes;
+mod self_test;
+mod sinks;
+mod sqlite_report;
+mod stream;
+mod throttle;
+mod timezone;
+mod usage;
+mod verdict;
+
 // ANSI escape codes for text colors
 const COLOR_GREEN: &str = "\x1b[32m";
 const COLOR_RED: &str = "\x1b[31m";
+const COLOR_YELLOW: &str = "\x1b[33m";
 const COLOR_RESET: &str = "\x1b[0m"; // Resets text color to default
 
 /// A simple CLI tool to test URLs from a configuration file.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Path to the configuration file (e.g., config.toml)
+    #[arg(short, long, required_unless_present = "print_schema")]
+    config: Option<String>,
+    /// Optional path to an output report, may be repeated; the format follows the extension: .json, .ndjson or .jsonl, .html, anything else is CSV (e.g., report.csv)
     #[arg(short, long)]
-    config: String,
-    /// Optional path to an output CSV file (e.g., report.csv)
-    #[arg(short, long)]
-    output: Option<String>,
+    output: Vec<String>,
+    /// Optional directory to write one CSV per environment into, named <env>.csv, with the same columns as --output
+    #[arg(long, value_name = "DIR")]
+    output_per_env: Option<String>,
+    /// Optional path to an output JSON file with every result and the run summary
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+    /// Layout of the JSON output: one flat results array, or results grouped per environment
+    #[arg(long, value_enum, default_value_t = JsonShape::Flat)]
+    json_shape: JsonShape,
+    /// Optional path to an output HTML report with a section per environment
+    #[arg(long, value_name = "FILE")]
+    html: Option<String>,
+    /// Optional path to a status-dashboard HTML page with a big pass/fail tile per environment, rewritten after every run (e.g. each --watch iteration)
+    #[arg(long, value_name = "FILE")]
+    dashboard: Option<String>,
+    /// How often the dashboard page reloads itself in the browser; defaults to the --watch interval, else 30
+    #[arg(long, value_name = "SECS", requires = "dashboard")]
+    dashboard_refresh: Option<u64>,
+    /// Optional path to an OpenMetrics file with a duration histogram and pass/fail counters per environment
+    #[arg(long, value_name = "FILE")]
+    openmetrics: Option<String>,
+    /// Optional: Attach the test_id of the slowest result in each histogram bucket as an exemplar
+    #[arg(long, requires = "openmetrics")]
+    openmetrics_exemplars: bool,
+    /// Optional path to a SQLite database that every run and its results are appended to
+    #[arg(long, value_name = "FILE")]
+    sqlite: Option<String>,
+    /// Optional: Free-text label for the run (e.g. "post-deploy"), recorded in every output; may be repeated
+    #[arg(long, value_name = "LABEL")]
+    label: Vec<String>,
+    /// Optional: File listing the changed paths, one per line as written in the config; only those paths are tested
+    #[arg(long, value_name = "FILE")]
+    changed_paths_file: Option<String>,
+    /// Optional: Test only the paths matching this regex, as written in the config
+    #[arg(long, value_name = "REGEX", value_parser = regex::Regex::new)]
+    path_filter: Option<regex::Regex>,
     /// Optional: Run tests only for a specific environment name defined in the config (e.g., "dev", "staging")
     #[arg(long)]
     env: Option<String>,
+    /// Optional: Run tests only for the environments of a group defined under [groups] in the config
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["env", "compare_envs"])]
+    group: Option<String>,
+    /// Optional: Keep re-running the tests every N seconds, reloading the config before each iteration
+    #[arg(long, value_name = "SECS")]
+    watch: Option<u64>,
+    /// Optional: Re-run the tests back to back until one fails, then report that iteration
+    #[arg(long, conflicts_with = "watch")]
+    repeat_until_fail: bool,
+    /// Most iterations of --repeat-until-fail before giving up
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1000,
+        requires = "repeat_until_fail"
+    )]
+    max_iterations: u64,
+    /// Optional: Order the failing table by severity (connection errors, 5xx, 4xx, then check failures)
+    #[arg(long)]
+    sort_severity: bool,
+    /// Optional: Path to a CSV report from a previous run to compare this run against
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<String>,
+    /// Optional: Exit 0 instead of 3 when no test ran, e.g. because the filters left no paths
+    #[arg(long)]
+    empty_ok: bool,
+    /// Which failures produce a non-zero exit code
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = FailOn::Any,
+        requires_if("regressions", "baseline"),
+        requires_if("anomalies", "anomaly_threshold")
+    )]
+    fail_on: FailOn,
+    /// Optional: Flag results slower than this many times their URL's historical median, taken from the --sqlite database or else the --baseline report
+    #[arg(long, value_name = "FACTOR")]
+    anomaly_threshold: Option<f64>,
+    /// How many of each URL's most recent passing results the historical median is taken over
+    #[arg(
+        long,
+        value_name = "RUNS",
+        default_value_t = 10,
+        requires = "anomaly_threshold"
+    )]
+    anomaly_history: usize,
+    /// URLs with fewer passing results in their history are left out of the anomaly check
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        requires = "anomaly_threshold"
+    )]
+    anomaly_min_samples: usize,
+    /// Optional: Print full details, including the response body, for every failing test
+    #[arg(short, long)]
+    verbose: bool,
+    /// Optional: Reformat JSON response bodies with indentation in verbose output
+    #[arg(long)]
+    pretty_body: bool,
+    /// Optional: Stop reading response bodies after this many bytes, checking only the part read
+    #[arg(long, value_name = "BYTES")]
+    max_body_read: Option<usize>,
+    /// Optional: Read response bodies no faster than this many kilobits per second, to simulate a slow link
+    #[arg(long, value_name = "KBPS", value_parser = clap::value_parser!(u32).range(1..))]
+    throttle_kbps: Option<u32>,
+    /// Optional: Compare each path's status and normalized body between two environments
+    #[arg(long, num_args = 2, value_names = ["ENV_A", "ENV_B"], conflicts_with = "env")]
+    compare_envs: Option<Vec<String>>,
+    /// Optional: Open a new connection for every request (no pooling, `Connection: close`), so each pays the full handshake
+    #[arg(long)]
+    no_keepalive: bool,
+    /// Optional: Report `forbidden_patterns` matches as warnings instead of failures
+    #[arg(long)]
+    forbidden_warn_only: bool,
+    /// Optional: Test environments even outside their `allowed_hours`
+    #[arg(long)]
+    ignore_schedule: bool,
+    /// Optional: Verify planned downtime: every environment must return the `[maintenance]` page (503 by default), and a normal response fails
+    #[arg(long)]
+    maintenance: bool,
+    /// Optional: Skip config entries with an unsupported URL scheme (e.g. ftp:// or mailto:) with a warning instead of refusing to load the config
+    #[arg(long)]
+    lax_config: bool,
+    /// Optional: Test every path even when an environment's health_gate fails
+    #[arg(long)]
+    ignore_health_gate: bool,
+    /// Optional: Send each request with a User-Agent from the config's `user_agents`, in turn or at random
+    #[arg(long, value_enum, value_name = "ORDER", num_args = 0..=1, default_missing_value = "round-robin")]
+    rotate_user_agent: Option<UserAgentOrder>,
+    /// Optional: Skip looking up each environment's host before its tests, which fails them all at once when it doesn't resolve
+    #[arg(long)]
+    no_dns_precheck: bool,
+    /// Optional: Send N throwaway requests per GET/HEAD path before measuring, to warm up connections
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    warmup: u32,
+    /// Optional: Wait this many seconds before each environment's first requests (overridden by startup_delay_secs)
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    initial_delay: Option<f64>,
+    /// Optional: Retry a failing request up to N times when it fails with a connection error, timeout or 5xx
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+    /// Seconds to wait between retries
+    #[arg(long, value_name = "SECS", default_value_t = 1.0, value_parser = parse_secs)]
+    retry_delay: f64,
+    /// Optional: Also retry body and redirect assertion failures, e.g. a stale body right after a deploy
+    #[arg(long, requires = "retries")]
+    retry_on_body_assertion: bool,
+    /// Optional: Re-request failing URLs once after the run and only count failures that persist
+    #[arg(long)]
+    reverify: bool,
+    /// Optional: Stop dispatching an environment's paths once its --fail-on verdict can't change anymore (a failure, a critical failure, or every critical path passed)
+    #[arg(long, conflicts_with = "reverify")]
+    short_circuit: bool,
+    /// Format of timestamps in every output: "rfc3339", or a strftime-style pattern such as "%F %T"
+    #[arg(long, value_name = "FORMAT", default_value = timezone::RFC3339, value_parser = timezone::parse_time_format)]
+    time_format: String,
+    /// Optional: Write timestamps in UTC (the default)
+    #[arg(long, conflicts_with = "local")]
+    utc: bool,
+    /// Optional: Write timestamps in the machine's time zone ($TZ or /etc/localtime)
+    #[arg(long)]
+    local: bool,
+    /// Optional: Diff-friendly console output: no colors, timings or progress messages, rows sorted by env and URL
+    #[arg(long)]
+    stable_output: bool,
+    /// Optional: Skip the result tables and only print the aggregate statistics
+    #[arg(long)]
+    stats_only: bool,
+    /// Optional: Print at most N failing rows per environment, picking a mix of failure kinds; reports still get every row
+    #[arg(long, value_name = "N")]
+    max_failures_shown: Option<usize>,
+    /// Optional: Leave the Duration column of the console tables empty
+    #[arg(long)]
+    no_duration: bool,
+    /// Optional: Print an ASCII histogram of response durations per environment in the summary
+    #[arg(long, conflicts_with_all = ["no_duration", "stable_output"])]
+    histogram: bool,
+    /// Optional: Print the most common failure causes in the summary, grouping error messages that only differ in URLs, IDs or timings
+    #[arg(long)]
+    group_errors: bool,
+    /// Optional: Number of causes listed by --group-errors
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        requires = "group_errors"
+    )]
+    group_errors_top: usize,
+    /// Optional: Maximum number of in-flight requests per environment (default: all paths at once)
+    #[arg(long, value_name = "N")]
+    concurrency: Option<usize>,
+    /// Optional: Test all environments at the same time, sharing out request slots between them in turn
+    #[arg(long)]
+    parallel_envs: bool,
+    /// Optional: Maximum number of in-flight requests across all environments with --parallel-envs
+    #[arg(long, value_name = "N", requires = "parallel_envs", value_parser = concurrency::parse_limit)]
+    total_concurrency: Option<usize>,
+    /// Optional: Maximum number of GET and HEAD requests in flight at once, across all environments
+    #[arg(long, value_name = "N", value_parser = concurrency::parse_limit)]
+    read_concurrency: Option<usize>,
+    /// Optional: Maximum number of requests with any other method (POST, PUT, DELETE...) in flight at once, across all environments
+    #[arg(long, value_name = "N", value_parser = concurrency::parse_limit)]
+    write_concurrency: Option<usize>,
+    /// Optional: Maximum number of in-flight requests to any one host, across all environments pointing at it
+    #[arg(long, value_name = "N", value_parser = concurrency::parse_limit)]
+    max_per_host: Option<usize>,
+    /// Optional: Halve the concurrency when the recent error rate is too high, and ramp it back up as errors subside
+    #[arg(long)]
+    adaptive_concurrency: bool,
+    /// Error rate (percent of recent requests failing with connection errors, timeouts or 5xx) that triggers a cut
+    #[arg(
+        long,
+        value_name = "PCT",
+        default_value_t = 20.0,
+        requires = "adaptive_concurrency"
+    )]
+    adaptive_error_threshold: f64,
+    /// Optional: Save the per-environment status code counts to a CSV file (columns env, status, count)
+    #[arg(long, value_name = "FILE")]
+    status_summary_csv: Option<String>,
+    /// Optional: Save one row per environment and State value (passed, failed, worst status, max duration) to a CSV file; see --print-schema csv for the columns
+    #[arg(long, value_name = "FILE")]
+    state_summary_csv: Option<String>,
+    /// Order in which each environment's paths are dispatched
+    #[arg(long, value_enum, default_value_t = Schedule::Fifo)]
+    schedule: Schedule,
+    /// Optional: Print the fully-resolved config (secrets redacted) as TOML or JSON and exit
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "toml")]
+    print_config: Option<ConfigFormat>,
+    /// Optional: List the requests the run would send, after every filter, and exit without sending any
+    #[arg(long, conflicts_with_all = ["watch", "repeat_until_fail"])]
+    dry_run: bool,
+    /// Optional: With --dry-run, describe each request's effective method, headers (redacted) and checks
+    #[arg(long, requires = "dry_run")]
+    explain: bool,
+    /// Output format of --explain
+    #[arg(long, value_enum, default_value_t = ExplainFormat::Text, requires = "explain")]
+    explain_format: ExplainFormat,
+    /// Optional: Run paths marked destructive (e.g. DELETE requests) in every environment
+    #[arg(long)]
+    allow_destructive: bool,
+    /// Optional: Print the equivalent curl command of every request, after every filter, and exit without sending any
+    #[arg(long, conflicts_with_all = ["watch", "repeat_until_fail", "dry_run"])]
+    dry_run_requests: bool,
+    /// Optional: Show secret header values and redacted parameters in --dry-run-requests output instead of masking them
+    #[arg(long, requires = "dry_run_requests")]
+    reveal_secrets: bool,
+    /// Optional: Print a JSON description of the CSV or JSON output fields and exit (default: json with --json, else csv)
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "auto")]
+    print_schema: Option<SchemaFormat>,
+}
+
+/// Layout of the `--json` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum JsonShape {
+    Flat,
+    Nested,
+}
+
+/// Output format for `--print-config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+/// Output format for `--explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExplainFormat {
+    Text,
+    Markdown,
+}
+
+/// Output described by `--print-schema`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SchemaFormat {
+    Auto,
+    Csv,
+    Json,
+}
+
+/// Subcommands that inspect a config instead of running the tests.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the environments defined in a config without making any requests
+    Envs {
+        /// Path to the configuration file (e.g., config.toml)
+        #[arg(short, long)]
+        config: String,
+        /// Output format for the listing
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+    },
+    /// Evaluate each path's body assertions against saved sample bodies, without any requests
+    CheckAssertions {
+        /// Path to the configuration file (e.g., config.toml)
+        #[arg(short, long)]
+        config: String,
+        /// Directory with one sample body per path, named after the sanitized path (e.g. `api_items.json` for `/api/items`)
+        #[arg(long, value_name = "DIR")]
+        samples: String,
+        /// Optional: Exit 0 instead of 3 when no path has a sample, so nothing was checked
+        #[arg(long)]
+        empty_ok: bool,
+    },
+    /// Compare two saved reports (CSV, or JSON by extension) and list what changed; exits 1 if a test started failing
+    Diff {
+        /// Report of the earlier run
+        before: String,
+        /// Report of the later run
+        after: String,
+        /// Output format for the comparison
+        #[arg(long, value_enum, default_value_t = DiffFormat::Table)]
+        format: DiffFormat,
+        /// Smallest duration change, in percent, reported as slower or faster
+        #[arg(long, value_name = "PCT", default_value_t = 20.0)]
+        duration_threshold_pct: f64,
+    },
+    /// Run the request and check engine against a built-in local server, to tell whether the tool or the network is broken; exits 1 if a scenario misbehaves
+    SelfTest,
+}
+
+/// Output format for the listing subcommands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ListFormat {
+    Table,
+    Json,
+}
+
+/// Output format for the `diff` subcommand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DiffFormat {
+    Table,
+    Json,
+    Markdown,
+}
+
+/// Exit-code policy selected with `--fail-on`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FailOn {
+    /// Any failing test
+    Any,
+    /// Only failing tests of paths marked `severity = "critical"`
+    Critical,
+    /// Only tests that passed in the `--baseline` report but fail now
+    Regressions,
+    /// Only results flagged by `--anomaly-threshold`, whether they passed or not
+    Anomalies,
+    /// Always exit 0 once the run completes
+    None,
+}
+
+/// How `--rotate-user-agent` picks from `user_agents`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum UserAgentOrder {
+    /// Each request takes the next entry, wrapping around
+    RoundRobin,
+    /// Each request takes a random entry
+    Random,
+}
+
+/// Dispatch order selected with `--schedule`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Schedule {
+    /// The order the paths appear in the config
+    Fifo,
+    /// A random order, different on every run
+    Shuffle,
+    /// Slowest first, by the durations recorded in the `--baseline` report
+    LongestFirst,
 }
 
 /// Represents a single environment with its base URL.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Environment {
+    // May be left out when the config has a `baseurl_template`
+    #[serde(default)]
     baseurl: String,
+    // Headers sent with every request to this environment, overriding global headers
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    // Seconds to wait before the first requests to this environment, e.g. while it warms up
+    #[serde(default)]
+    startup_delay_secs: Option<f64>,
+    // Most in-flight requests to this environment, overriding --concurrency
+    #[serde(default)]
+    max_concurrency: Option<usize>,
+    // HTTP version every response must have been served with, e.g. "2"
+    #[serde(default)]
+    require_http_version: Option<String>,
+    // Speak HTTP/2 without negotiating it first, for cleartext (h2c) backends
+    #[serde(default)]
+    http2_prior_knowledge: bool,
+    // Disabled environments only run when named with --env
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    // Local time window the environment may be tested in, e.g. "20:00-06:00"
+    #[serde(default)]
+    allowed_hours: Option<String>,
+    // IANA time zone of `allowed_hours`, e.g. "Australia/Sydney"; defaults to UTC
+    #[serde(default)]
+    timezone: Option<String>,
+    // Runs paths marked `destructive` without --allow-destructive
+    #[serde(default)]
+    allow_destructive: bool,
+    // Path requested before any other; when it fails the environment's paths are skipped
+    #[serde(default)]
+    health_gate: Option<String>,
+    // Under planned maintenance: every path must return the `[maintenance]` page
+    #[serde(default)]
+    maintenance: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment {
+            baseurl: String::new(),
+            headers: HashMap::new(),
+            startup_delay_secs: None,
+            max_concurrency: None,
+            require_http_version: None,
+            http2_prior_knowledge: false,
+            enabled: default_enabled(),
+            allowed_hours: None,
+            timezone: None,
+            allow_destructive: false,
+            health_gate: None,
+            maintenance: false,
+        }
+    }
+}
+
+/// A daily window such as "20:00-06:00", in minutes since midnight. The end is exclusive,
+/// and a window whose end is before its start runs across midnight.
+struct HoursWindow {
+    start: u32,
+    end: u32,
+}
+
+impl HoursWindow {
+    fn parse(text: &str) -> Result<HoursWindow, String> {
+        let invalid = || {
+            format!(
+                "invalid allowed_hours '{}', expected e.g. \"20:00-06:00\"",
+                text
+            )
+        };
+        let minutes = |time: &str| -> Option<u32> {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+        let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+        Ok(HoursWindow {
+            start: minutes(start).ok_or_else(invalid)?,
+            end: minutes(end).ok_or_else(invalid)?,
+        })
+    }
+
+    fn contains(&self, minute_of_day: u32) -> bool {
+        if self.start <= self.end {
+            // Equal ends allow the whole day
+            self.start == self.end || (self.start..self.end).contains(&minute_of_day)
+        } else {
+            minute_of_day >= self.start || minute_of_day < self.end
+        }
+    }
+}
+
+/// Why an environment can't be tested at the Unix time `now`, if its `allowed_hours`
+/// window is closed.
+fn outside_allowed_hours(env: &Environment, now: u64) -> Result<Option<String>, String> {
+    let Some(hours) = &env.allowed_hours else {
+        return Ok(None);
+    };
+    let window = HoursWindow::parse(hours)?;
+    let zone_name = env.timezone.as_deref().unwrap_or("UTC");
+    let zone = timezone::TimeZone::load(zone_name)?;
+    let local = now as i64 + zone.offset_at(now as i64);
+    let minute_of_day = (local.rem_euclid(86_400) / 60) as u32;
+    if window.contains(minute_of_day) {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "outside allowed_hours {} ({}, now {:02}:{:02})",
+        hours,
+        zone_name,
+        minute_of_day / 60,
+        minute_of_day % 60
+    )))
+}
+
+fn default_enabled() -> bool {
+    true
 }
 
 /// Represents the structure of our configuration file.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Config {
+    #[serde(default)]
     environments: HashMap<String, Environment>,
-    paths: Vec<String>,
+    // Named lists of environments, run together with --group, e.g. all-prod-regions
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    groups: BTreeMap<String, Vec<String>>,
+    // Base URL for environments without their own, with `{env}` replaced by the name
+    #[serde(default)]
+    baseurl_template: Option<String>,
+    // Environments that only need the templated base URL
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    environment_names: Vec<String>,
+    // Each entry is either a plain path string or a table with extra options
+    #[serde(deserialize_with = "deserialize_paths")]
+    paths: Vec<PathConfig>,
     // Optional application error key to search for (e.g., "code", "errorCode")
     // Defaults to "code" if not specified in the TOML.
     #[serde(default = "default_app_error_key")]
@@ -44,6 +576,77 @@ struct Config {
     // in which case it will default to None.
     #[serde(default)]
     app_error_code_to_fail: Option<String>,
+    // Optional shell commands to run once the report has been produced
+    #[serde(default)]
+    hooks: HooksConfig,
+    // By default every request asks intermediate caches to revalidate with the origin;
+    // set this to true to test the cached path instead
+    #[serde(default)]
+    respect_caches: bool,
+    // Headers sent with every request, overridden by environment and path headers
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    // Headers per HTTP method (e.g. "POST"), overriding `headers` and overridden by
+    // environment and path headers
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    default_headers: HashMap<String, HashMap<String, String>>,
+    // Body per HTTP method, merged into the body of every path with that method
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    default_body: HashMap<String, String>,
+    // Patterns no response body may contain, e.g. card numbers; failures name the pattern only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    forbidden_patterns: Vec<ForbiddenPattern>,
+    // User-Agent values used by --rotate-user-agent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    user_agents: Vec<String>,
+    // Body substrings that mark an otherwise successful response as failed (e.g. a soft 404 page)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    soft_error_markers: Vec<String>,
+    // Hosts a response must never come from or redirect to, e.g. the marketing site a
+    // misrouted API request ends up on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fail_on_redirect_to_hosts: Vec<String>,
+    // `Server-Timing` metrics written to their own CSV columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    server_timing_metrics: Vec<String>,
+    // Response headers recorded on every result, keyed by annotation name, e.g.
+    // { release = "x-release" }; each gets an `annotation_<name>` CSV column
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotate_from_headers: BTreeMap<String, String>,
+    // Latency objective: a result is within SLA when it passed in at most this many ms
+    #[serde(default)]
+    sla_ms: Option<f64>,
+    // Percentage of results that should be within SLA; environments below it are shown in red
+    #[serde(default)]
+    sla_target_pct: Option<f64>,
+    // Whether duration_secs (and so the SLA and latency stats) stops at the response
+    // headers or includes downloading the body
+    #[serde(default)]
+    duration_measure: DurationMeasure,
+    // Query parameters (e.g. timestamps, cache busters) left out of each result's test_id
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    test_id_ignored_params: Vec<String>,
+    // Query parameters whose values are shown as *** in every output (still sent as-is)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    redact_params: Vec<String>,
+    // Headers whose values are shown as *** in every output, on top of the built-in list
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    redact_headers: Vec<String>,
+    // Endpoint every result is POSTed to as soon as it completes
+    #[serde(default)]
+    stream: Option<stream::StreamConfig>,
+    // Rate-limit headers captured into each result, with optional warnings and pausing
+    #[serde(default)]
+    rate_limit: rate_limit::RateLimitConfig,
+    // Token request sent once at startup; its bearer token goes on every request
+    #[serde(default)]
+    auth_request: Option<auth::AuthRequest>,
+    // The page environments under maintenance must return
+    #[serde(default)]
+    maintenance: maintenance::MaintenanceCheck,
+    // Entries with an unsupported URL scheme dropped under --lax-config, for --dry-run
+    #[serde(skip)]
+    lax_skipped: Vec<String>,
 }
 
 // Helper function to provide a default value for app_error_key_to_fail
@@ -51,17 +654,672 @@ fn default_app_error_key() -> String {
     "code".to_string()
 }
 
+/// Commands run after the report, configured in the `[hooks]` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct HooksConfig {
+    // Runs after every run
+    #[serde(default)]
+    on_complete: Option<String>,
+    // Runs only when the run exits with a non-zero code
+    #[serde(default)]
+    on_failure: Option<String>,
+    // When true, a failing or timed-out hook makes the run exit non-zero
+    #[serde(default)]
+    hook_failures_fail_run: bool,
+    // Hooks still running after this long are killed
+    #[serde(default = "default_hook_timeout_secs")]
+    timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            on_complete: None,
+            on_failure: None,
+            hook_failures_fail_run: false,
+            timeout_secs: default_hook_timeout_secs(),
+        }
+    }
+}
+
+fn default_hook_timeout_secs() -> u64 {
+    30
+}
+
+/// A single path to test, with optional request details and setup/teardown sub-requests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PathConfig {
+    path: String,
+    // Name other paths can refer to in `depends_on`, unique within the config
+    #[serde(default)]
+    name: Option<String>,
+    // Name of a path that must pass first, e.g. the request creating what this one fetches.
+    // The path waits for that result and is skipped when it failed or didn't run
+    #[serde(default)]
+    depends_on: Option<String>,
+    // HTTP method for the main request, defaults to GET
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    body: Option<String>,
+    // Headers for this path only, overriding environment and global headers
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    // Expected `Location` header of a redirect response. Setting this disables
+    // redirect-following for the path so the redirect itself can be inspected.
+    #[serde(default)]
+    expected_location: Option<LocationMatcher>,
+    // Exact status code the response must have, e.g. 304 for a conditional request
+    // sent with an `If-None-Match` header. Replaces the usual 2xx check.
+    #[serde(default)]
+    expected_status: Option<u16>,
+    // Smallest acceptable body size in bytes, to catch truncated responses
+    #[serde(default)]
+    min_response_bytes: Option<usize>,
+    // Arrays in the JSON body that must have at least / at most this many items
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    expect_json_array_min: Vec<JsonArrayMin>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    expect_json_array_max: Vec<JsonArrayMax>,
+    // Fields of the JSON body that must equal a value, keyed by a dotted path such as
+    // "data.status" or "items[0].id" (or a JSON pointer starting with '/')
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    json_equals: BTreeMap<String, serde_json::Value>,
+    // Fields of the JSON body that must be present and not null, as dotted paths or pointers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    require_json_keys: Vec<String>,
+    // Fails when the body doesn't parse as JSON, e.g. an HTML error page from a JSON endpoint
+    #[serde(default)]
+    require_valid_json: bool,
+    // Exempts the path from `app_error_code_to_fail`, e.g. a docs page listing error codes
+    #[serde(default)]
+    skip_app_error_check: bool,
+    // Expression deciding pass/fail from the response, e.g. 'status == 200 && json("/ok") == true'
+    #[serde(default)]
+    assert_expr: Option<String>,
+    // `assert_expr` parsed while the config loads, so responses don't re-parse it
+    #[serde(skip)]
+    parsed_expr: Option<expr::Expr>,
+    // Cookies the response must set through `Set-Cookie`, with optional attribute checks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    expected_cookies: Vec<cookies::ExpectedCookie>,
+    // Reads the body as a stream (e.g. server-sent events) for a bounded time instead of
+    // waiting for it to end
+    #[serde(default)]
+    streaming: Option<StreamingCheck>,
+    // Byte range to request instead of the whole body, e.g. "0-1023", "1000-" or "-500".
+    // The response must be a 206 with a `Content-Range` header
+    #[serde(default)]
+    range: Option<String>,
+    // Whether a server ignoring `range` and sending the full body with a 200 still passes
+    #[serde(default)]
+    allow_full_response: bool,
+    // Maximum allowed `Server-Timing` duration (ms) per metric name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    server_timing_max: HashMap<String, f64>,
+    // How much a failure of this path matters, used by `--fail-on critical`
+    #[serde(default)]
+    severity: Severity,
+    // Expected duration of this path; exceeding it by more than regression_pct is a regression
+    #[serde(default)]
+    baseline_duration_secs: Option<f64>,
+    // Allowed slowdown over baseline_duration_secs, in percent
+    #[serde(default)]
+    regression_pct: f64,
+    // Whether a latency regression fails the test or only warns
+    #[serde(default)]
+    on_regression: RegressionAction,
+    // Share of the environment's health score carried by this path
+    #[serde(default = "default_weight")]
+    weight: f64,
+    // Required when any request of the path isn't GET or HEAD. Such paths are skipped unless
+    // --allow-destructive is given or the environment sets allow_destructive
+    #[serde(default)]
+    destructive: bool,
+    // Request that must succeed before the main request is sent
+    #[serde(default)]
+    setup: Option<SubRequest>,
+    // Request that is always attempted after the main request (and after a failed setup)
+    #[serde(default)]
+    teardown: Option<SubRequest>,
+}
+
+/// What `duration_secs` measures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DurationMeasure {
+    /// Until the response headers arrive (time to first byte)
+    Ttfb,
+    /// Until the body has been read
+    #[default]
+    Total,
+}
+
+/// What happens when a path is slower than its `baseline_duration_secs` allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RegressionAction {
+    #[default]
+    Fail,
+    Warn,
+}
+
+/// How important a path is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    Critical,
+    #[default]
+    Normal,
+}
+
+/// Matches a redirect's `Location` header, either exactly (`"/login"`) or by regex
+/// (`{ regex = "^/login" }`). The regex is compiled while the config is parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "LocationMatcherSpec", into = "LocationMatcherSpec")]
+enum LocationMatcher {
+    Exact(String),
+    Regex(regex::Regex),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum LocationMatcherSpec {
+    Exact(String),
+    Regex { regex: String },
+}
+
+impl TryFrom<LocationMatcherSpec> for LocationMatcher {
+    type Error = String;
+
+    fn try_from(spec: LocationMatcherSpec) -> Result<Self, Self::Error> {
+        Ok(match spec {
+            LocationMatcherSpec::Exact(expected) => LocationMatcher::Exact(expected),
+            LocationMatcherSpec::Regex { regex } => LocationMatcher::Regex(
+                regex::Regex::new(&regex)
+                    .map_err(|e| format!("invalid expected_location regex: {}", e))?,
+            ),
+        })
+    }
+}
+
+impl From<LocationMatcher> for LocationMatcherSpec {
+    fn from(matcher: LocationMatcher) -> Self {
+        match matcher {
+            LocationMatcher::Exact(expected) => LocationMatcherSpec::Exact(expected),
+            LocationMatcher::Regex(regex) => LocationMatcherSpec::Regex {
+                regex: regex.as_str().to_string(),
+            },
+        }
+    }
+}
+
+impl PartialEq for LocationMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LocationMatcher::Exact(a), LocationMatcher::Exact(b)) => a == b,
+            (LocationMatcher::Regex(a), LocationMatcher::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl LocationMatcher {
+    fn matches(&self, location: &str) -> bool {
+        match self {
+            LocationMatcher::Exact(expected) => expected == location,
+            LocationMatcher::Regex(regex) => regex.is_match(location),
+        }
+    }
+}
+
+impl std::fmt::Display for LocationMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocationMatcher::Exact(expected) => write!(f, "'{}'", expected),
+            LocationMatcher::Regex(regex) => write!(f, "matching /{}/", regex),
+        }
+    }
+}
+
+/// A `forbidden_patterns` entry. The regex is compiled while the config is parsed, so an
+/// invalid one fails the load and every test reuses the compiled pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ForbiddenPatternSpec", into = "ForbiddenPatternSpec")]
+struct ForbiddenPattern {
+    name: String,
+    regex: regex::Regex,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ForbiddenPatternSpec {
+    name: String,
+    regex: String,
+}
+
+impl TryFrom<ForbiddenPatternSpec> for ForbiddenPattern {
+    type Error = String;
+
+    fn try_from(spec: ForbiddenPatternSpec) -> Result<Self, Self::Error> {
+        let regex = regex::Regex::new(&spec.regex)
+            .map_err(|e| format!("forbidden pattern '{}': invalid regex: {}", spec.name, e))?;
+        Ok(ForbiddenPattern {
+            name: spec.name,
+            regex,
+        })
+    }
+}
+
+impl From<ForbiddenPattern> for ForbiddenPatternSpec {
+    fn from(pattern: ForbiddenPattern) -> Self {
+        ForbiddenPatternSpec {
+            name: pattern.name,
+            regex: pattern.regex.as_str().to_string(),
+        }
+    }
+}
+
+impl PartialEq for ForbiddenPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+/// How a streaming response is read: for at most `max_secs`, stopping early at the first
+/// line matching `until`. Without `until` the test passes if the stream stays healthy. The
+/// regex is compiled while the config is parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "StreamingCheckSpec", into = "StreamingCheckSpec")]
+struct StreamingCheck {
+    max_secs: f64,
+    until: Option<regex::Regex>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StreamingCheckSpec {
+    max_secs: f64,
+    #[serde(default)]
+    until: Option<String>,
+}
+
+impl TryFrom<StreamingCheckSpec> for StreamingCheck {
+    type Error = String;
+
+    fn try_from(spec: StreamingCheckSpec) -> Result<Self, Self::Error> {
+        let until = spec
+            .until
+            .map(|until| regex::Regex::new(&until))
+            .transpose()
+            .map_err(|e| format!("invalid streaming until regex: {}", e))?;
+        Ok(StreamingCheck {
+            max_secs: spec.max_secs,
+            until,
+        })
+    }
+}
+
+impl From<StreamingCheck> for StreamingCheckSpec {
+    fn from(streaming: StreamingCheck) -> Self {
+        StreamingCheckSpec {
+            max_secs: streaming.max_secs,
+            until: streaming.until.map(|until| until.as_str().to_string()),
+        }
+    }
+}
+
+impl PartialEq for StreamingCheck {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_secs == other.max_secs
+            && self.until.as_ref().map(regex::Regex::as_str)
+                == other.until.as_ref().map(regex::Regex::as_str)
+    }
+}
+
+/// `expect_json_array_min`: the array at `pointer` must have at least `min` items.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct JsonArrayMin {
+    pointer: String,
+    min: usize,
+}
+
+/// `expect_json_array_max`: the array at `pointer` must have at most `max` items.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct JsonArrayMax {
+    pointer: String,
+    max: usize,
+}
+
+/// A setup or teardown request attached to a path.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SubRequest {
+    // Either an absolute URL or a path appended to the environment's baseurl
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+// Helper function to provide a default value for the request method
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+/// Paths can be written as plain strings (`"/health"`) or as tables (`{ path = "/health", ... }`).
+enum PathSpec {
+    Simple(String),
+    Detailed(Box<PathConfig>),
+}
+
+// Picks the variant by the value's type rather than `#[serde(untagged)]`, which would replace
+// a table's own error (e.g. an invalid regex) with "did not match any variant"
+impl<'de> Deserialize<'de> for PathSpec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct PathSpecVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PathSpecVisitor {
+            type Value = PathSpec;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a path string or a path table")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, path: &str) -> Result<PathSpec, E> {
+                Ok(PathSpec::Simple(path.to_string()))
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<PathSpec, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let deserializer = serde::de::value::MapAccessDeserializer::new(map);
+                PathConfig::deserialize(deserializer).map(|path| PathSpec::Detailed(Box::new(path)))
+            }
+        }
+
+        deserializer.deserialize_any(PathSpecVisitor)
+    }
+}
+
+/// Accepts either a single table or a list of tables.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(item) => vec![item],
+        OneOrMany::Many(items) => items,
+    })
+}
+
+fn deserialize_paths<'de, D>(deserializer: D) -> Result<Vec<PathConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let specs: Vec<PathSpec> = Vec::deserialize(deserializer)?;
+    Ok(specs
+        .into_iter()
+        .map(|spec| match spec {
+            PathSpec::Simple(path) => PathConfig::simple(path),
+            PathSpec::Detailed(config) => *config,
+        })
+        .collect())
+}
+
+impl PathConfig {
+    /// A path written as a plain string: a GET with every option at its default.
+    fn simple(path: String) -> Self {
+        PathConfig {
+            path,
+            name: None,
+            depends_on: None,
+            method: default_method(),
+            body: None,
+            headers: HashMap::new(),
+            expected_location: None,
+            expected_status: None,
+            min_response_bytes: None,
+            expect_json_array_min: Vec::new(),
+            expect_json_array_max: Vec::new(),
+            json_equals: BTreeMap::new(),
+            require_json_keys: Vec::new(),
+            require_valid_json: false,
+            skip_app_error_check: false,
+            assert_expr: None,
+            parsed_expr: None,
+            expected_cookies: Vec::new(),
+            streaming: None,
+            range: None,
+            allow_full_response: false,
+            server_timing_max: HashMap::new(),
+            severity: Severity::Normal,
+            baseline_duration_secs: None,
+            regression_pct: 0.0,
+            on_regression: RegressionAction::Fail,
+            weight: default_weight(),
+            destructive: false,
+            setup: None,
+            teardown: None,
+        }
+    }
+}
+
 /// Struct to parse the relevant part of the API response, focusing only on the message.
 #[derive(Debug, Deserialize)]
 struct ApiResponse {
     message: String,
 }
 
+/// The HTTP clients shared by all tests, one per redirect policy and HTTP/2 mode.
+#[derive(Clone)]
+struct HttpClients {
+    default: reqwest::Client,
+    // Used for paths that assert on a redirect instead of following it
+    no_redirect: reqwest::Client,
+    // The same two for environments with `http2_prior_knowledge`
+    h2c: reqwest::Client,
+    h2c_no_redirect: reqwest::Client,
+    // The `[auth_request]` token, once fetched
+    bearer: Option<std::sync::Arc<auth::BearerToken>>,
+}
+
+impl HttpClients {
+    fn new(mode: ConnectionMode) -> Result<Self, reqwest::Error> {
+        Self::build(mode, true)
+    }
+
+    /// Clients that ignore `HTTP(S)_PROXY`, for servers on this machine.
+    fn without_proxy(mode: ConnectionMode) -> Result<Self, reqwest::Error> {
+        Self::build(mode, false)
+    }
+
+    fn build(mode: ConnectionMode, use_proxy: bool) -> Result<Self, reqwest::Error> {
+        let timeout = std::time::Duration::from_secs(10);
+        let builder = || {
+            let mut builder = reqwest::Client::builder()
+                .timeout(timeout)
+                .connector_layer(connection::TrackConnectsLayer);
+            if !use_proxy {
+                builder = builder.no_proxy();
+            }
+            match mode {
+                ConnectionMode::Pooled => builder,
+                ConnectionMode::Fresh => builder.pool_max_idle_per_host(0),
+            }
+        };
+        // HTTP/2 has no `Connection` header, so h2c clients only skip the pool
+        let http1_builder = || match mode {
+            ConnectionMode::Pooled => builder(),
+            ConnectionMode::Fresh => {
+                let mut headers = reqwest::header::HeaderMap::new();
+                headers.insert(
+                    reqwest::header::CONNECTION,
+                    reqwest::header::HeaderValue::from_static("close"),
+                );
+                builder().default_headers(headers)
+            }
+        };
+        Ok(HttpClients {
+            default: http1_builder().build()?,
+            no_redirect: http1_builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?,
+            h2c: builder().http2_prior_knowledge().build()?,
+            h2c_no_redirect: builder()
+                .http2_prior_knowledge()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?,
+            bearer: None,
+        })
+    }
+
+    /// The client for one path of an environment.
+    fn for_path(&self, env: &Environment, path: &PathConfig) -> reqwest::Client {
+        let client = match (env.http2_prior_knowledge, path.expected_location.is_some()) {
+            (false, false) => &self.default,
+            (false, true) => &self.no_redirect,
+            (true, false) => &self.h2c,
+            (true, true) => &self.h2c_no_redirect,
+        };
+        client.clone()
+    }
+}
+
+/// Whether requests may reuse pooled connections, selected with `--no-keepalive`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ConnectionMode {
+    #[default]
+    Pooled,
+    Fresh,
+}
+
+impl ConnectionMode {
+    fn from_args(args: &Args) -> Self {
+        if args.no_keepalive {
+            ConnectionMode::Fresh
+        } else {
+            ConnectionMode::Pooled
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionMode::Pooled => write!(f, "pooled"),
+            ConnectionMode::Fresh => write!(f, "fresh"),
+        }
+    }
+}
+
+/// Parses a number of seconds to wait, e.g. for `--initial-delay`.
+fn parse_secs(value: &str) -> Result<f64, String> {
+    let secs = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number of seconds", value))?;
+    check_secs(secs)?;
+    Ok(secs)
+}
+
+// Waits are slept with `Duration`, which can't be negative, NaN or infinite
+fn check_secs(secs: f64) -> Result<(), String> {
+    std::time::Duration::try_from_secs_f64(secs)
+        .map(|_| ())
+        .map_err(|_| format!("expected a non-negative number of seconds, got {}", secs))
+}
+
+/// Parses a `require_http_version` value ("1.0", "1.1" or "2").
+fn parse_http_version(version: &str) -> Result<reqwest::Version, String> {
+    match version.trim().trim_start_matches("HTTP/") {
+        "1.0" => Ok(reqwest::Version::HTTP_10),
+        "1.1" => Ok(reqwest::Version::HTTP_11),
+        "2" | "2.0" => Ok(reqwest::Version::HTTP_2),
+        // No request is ever sent over HTTP/3, so requiring it would fail every test
+        "3" | "3.0" => Err(format!(
+            "require_http_version '{}' is not supported: requests are only sent over HTTP/1.x and HTTP/2",
+            version
+        )),
+        _ => Err(format!("unsupported require_http_version '{}'", version)),
+    }
+}
+
+// e.g. "HTTP/1.1" or "HTTP/2", as used in messages
+fn http_version_label(version: reqwest::Version) -> String {
+    match version {
+        reqwest::Version::HTTP_2 => "HTTP/2".to_string(),
+        reqwest::Version::HTTP_3 => "HTTP/3".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Why a test failed. Variants are declared from most to least severe, so sorting by
+/// this type puts "the service is unreachable" above "a check on the body failed".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FailureKind {
+    Dns,
+    Connection,
+    Timeout,
+    ServerError,
+    ClientError,
+    Setup,
+    Assertion,
+    Teardown,
+}
+
+/// What happened when a failure was re-requested by --reverify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ReverifyOutcome {
+    Confirmed,
+    RecoveredOnRetry,
+}
+
+impl std::fmt::Display for ReverifyOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReverifyOutcome::Confirmed => write!(f, "confirmed"),
+            ReverifyOutcome::RecoveredOnRetry => write!(f, "recovered on retry"),
+        }
+    }
+}
+
 /// Represents the result of a single URL test.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 struct UrlTestResult {
     environment_name: String,
     url: String,
+    // Stable id for matching this test across runs, see `test_id`
+    test_id: String,
+    // The configured path, used to line results up across environments
+    #[serde(skip)]
+    path: String,
+    // Position of the path in the config, so --reverify can re-run it
+    #[serde(skip)]
+    path_index: usize,
     // Fix for UnequalLengths: Removed #[serde(skip_serializing_if = "Option::is_none")]
     // This ensures all records have the same number of columns in CSV,
     // with None values appearing as empty fields.
@@ -69,195 +1327,4367 @@ struct UrlTestResult {
     response_body_preview: String,
     passed: bool,
     // Fix for UnequalLengths: Removed #[serde(skip_serializing_if = "Option::is_none")]
+    // Every entry of `failures`, joined with "; "
     error_message: Option<String>,
+    // Each check that failed, in the order they ran; JSON only
+    failures: Vec<String>,
+    // Problems that don't fail the test, e.g. a latency regression with on_regression = "warn"
+    warning: Option<String>,
     duration_secs: f64,
+    // Requests made for this test, including --retries
+    attempts: u32,
+    // True when an assertion failure was retried under --retry-on-body-assertion
+    retried_assertion: bool,
+    // Time spent reading the response body, whichever duration_measure is selected
+    download_secs: Option<f64>,
+    // Whether the test passed within `sla_ms`; empty when no SLA is configured
+    within_sla: Option<bool>,
+    // Median duration of the URL's recent passing results, and whether this one took more
+    // than --anomaly-threshold times it; empty when the URL has too little history
+    historical_median_secs: Option<f64>,
+    anomaly: Option<bool>,
     // Fix for UnequalLengths: Removed #[serde(skip_serializing_if = "Option::is_none")]
     state_param: Option<String>,
+    setup_error: Option<String>,
+    teardown_error: Option<String>,
+    failure_kind: Option<FailureKind>,
+    severity: Severity,
+    // The path's `weight` in the health score
+    #[serde(skip)]
+    weight: f64,
+    // True when the body was cut off by --max-body-read
+    body_truncated: bool,
+    // Bytes and complete lines read from a `streaming` response
+    streamed_bytes: Option<u64>,
+    streamed_lines: Option<u64>,
+    // Approximate bytes sent and received by the main request, headers included, summed over
+    // every attempt
+    request_bytes: Option<u64>,
+    response_bytes: Option<u64>,
+    // Size of the whole resource as advertised by the `Content-Range` of a `range` response
+    total_size_bytes: Option<u64>,
+    // Value of the response's `Age` header, in seconds
+    cache_age: Option<u64>,
+    // Value of the response's `X-Cache` or `CF-Cache-Status` header
+    cache_status: Option<String>,
+    // Negotiated protocol, e.g. "HTTP/1.1" or "HTTP/2.0"
+    http_version: Option<String>,
+    // IP and port of the peer that served the response, when the connection exposes it
+    remote_addr: Option<String>,
+    // Best effort: whether the request went over a pooled connection; empty in fresh mode
+    connection_reused: Option<bool>,
+    // The User-Agent picked by --rotate-user-agent
+    user_agent: Option<String>,
+    // Durations (ms) reported by the backend in the `Server-Timing` header, keyed by metric name
+    server_timing: BTreeMap<String, f64>,
+    // Values of the `annotate_from_headers` headers, keyed by annotation name; empty and
+    // missing headers are left out
+    annotations: BTreeMap<String, String>,
+    // Cookies set by the response, in header order; JSON only
+    cookies: Vec<cookies::SetCookie>,
+    // Rate-limit budget reported by the response, read from the `[rate_limit]` headers
+    rate_limit_limit: Option<u64>,
+    rate_limit_remaining: Option<u64>,
+    rate_limit_reset: Option<u64>,
+    // Consecutive failing watch iterations, set in watch mode only
+    #[serde(skip)]
+    failure_streak: Option<FailureStreak>,
+    // The environment's health_gate request rather than a configured path
+    #[serde(skip)]
+    health_gate: bool,
+    // Name of the path this one waited for (`depends_on`)
+    depends_on: Option<String>,
+    // Why the test didn't run when its `depends_on` path failed or didn't run, e.g.
+    // "dependency failed (create)". Such rows are written to the reports only and count as
+    // neither passed nor failed
+    skip_reason: Option<String>,
+    // Outcome of the --reverify retry, for failures only
+    reverified: Option<ReverifyOutcome>,
+    // Duration of the --reverify retry; duration_secs keeps the original measurement
+    reverify_duration_secs: Option<f64>,
+    // Fingerprint of the config that produced this result
+    config_hash: String,
+    // The run's --label values, joined with ", "
+    run_label: Option<String>,
+    // Whether connections were pooled or opened per request (--no-keepalive)
+    connection_mode: ConnectionMode,
+    // Simulated link speed the body was read at (--throttle-kbps); durations of throttled
+    // and unthrottled runs aren't comparable
+    throttle_kbps: Option<u32>,
+    // Headers the main request was sent with, shown (redacted) in verbose output
+    #[serde(skip)]
+    request_headers: reqwest::header::HeaderMap,
+    // Full response body, only kept for verbose output and --compare-envs
+    #[serde(skip)]
+    response_body: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+impl UrlTestResult {
+    /// A result for the test described by `spec`, with nothing recorded yet.
+    fn for_spec(spec: &TestSpec) -> Self {
+        let state_param = state_param(&spec.path.path);
+        UrlTestResult {
+            environment_name: spec.environment_name.clone(),
+            url: format!("{}{}", spec.base_url, spec.path.path),
+            test_id: String::new(),
+            path: spec.path.path.clone(),
+            path_index: spec.path_index,
+            status_code: None,
+            response_body_preview: String::new(),
+            passed: false,
+            error_message: None,
+            failures: Vec::new(),
+            warning: None,
+            duration_secs: 0.0,
+            attempts: 1,
+            retried_assertion: false,
+            download_secs: None,
+            within_sla: None,
+            historical_median_secs: None,
+            anomaly: None,
+            state_param,
+            setup_error: None,
+            teardown_error: None,
+            failure_kind: None,
+            severity: spec.path.severity,
+            weight: spec.path.weight,
+            body_truncated: false,
+            streamed_bytes: None,
+            streamed_lines: None,
+            request_bytes: None,
+            response_bytes: None,
+            total_size_bytes: None,
+            cache_age: None,
+            cache_status: None,
+            http_version: None,
+            remote_addr: None,
+            connection_reused: None,
+            user_agent: spec.user_agent.clone(),
+            server_timing: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            cookies: Vec::new(),
+            rate_limit_limit: None,
+            rate_limit_remaining: None,
+            rate_limit_reset: None,
+            failure_streak: None,
+            health_gate: false,
+            depends_on: spec.path.depends_on.clone(),
+            skip_reason: None,
+            reverified: None,
+            reverify_duration_secs: None,
+            config_hash: String::new(),
+            run_label: None,
+            connection_mode: spec.connection_mode,
+            throttle_kbps: spec.throttle_kbps,
+            request_headers: spec.headers.clone(),
+            response_body: None,
+        }
+    }
 
-    println!("Loading configuration from: {}", args.config);
-    let config_content = fs::read_to_string(&args.config)?;
-    let config: Config = toml::from_str(&config_content)?;
+    /// Records a failed check. `error_message` lists every failure so far and
+    /// `failure_kind` keeps the most severe one, by the declaration order of `FailureKind`.
+    fn fail(&mut self, kind: FailureKind, message: String) {
+        self.passed = false;
+        self.failures.push(message);
+        self.error_message = Some(self.failures.join("; "));
+        self.failure_kind = Some(self.failure_kind.map_or(kind, |worst| worst.min(kind)));
+    }
 
-    if config.environments.is_empty() {
-        println!("No environments found in the configuration file. Exiting.");
-        return Ok(());
+    /// Records a problem that doesn't fail the test, after any earlier ones.
+    fn add_warning(&mut self, message: String) {
+        self.warning = Some(match self.warning.take() {
+            Some(earlier) => format!("{}; {}", earlier, message),
+            None => message,
+        });
+    }
+}
+
+/// Everything collected by one pass over the configured environments.
+struct TestRun {
+    results: Vec<UrlTestResult>,
+    // Unix timestamp (seconds) of when the run started
+    started_at: u64,
+    duration: std::time::Duration,
+    config_hash: String,
+    // Environments left out because of their `allowed_hours`, with the reason
+    schedule_skipped: BTreeMap<String, String>,
+    // Environment -> paths that weren't run, e.g. a destructive path without permission
+    skipped_paths: BTreeMap<String, Vec<SkippedPath>>,
+    // Rows for the tests skipped because of their `depends_on` path; written to the
+    // reports, not counted as passed or failed
+    skipped_results: Vec<UrlTestResult>,
+    // Environments whose --short-circuit verdict was settled before all their paths ran
+    decided_early: BTreeMap<String, verdict::EarlyVerdict>,
+    // Why no test ran, when the run has no results
+    nothing_ran: Option<String>,
+    // Stopped by Ctrl+C, so only the tests that completed before it are in `results`
+    interrupted: bool,
+    usage: usage::ResourceUsage,
+}
+
+impl TestRun {
+    /// A run that didn't test anything, still reported so every output file gets written.
+    fn empty(config: &Config, args: &Args, reason: &str) -> TestRun {
+        TestRun {
+            results: Vec::new(),
+            started_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            duration: std::time::Duration::ZERO,
+            config_hash: config_fingerprint(config, args),
+            schedule_skipped: BTreeMap::new(),
+            skipped_paths: BTreeMap::new(),
+            skipped_results: Vec::new(),
+            decided_early: BTreeMap::new(),
+            nothing_ran: Some(reason.to_string()),
+            interrupted: false,
+            usage: usage::ResourceUsage::default(),
+        }
+    }
+}
+
+/// A path an environment left out of the run. The same path can be listed more than once,
+/// e.g. with different methods.
+#[derive(Debug, Clone, Serialize)]
+struct SkippedPath {
+    path: String,
+    method: String,
+    reason: String,
+}
+
+impl SkippedPath {
+    fn new(path: &PathConfig, redactor: &redact::Redactor, reason: String) -> Self {
+        SkippedPath {
+            path: redactor.redact(&path.path),
+            method: path.method.to_uppercase(),
+            reason,
+        }
+    }
+}
+
+/// Aggregate outcome of a run, handed to hooks as JSON on stdin and embedded in the JSON output.
+#[derive(Debug, Serialize)]
+struct RunSummary {
+    // The run's --label values
+    labels: Vec<String>,
+    total: usize,
+    passed: usize,
+    failed: usize,
+    duration_secs: f64,
+    config_hash: String,
+    report_path: Option<String>,
+    // Environment -> file written by --output-per-env, with its row count
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    env_reports: BTreeMap<String, csv_report::EnvReport>,
+    exit_code: u8,
+    // Stopped by Ctrl+C before every test ran
+    interrupted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sla: Option<SlaSummary>,
+    // Environment -> status code (or "timeout"/"dns_error"/"transport_error"/"setup_failed") -> count
+    status_codes: BTreeMap<String, BTreeMap<String, usize>>,
+    // Environment -> weighted share of passing tests, 0-100
+    health_scores: BTreeMap<String, f64>,
+    // Environment -> State value -> counts, for states whose paths both passed and failed
+    mixed_states: BTreeMap<String, BTreeMap<String, EnvironmentSummary>>,
+    // Durations of pooled and fresh runs aren't comparable
+    connection_mode: ConnectionMode,
+    // The --throttle-kbps rate, null when bodies were read at full speed
+    throttle_kbps: Option<u32>,
+    // Environment -> why it was skipped, e.g. outside its allowed_hours
+    skipped_environments: BTreeMap<String, String>,
+    // Environment -> paths that were skipped and why, e.g. destructive without permission
+    skipped_paths: BTreeMap<String, Vec<SkippedPath>>,
+    // Environment -> verdict settled early by --short-circuit, with how many paths didn't run
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    decided_early: BTreeMap<String, verdict::EarlyVerdict>,
+    // Requests sent, bytes downloaded and concurrency achieved by the runner
+    resource_usage: usage::ResourceUsage,
+}
+
+/// Finds State values with more than one path in an environment where some of those
+/// paths passed and some failed, which usually points at a partial outage.
+fn mixed_states(
+    results: &[UrlTestResult],
+) -> BTreeMap<String, BTreeMap<String, EnvironmentSummary>> {
+    let mut by_state: BTreeMap<String, BTreeMap<String, EnvironmentSummary>> = BTreeMap::new();
+    for res in results {
+        let Some(state) = &res.state_param else {
+            continue;
+        };
+        let counts = by_state
+            .entry(res.environment_name.clone())
+            .or_default()
+            .entry(state.clone())
+            .or_default();
+        counts.total += 1;
+        if res.passed {
+            counts.passed += 1;
+        } else {
+            counts.failed += 1;
+        }
+    }
+    for states in by_state.values_mut() {
+        states.retain(|_, counts| counts.total > 1 && counts.passed > 0 && counts.failed > 0);
+    }
+    by_state.retain(|_, states| !states.is_empty());
+    by_state
+}
+
+/// Value of the path's `State` query parameter, if it has one.
+fn state_param(path: &str) -> Option<String> {
+    let (_, rest) = path.split_once("State=")?;
+    let state = rest.split_once('&').map_or(rest, |(state, _)| state);
+    Some(state.to_string())
+}
+
+// Names of the `annotation_<name>` CSV columns, in column order
+fn annotation_names(config: &Config) -> Vec<String> {
+    config.annotate_from_headers.keys().cloned().collect()
+}
+
+// The state of results without a `State` parameter in `--state-summary-csv`
+const UNSPECIFIED_STATE: &str = "UNSPECIFIED";
+
+/// One row of `--state-summary-csv`.
+#[derive(Debug, Clone, Default)]
+struct StateRollup {
+    // Tested paths: passed + failed + recovered
+    total: usize,
+    passed: usize,
+    // Failures that count towards the exit code
+    failed: usize,
+    // Failures that passed when --reverify retried them
+    recovered: usize,
+    // Paths not tested, e.g. because of a failed health_gate
+    skipped: usize,
+    // Highest status code received
+    worst_status: Option<u16>,
+    max_duration_secs: Option<f64>,
+}
+
+/// Aggregates the results and skipped paths per environment and State, with paths without a
+/// State under `UNSPECIFIED`.
+fn state_rollups(
+    results: &[UrlTestResult],
+    skipped_paths: &BTreeMap<String, Vec<SkippedPath>>,
+) -> BTreeMap<(String, String), StateRollup> {
+    let key = |env_name: &str, state: Option<String>| {
+        (
+            env_name.to_string(),
+            state.unwrap_or_else(|| UNSPECIFIED_STATE.to_string()),
+        )
+    };
+    let mut rollups: BTreeMap<(String, String), StateRollup> = BTreeMap::new();
+    for res in results {
+        let rollup = rollups
+            .entry(key(&res.environment_name, res.state_param.clone()))
+            .or_default();
+        rollup.total += 1;
+        if res.passed {
+            rollup.passed += 1;
+        } else if counts_as_failure(res) {
+            rollup.failed += 1;
+        } else {
+            rollup.recovered += 1;
+        }
+        rollup.worst_status = rollup.worst_status.max(res.status_code);
+        rollup.max_duration_secs = Some(
+            rollup
+                .max_duration_secs
+                .map_or(res.duration_secs, |max| max.max(res.duration_secs)),
+        );
+    }
+    for (env_name, paths) in skipped_paths {
+        for skipped in paths {
+            rollups
+                .entry(key(env_name, state_param(&skipped.path)))
+                .or_default()
+                .skipped += 1;
+        }
+    }
+    rollups
+}
+
+/// Finds annotations with more than one value within an environment, e.g. two releases
+/// served during a partial rollout, with how many results saw each value.
+fn mixed_annotations(
+    results: &[UrlTestResult],
+) -> BTreeMap<String, BTreeMap<String, BTreeMap<String, usize>>> {
+    let mut by_env: BTreeMap<String, BTreeMap<String, BTreeMap<String, usize>>> = BTreeMap::new();
+    for res in results {
+        for (name, value) in &res.annotations {
+            *by_env
+                .entry(res.environment_name.clone())
+                .or_default()
+                .entry(name.clone())
+                .or_default()
+                .entry(value.clone())
+                .or_default() += 1;
+        }
+    }
+    for annotations in by_env.values_mut() {
+        annotations.retain(|_, values| values.len() > 1);
+    }
+    by_env.retain(|_, annotations| !annotations.is_empty());
+    by_env
+}
+
+/// Lists the annotations of `mixed_annotations`. Informational only, like the mixed states.
+fn print_mixed_annotations(mixed: &BTreeMap<String, BTreeMap<String, BTreeMap<String, usize>>>) {
+    if mixed.is_empty() {
+        return;
+    }
+    println!("\n--- Mixed Annotations ---");
+    for (env_name, annotations) in mixed {
+        for (name, values) in annotations {
+            let counts: Vec<String> = values
+                .iter()
+                .map(|(value, count)| {
+                    format!(
+                        "{} ({} request{})",
+                        value,
+                        count,
+                        if *count == 1 { "" } else { "s" }
+                    )
+                })
+                .collect();
+            println!(
+                "{} served {} {} values: {}",
+                env_name,
+                values.len(),
+                name,
+                counts.join(", ")
+            );
+        }
+    }
+}
+
+/// Lists the states of `mixed_states`. Informational only; it never affects the exit code.
+fn print_mixed_states(mixed: &BTreeMap<String, BTreeMap<String, EnvironmentSummary>>) {
+    if mixed.is_empty() {
+        return;
+    }
+    println!("\n--- Mixed Outcomes by State ---");
+    for (env_name, states) in mixed {
+        for (state, counts) in states {
+            println!(
+                "{: <10} | {: <20} | {} passed, {} failed",
+                truncate_string(env_name, 10),
+                truncate_string(state, 20),
+                counts.passed,
+                counts.failed
+            );
+        }
+    }
+}
+
+/// Scores each environment from 0 to 100 as the weighted fraction of its tests that
+/// passed. An environment whose paths all have weight 0 scores 100.
+fn health_scores(results: &[UrlTestResult]) -> BTreeMap<String, f64> {
+    let mut weights: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+    for res in results {
+        let (passed, total) = weights.entry(res.environment_name.clone()).or_default();
+        *total += res.weight;
+        if res.passed {
+            *passed += res.weight;
+        }
+    }
+    weights
+        .into_iter()
+        .map(|(env_name, (passed, total))| {
+            let score = if total > 0.0 {
+                passed * 100.0 / total
+            } else {
+                100.0
+            };
+            (env_name, score)
+        })
+        .collect()
+}
+
+// Most buckets of a latency histogram, and the length of its longest bar
+const HISTOGRAM_BUCKETS: usize = 10;
+const HISTOGRAM_BAR_WIDTH: usize = 40;
+
+/// Prints a histogram of response durations per environment. Bucket widths are rounded up to
+/// 1, 2 or 5 times a power of ten so boundaries read well, and start at a multiple of the
+/// width below the fastest response.
+fn print_latency_histograms(results: &[UrlTestResult]) {
+    println!("\n--- Latency Histogram ---");
+    for env_name in summarize_environments(results).keys() {
+        let durations_ms: Vec<f64> = results
+            .iter()
+            .filter(|res| &res.environment_name == env_name && res.status_code.is_some())
+            .map(|res| res.duration_secs * 1000.0)
+            .collect();
+        println!("{} ({} responses)", env_name, durations_ms.len());
+        let (Some(min), Some(max)) = (
+            durations_ms.iter().copied().reduce(f64::min),
+            durations_ms.iter().copied().reduce(f64::max),
+        ) else {
+            continue;
+        };
+
+        let bucket_count = |width: f64| {
+            let start = (min / width).floor() * width;
+            ((max - start) / width).floor() as usize + 1
+        };
+        let mut width = nice_bucket_width((max - min) / HISTOGRAM_BUCKETS as f64);
+        // Starting below the fastest response can take one bucket too many; the next step fits
+        while bucket_count(width) > HISTOGRAM_BUCKETS {
+            width = nice_bucket_width(width * 1.5);
+        }
+        let start = (min / width).floor() * width;
+        let buckets = bucket_count(width);
+        let mut counts = vec![0usize; buckets];
+        for duration in &durations_ms {
+            let index = ((duration - start) / width).floor() as usize;
+            counts[index.min(buckets - 1)] += 1;
+        }
+
+        let decimals = (-width.log10().floor()).max(0.0) as usize;
+        let label = |ms: f64| format!("{:.*}", decimals, ms);
+        let label_width = label(start + width * buckets as f64).len();
+        let largest = counts.iter().copied().max().unwrap_or(1).max(1);
+        for (index, count) in counts.iter().enumerate() {
+            let lower = start + width * index as f64;
+            // Every non-empty bucket shows at least one mark
+            let bar = (count * HISTOGRAM_BAR_WIDTH).div_ceil(largest);
+            println!(
+                "  {:>w$}-{:>w$}ms | {} {}",
+                label(lower),
+                label(lower + width),
+                "#".repeat(bar),
+                count,
+                w = label_width
+            );
+        }
+    }
+}
+
+// The smallest of 1, 2 or 5 times a power of ten that is at least `raw` (ms)
+fn nice_bucket_width(raw: f64) -> f64 {
+    if raw <= 0.0 || !raw.is_finite() {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|step| step * magnitude)
+        .find(|width| *width >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Prints one line per environment with its health score.
+fn print_health_scores(scores: &BTreeMap<String, f64>) {
+    println!("\n--- Health Score ---");
+    for (env_name, score) in scores {
+        println!("{: <10} | {:.1}/100", truncate_string(env_name, 10), score);
+    }
+}
+
+/// Tallies status codes per environment. Results without a response get a pseudo-bucket
+/// saying why there wasn't one.
+fn status_distribution(results: &[UrlTestResult]) -> BTreeMap<String, BTreeMap<String, usize>> {
+    let mut distribution: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for res in results {
+        let bucket = match (res.status_code, res.failure_kind) {
+            (Some(status), _) => status.to_string(),
+            (None, Some(FailureKind::Dns)) => "dns_error".to_string(),
+            (None, Some(FailureKind::Timeout)) => "timeout".to_string(),
+            (None, Some(FailureKind::Setup)) => "setup_failed".to_string(),
+            (None, _) => "transport_error".to_string(),
+        };
+        *distribution
+            .entry(res.environment_name.clone())
+            .or_default()
+            .entry(bucket)
+            .or_default() += 1;
+    }
+    distribution
+}
+
+/// Buckets of one environment, most frequent first.
+fn sorted_buckets(counts: &BTreeMap<String, usize>) -> Vec<(&str, usize)> {
+    let mut buckets: Vec<(&str, usize)> = counts
+        .iter()
+        .map(|(bucket, count)| (bucket.as_str(), *count))
+        .collect();
+    buckets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    buckets
+}
+
+/// Prints e.g. "prod: 187×200, 4×404, 9×503" for every environment.
+fn print_status_distribution(distribution: &BTreeMap<String, BTreeMap<String, usize>>) {
+    println!("\n--- Status Codes ---");
+    for (env_name, counts) in distribution {
+        let buckets: Vec<String> = sorted_buckets(counts)
+            .into_iter()
+            .map(|(bucket, count)| format!("{}×{}", count, bucket))
+            .collect();
+        println!("{}: {}", env_name, buckets.join(", "));
+    }
+}
+
+/// Share of results within `sla_ms`, overall and per environment.
+#[derive(Debug, Serialize)]
+struct SlaSummary {
+    sla_ms: f64,
+    target_pct: Option<f64>,
+    within_sla_pct: f64,
+    environments: BTreeMap<String, f64>,
+}
+
+fn summarize_sla(config: &Config, results: &[UrlTestResult]) -> Option<SlaSummary> {
+    let sla_ms = config.sla_ms?;
+    let pct = |results: &[&UrlTestResult]| {
+        let within = results
+            .iter()
+            .filter(|res| res.within_sla == Some(true))
+            .count();
+        if results.is_empty() {
+            100.0
+        } else {
+            within as f64 * 100.0 / results.len() as f64
+        }
+    };
+    let mut by_env: BTreeMap<String, Vec<&UrlTestResult>> = BTreeMap::new();
+    for res in results {
+        by_env
+            .entry(res.environment_name.clone())
+            .or_default()
+            .push(res);
+    }
+    Some(SlaSummary {
+        sla_ms,
+        target_pct: config.sla_target_pct,
+        within_sla_pct: pct(&results.iter().collect::<Vec<_>>()),
+        environments: by_env
+            .iter()
+            .map(|(name, results)| (name.clone(), pct(results)))
+            .collect(),
+    })
+}
+
+/// Nearest-rank percentile of already sorted values.
+fn percentile(sorted: &[f64], pct: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// "p50 0.012s | p90 0.150s | ..." over the durations of results that got a response.
+fn latency_line(results: &[&UrlTestResult]) -> String {
+    let mut durations: Vec<f64> = results
+        .iter()
+        .filter(|res| res.status_code.is_some())
+        .map(|res| res.duration_secs)
+        .collect();
+    durations.sort_by(f64::total_cmp);
+    if durations.is_empty() {
+        return "no responses".to_string();
+    }
+    [
+        ("p50", 50.0),
+        ("p90", 90.0),
+        ("p95", 95.0),
+        ("p99", 99.0),
+        ("max", 100.0),
+    ]
+    .iter()
+    .filter_map(|(label, pct)| {
+        percentile(&durations, *pct).map(|secs| format!("{} {:.3}s", label, secs))
+    })
+    .collect::<Vec<_>>()
+    .join(" | ")
+}
+
+// e.g. "pooled, 97 of 100 requests reused a connection"
+fn connection_line(results: &[UrlTestResult], args: &Args) -> String {
+    let mode = ConnectionMode::from_args(args);
+    if mode == ConnectionMode::Fresh {
+        return format!(
+            "{} (--no-keepalive), every request opened a connection",
+            mode
+        );
+    }
+    let known: Vec<bool> = results
+        .iter()
+        .filter_map(|res| res.connection_reused)
+        .collect();
+    let reused = known.iter().filter(|reused| **reused).count();
+    format!(
+        "{}, {} of {} requests reused a connection",
+        mode,
+        reused,
+        known.len()
+    )
+}
+
+fn print_resource_usage(usage: &usage::ResourceUsage) {
+    println!(
+        "Resource usage: {} requests ({:.1}/s) | {} downloaded | in flight: average {:.1}, peak {}",
+        usage.requests_sent,
+        usage.requests_per_sec,
+        usage::format_bytes(usage.bytes_downloaded),
+        usage.average_in_flight,
+        usage.peak_in_flight
+    );
+    println!(
+        "Transfer (approximate, headers included): {} sent, {} received",
+        usage::format_bytes(usage.bytes_sent),
+        usage::format_bytes(usage.bytes_received)
+    );
+}
+
+/// Prints counts, success rate and latency percentiles, overall and per environment.
+/// Latencies are left out under --stable-output and --no-duration.
+fn print_statistics(results: &[UrlTestResult], args: &Args) {
+    let show_latency = !(args.stable_output || args.no_duration);
+    let success_rate = |passed: usize, total: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            passed as f64 * 100.0 / total as f64
+        }
+    };
+    let passed = results.iter().filter(|res| res.passed).count();
+    println!("\n--- Statistics ---");
+    println!(
+        "Total: {} | Passed: {} | Failed: {} | Success rate: {:.1}%",
+        results.len(),
+        passed,
+        results.len() - passed,
+        success_rate(passed, results.len())
+    );
+    if show_latency {
+        println!(
+            "Latency: {}",
+            latency_line(&results.iter().collect::<Vec<_>>())
+        );
+    }
+    if !args.stable_output {
+        println!("Connections: {}", connection_line(results, args));
+    }
+    if let Some(kbps) = args.throttle_kbps {
+        println!(
+            "Throttle: response bodies read at {} kbps (--throttle-kbps)",
+            kbps
+        );
+    }
+    for (env_name, summary) in summarize_environments(results) {
+        let env_results: Vec<&UrlTestResult> = results
+            .iter()
+            .filter(|res| res.environment_name == env_name)
+            .collect();
+        let latency = if show_latency {
+            format!(" | {}", latency_line(&env_results))
+        } else {
+            String::new()
+        };
+        println!(
+            "{: <10} | {} passed, {} failed ({:.1}%){}",
+            truncate_string(&env_name, 10),
+            summary.passed,
+            summary.failed,
+            success_rate(summary.passed, summary.total),
+            latency
+        );
+    }
+}
+
+/// Prints one line per environment with its percentage of results within SLA.
+fn print_sla_summary(sla: &SlaSummary, args: &Args) {
+    match sla.target_pct {
+        Some(target) => println!("\n--- SLA ({}ms, target {}%) ---", sla.sla_ms, target),
+        None => println!("\n--- SLA ({}ms) ---", sla.sla_ms),
+    }
+    for (env_name, pct) in &sla.environments {
+        let below_target = sla.target_pct.is_some_and(|target| *pct < target);
+        let color = if below_target { COLOR_RED } else { "" };
+        println!(
+            "{: <10} | {} within SLA",
+            truncate_string(env_name, 10),
+            paint(&format!("{:.1}%", pct), color, args)
+        );
+    }
+}
+
+/// Pass/fail counts for one environment.
+#[derive(Debug, Clone, Default, Serialize)]
+struct EnvironmentSummary {
+    total: usize,
+    passed: usize,
+    failed: usize,
+}
+
+fn summarize_environments<'a>(
+    results: impl IntoIterator<Item = &'a UrlTestResult>,
+) -> BTreeMap<String, EnvironmentSummary> {
+    let mut summaries: BTreeMap<String, EnvironmentSummary> = BTreeMap::new();
+    for res in results {
+        let summary = summaries.entry(res.environment_name.clone()).or_default();
+        summary.total += 1;
+        if res.passed {
+            summary.passed += 1;
+        } else {
+            summary.failed += 1;
+        }
+    }
+    summaries
+}
+
+/// A row of a previously saved CSV report, as far as the baseline comparison needs it.
+#[derive(Debug, Deserialize)]
+struct BaselineRow {
+    environment_name: String,
+    url: String,
+    #[serde(default)]
+    test_id: Option<String>,
+    passed: bool,
+    #[serde(default)]
+    duration_secs: Option<f64>,
+    // Older reports were written before this column existed
+    #[serde(default)]
+    config_hash: Option<String>,
+    #[serde(default)]
+    run_label: Option<String>,
+    #[serde(default)]
+    connection_mode: Option<String>,
+    #[serde(default)]
+    throttle_kbps: Option<u32>,
+}
+
+/// The outcomes recorded in a `--baseline` report, keyed by test_id.
+struct Baseline {
+    path: String,
+    outcomes: HashMap<String, bool>,
+    // Last-known duration of each test, used by `--schedule longest-first`
+    durations: HashMap<String, f64>,
+    config_hashes: Vec<String>,
+    // Distinct run_label values of the report
+    labels: Vec<String>,
+    // Distinct connection_mode values of the report
+    connection_modes: Vec<String>,
+    // Distinct throttle_kbps values of the report, None for unthrottled rows
+    throttle_rates: Vec<Option<u32>>,
+}
+
+/// Differences between the current run and the baseline report.
+struct BaselineComparison {
+    regressions: Vec<(String, String)>,
+    fixed: Vec<(String, String)>,
+}
+
+/// Exit code of a run in which no test ran, unless --empty-ok is given.
+const EXIT_NOTHING_RAN: u8 = 3;
+
+/// The exit code of a run and the reason it was chosen.
+struct ExitDecision {
+    code: u8,
+    reason: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    match &args.command {
+        Some(Command::Envs { config, format }) => {
+            list_environments(&load_config(config, &[], false)?, *format)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::CheckAssertions {
+            config,
+            samples,
+            empty_ok,
+        }) => {
+            return Ok(
+                match check_assertions(&load_config(config, &[], false)?, samples)? {
+                    Some(true) => ExitCode::SUCCESS,
+                    Some(false) => ExitCode::FAILURE,
+                    None => {
+                        let decision = nothing_ran_decision("no path has a sample", *empty_ok);
+                        println!("\n{}", decision.reason);
+                        ExitCode::from(decision.code)
+                    }
+                },
+            );
+        }
+        Some(Command::Diff {
+            before,
+            after,
+            format,
+            duration_threshold_pct,
+        }) => {
+            let newly_failing =
+                report_diff::diff_reports(before, after, *format, *duration_threshold_pct)?;
+            return Ok(if newly_failing {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            });
+        }
+        Some(Command::SelfTest) => {
+            return Ok(if self_test::run().await? {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+        None => {}
+    }
+
+    if let Some(format) = args.print_schema {
+        let format = match format {
+            SchemaFormat::Auto if args.json.is_some() => SchemaFormat::Json,
+            SchemaFormat::Auto => SchemaFormat::Csv,
+            other => other,
+        };
+        let schema = if format == SchemaFormat::Json {
+            serde_json::to_string_pretty(&schema::json_schema())?
+        } else {
+            serde_json::to_string_pretty(&schema::csv_schema())?
+        };
+        println!("{}", schema);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // clap guarantees the config is present when no subcommand or --print-schema was given
+    let config_path = args.config.clone().unwrap_or_default();
+    // Every environment named on the command line must exist in the config
+    let required_envs: Vec<&str> = args
+        .env
+        .iter()
+        .chain(args.compare_envs.iter().flatten())
+        .map(String::as_str)
+        .collect();
+
+    // Keep --print-config output machine-readable
+    if args.print_config.is_none() {
+        println!("Loading configuration from: {}", config_path);
+    }
+    let mut config = load_config(&config_path, &required_envs, args.lax_config)?;
+    selected_environments(&config, &args)?;
+    // Read once: the file describes the change under test, not the current config
+    let changed_paths = match &args.changed_paths_file {
+        Some(path) => Some(read_changed_paths(path)?),
+        None => None,
+    };
+    // The first filter to leave no paths is the one reported
+    let mut no_paths_reason = "no paths found in the configuration file";
+    if let Some(changed) = &changed_paths {
+        if !config.paths.is_empty() {
+            no_paths_reason = "none of the changed paths are in the configuration file";
+        }
+        keep_changed_paths(&mut config, changed, true);
+        if args.print_config.is_none() {
+            println!("Testing only changed paths: {} matched", config.paths.len());
+        }
+    }
+    if let Some(filter) = &args.path_filter {
+        if !config.paths.is_empty() {
+            no_paths_reason = "no paths in the configuration file match --path-filter";
+        }
+        config.paths.retain(|p| filter.is_match(&p.path));
+        if args.print_config.is_none() {
+            println!(
+                "Testing only paths matching '{}': {} matched",
+                filter,
+                config.paths.len()
+            );
+        }
+    }
+
+    if let Some(format) = args.print_config {
+        print_effective_config(&config, selected_environments(&config, &args)?, format)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.dry_run || args.dry_run_requests {
+        dry_run(&config, &args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let nothing_to_run = if config.environments.is_empty() {
+        Some("no environments found in the configuration file")
+    } else if config.paths.is_empty() {
+        Some(no_paths_reason)
+    } else {
+        None
+    };
+    if let Some(reason) = nothing_to_run {
+        println!("Nothing to test: {}.", reason);
+        // Still written out, so callers find well-formed (empty) reports
+        let run = TestRun::empty(&config, &args, reason);
+        let decision = finish_run(run, &config, &args, None, None).await?;
+        return Ok(ExitCode::from(decision.code));
+    }
+
+    anomaly::validate_args(&args)?;
+    verdict::validate_args(&args)?;
+    let baseline = match &args.baseline {
+        Some(path) => Some(load_baseline(path, &config)?),
+        None => None,
+    };
+
+    if args.rotate_user_agent.is_some() && config.user_agents.is_empty() {
+        return Err("--rotate-user-agent needs a non-empty user_agents list in the config".into());
+    }
+
+    let mut clients = HttpClients::new(ConnectionMode::from_args(&args))?;
+    // Fetched once like the stream below; a watch iteration's reloaded `[auth_request]`
+    // doesn't replace it
+    if let Some(auth_request) = &config.auth_request {
+        println!(
+            "Fetching bearer token: {} {}",
+            auth_request.method, auth_request.url
+        );
+        let token = auth::BearerToken::fetch(auth_request, &clients.default)
+            .await
+            .map_err(|e| format!("auth_request failed: {}", e))?;
+        clients.bearer = Some(std::sync::Arc::new(token));
+    }
+    // Started once, so a watch iteration whose reloaded config changes `[stream]` keeps the
+    // endpoint it started with
+    let result_stream = match &config.stream {
+        Some(stream_config) => Some(stream::ResultStream::start(stream_config)?),
+        None => None,
+    };
+    let result_stream = result_stream.as_ref();
+
+    // One listener for the whole process, so a Ctrl+C at any point still gets the tests
+    // that completed reported
+    let interrupt = interrupt::Interrupt::listen();
+    if args.repeat_until_fail {
+        return repeat_until_fail(
+            &config,
+            &args,
+            &clients,
+            baseline.as_ref(),
+            result_stream,
+            &interrupt,
+        )
+        .await;
+    }
+
+    let Some(interval_secs) = args.watch else {
+        let run = run_tests(
+            &config,
+            &args,
+            &clients,
+            baseline.as_ref(),
+            result_stream,
+            true,
+            &interrupt,
+        )
+        .await?;
+        let decision = finish_run(run, &config, &args, baseline.as_ref(), result_stream).await?;
+        return Ok(ExitCode::from(decision.code));
+    };
+
+    // Outcome of every URL in the previous iteration, keyed by (environment, url)
+    let mut previous_outcomes: Option<HashMap<(String, String), bool>> = None;
+    // URLs failing in every iteration since their streak began, keyed by (environment, url)
+    let mut streaks: HashMap<(String, String), FailureStreak> = HashMap::new();
+    let mut iteration: u64 = 0;
+
+    loop {
+        iteration += 1;
+
+        // Re-read the config at the start of every iteration after the first, so edits
+        // made between iterations are picked up. A broken file keeps the last good config.
+        if iteration > 1 {
+            match load_config(&config_path, &required_envs, args.lax_config) {
+                Ok(mut new_config) => {
+                    if let Some(changed) = &changed_paths {
+                        keep_changed_paths(&mut new_config, changed, false);
+                    }
+                    if let Some(filter) = &args.path_filter {
+                        new_config.paths.retain(|p| filter.is_match(&p.path));
+                    }
+                    if new_config != config {
+                        println!(
+                            "\nconfig reloaded ({} paths, {} environments)",
+                            new_config.paths.len(),
+                            new_config.environments.len()
+                        );
+                        config = new_config;
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "\n{}WARNING: failed to reload '{}': {}. Continuing with the previous config.{}",
+                        COLOR_RED, config_path, e, COLOR_RESET
+                    );
+                }
+            }
+        }
+
+        println!("\n=== Watch iteration {} ===", iteration);
+        let mut run = run_tests(
+            &config,
+            &args,
+            &clients,
+            baseline.as_ref(),
+            result_stream,
+            iteration == 1,
+            &interrupt,
+        )
+        .await?;
+        // Paths an interrupted iteration didn't get to would read as removed or recovered
+        if !run.interrupted {
+            update_failure_streaks(&mut streaks, &mut run.results);
+
+            let current_outcomes: HashMap<(String, String), bool> = run
+                .results
+                .iter()
+                .map(|res| ((res.environment_name.clone(), res.url.clone()), res.passed))
+                .collect();
+            if let Some(previous) = &previous_outcomes {
+                print_watch_delta(previous, &current_outcomes, &args);
+            }
+            previous_outcomes = Some(current_outcomes);
+        }
+
+        finish_run(run, &config, &args, baseline.as_ref(), result_stream).await?;
+        if interrupt.is_set() {
+            break;
+        }
+
+        println!("\nNext iteration in {}s (Ctrl+C to stop)...", interval_secs);
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(interval_secs)) => {}
+            _ = interrupt.wait() => break,
+        }
+    }
+
+    print_ongoing_failures(&streaks, &args);
+    Ok(ExitCode::from(interrupt::EXIT_INTERRUPTED))
+}
+
+/// `--repeat-until-fail`: runs the tests again and again until a run has a failing result,
+/// then reports that run as a normal run would be. Earlier, passing runs aren't reported. An
+/// interrupted iteration is reported too, with what it completed.
+async fn repeat_until_fail(
+    config: &Config,
+    args: &Args,
+    clients: &HttpClients,
+    baseline: Option<&Baseline>,
+    result_stream: Option<&stream::ResultStream>,
+    interrupt: &interrupt::Interrupt,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    for iteration in 1..=args.max_iterations {
+        println!(
+            "\n=== Iteration {} of at most {} ===",
+            iteration, args.max_iterations
+        );
+        let run = run_tests(
+            config,
+            args,
+            clients,
+            baseline,
+            result_stream,
+            iteration == 1,
+            interrupt,
+        )
+        .await?;
+        if run.interrupted {
+            println!(
+                "\nStopped after {} passing iteration{}.",
+                iteration - 1,
+                if iteration == 2 { "" } else { "s" }
+            );
+            let decision = finish_run(run, config, args, baseline, result_stream).await?;
+            return Ok(ExitCode::from(decision.code));
+        }
+
+        let failed = run.results.iter().filter(|res| !res.passed).count();
+        if failed == 0 && run.nothing_ran.is_none() {
+            println!(
+                "Iteration {}: all {} tests passed",
+                iteration,
+                run.results.len()
+            );
+            continue;
+        }
+        if run.nothing_ran.is_none() {
+            println!(
+                "\n{}Iteration {}: {} of {} tests failed, stopping.{}",
+                COLOR_RED,
+                iteration,
+                failed,
+                run.results.len(),
+                COLOR_RESET
+            );
+        }
+        let decision = finish_run(run, config, args, baseline, result_stream).await?;
+        return Ok(ExitCode::from(decision.code));
+    }
+
+    println!(
+        "\nNo failures in {} iterations (--max-iterations).",
+        args.max_iterations
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// A run of consecutive failing watch iterations for one URL.
+#[derive(Debug, Clone, Copy)]
+struct FailureStreak {
+    // Unix timestamp (seconds) of the first failing iteration
+    since: u64,
+    iterations: u32,
+}
+
+/// Extends the streak of every failing result (starting one if needed), forgets URLs that
+/// passed or were not run, and attaches the streak to each failing result.
+fn update_failure_streaks(
+    streaks: &mut HashMap<(String, String), FailureStreak>,
+    results: &mut [UrlTestResult],
+) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut current = HashMap::new();
+    for res in results.iter_mut().filter(|res| !res.passed) {
+        let key = (res.environment_name.clone(), res.url.clone());
+        let mut streak = streaks.get(&key).copied().unwrap_or(FailureStreak {
+            since: now,
+            iterations: 0,
+        });
+        streak.iterations += 1;
+        res.failure_streak = Some(streak);
+        current.insert(key, streak);
+    }
+    *streaks = current;
+}
+
+/// Printed when watch mode is interrupted: the URLs still failing, longest streak first.
+fn print_ongoing_failures(streaks: &HashMap<(String, String), FailureStreak>, args: &Args) {
+    if streaks.is_empty() {
+        println!("\nStopped. No ongoing failures.");
+        return;
+    }
+    let mut ongoing: Vec<(&(String, String), &FailureStreak)> = streaks.iter().collect();
+    ongoing.sort_by(|a, b| {
+        b.1.iterations
+            .cmp(&a.1.iterations)
+            .then_with(|| a.0.cmp(b.0))
+    });
+    println!("\n--- Longest Ongoing Failures ---");
+    for ((env_name, url), streak) in ongoing {
+        println!(
+            "{: <10} | {} | {}",
+            truncate_string(env_name, 10),
+            describe_streak(streak, args),
+            url
+        );
+    }
+}
+
+// e.g. "12 iterations since 2024-06-12T14:03:05Z"
+fn describe_streak(streak: &FailureStreak, args: &Args) -> String {
+    format!(
+        "{} iteration{} since {}",
+        streak.iterations,
+        if streak.iterations == 1 { "" } else { "s" },
+        timestamp(args, streak.since)
+    )
+}
+
+/// Formats a Unix timestamp for any output, following `--time-format` and `--utc`/`--local`.
+fn timestamp(args: &Args, unix_secs: u64) -> String {
+    timezone::format_timestamp(unix_secs, &args.time_format, args.local)
+}
+
+/// Reads a `--changed-paths-file`: one path per line, with blank lines and `#` comments ignored.
+fn read_changed_paths(path: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read changed paths file '{}': {}", path, e))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Drops every path not listed in `changed`. With `warn_unknown`, listed paths that aren't
+/// in the config are reported, since they usually mean the file and config disagree.
+fn keep_changed_paths(config: &mut Config, changed: &HashSet<String>, warn_unknown: bool) {
+    if warn_unknown {
+        let mut unknown: Vec<&str> = changed
+            .iter()
+            .filter(|changed| !config.paths.iter().any(|p| &p.path == *changed))
+            .map(String::as_str)
+            .collect();
+        unknown.sort_unstable();
+        if !unknown.is_empty() {
+            eprintln!(
+                "WARNING: changed paths not found in the config: {}",
+                unknown.join(", ")
+            );
+        }
+    }
+    config.paths.retain(|p| changed.contains(&p.path));
+}
+
+/// Reads and parses the configuration file, checking that the requested environment exists.
+/// With `lax`, entries with an unsupported URL scheme are dropped with a warning instead of
+/// failing the load.
+fn load_config(
+    path: &str,
+    env_filter: &[&str],
+    lax: bool,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    parse_config(&fs::read_to_string(path)?, env_filter, lax)
+}
+
+/// Parses and checks a configuration, as `load_config` does for a file.
+fn parse_config(
+    config_content: &str,
+    env_filter: &[&str],
+    lax: bool,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config: Config = toml::from_str(config_content)?;
+    expand_baseurl_template(&mut config)?;
+    apply_default_bodies(&mut config)?;
+    // Before --lax-config drops any path, so a dropped dependency only skips its dependents
+    dependencies::validate(&config.paths)?;
+
+    for &env_name in env_filter {
+        if !config.environments.contains_key(env_name) {
+            eprintln!(
+                "Error: Environment '{}' not found in config.toml.",
+                env_name
+            );
+            return Err(format!("Environment '{}' not found.", env_name).into());
+        }
+    }
+    // Before --lax-config drops any environment, which then just leaves its groups
+    for (group, members) in &config.groups {
+        if members.is_empty() {
+            return Err(format!("Group '{}' has no environments", group).into());
+        }
+        if let Some(missing) = members
+            .iter()
+            .find(|name| !config.environments.contains_key(*name))
+        {
+            return Err(format!(
+                "Group '{}': environment '{}' not found in the config",
+                group, missing
+            )
+            .into());
+        }
+    }
+    schemes::check_config(&mut config, lax)?;
+    for &env_name in env_filter {
+        if !config.environments.contains_key(env_name) {
+            return Err(format!(
+                "Environment '{}' was dropped by --lax-config, see the warning above.",
+                env_name
+            )
+            .into());
+        }
+    }
+
+    if let Some(user_agent) = config
+        .user_agents
+        .iter()
+        .find(|agent| reqwest::header::HeaderValue::from_str(agent).is_err())
+    {
+        return Err(format!("invalid user_agents entry '{}'", user_agent).into());
+    }
+
+    if let Some(auth_request) = &config.auth_request {
+        auth::validate(auth_request)?;
+    }
+    config.maintenance.validate()?;
+    if let Some(interval) = config
+        .stream
+        .as_ref()
+        .and_then(|stream| stream.batch_interval_secs)
+    {
+        if std::time::Duration::try_from_secs_f64(interval).is_err() {
+            return Err(format!(
+                "[stream] batch_interval_secs must be a non-negative number of seconds, got {}",
+                interval
+            )
+            .into());
+        }
+    }
+    for (name, header) in &config.annotate_from_headers {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "invalid annotate_from_headers name '{}', use letters, digits, '_' and '-'",
+                name
+            )
+            .into());
+        }
+        reqwest::header::HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+            format!(
+                "invalid header name '{}' for annotation '{}' in annotate_from_headers",
+                header, name
+            )
+        })?;
+    }
+
+    if let Some(host) = config
+        .fail_on_redirect_to_hosts
+        .iter()
+        .find(|host| host.trim().is_empty() || host.contains(['/', ':']))
+    {
+        return Err(format!(
+            "invalid fail_on_redirect_to_hosts entry '{}', expected a host name such as 'www.example.com'",
+            host
+        )
+        .into());
+    }
+
+    for (env_name, env) in &config.environments {
+        if env.max_concurrency == Some(0) {
+            return Err(format!(
+                "Environment '{}': max_concurrency must be at least 1",
+                env_name
+            )
+            .into());
+        }
+        if let Some(delay) = env.startup_delay_secs {
+            check_secs(delay)
+                .map_err(|e| format!("Environment '{}': startup_delay_secs: {}", env_name, e))?;
+        }
+        if let Some(version) = &env.require_http_version {
+            parse_http_version(version)
+                .map_err(|e| format!("Environment '{}': {}", env_name, e))?;
+        }
+        if let Some(hours) = &env.allowed_hours {
+            HoursWindow::parse(hours).map_err(|e| format!("Environment '{}': {}", env_name, e))?;
+        }
+        if let Some(zone) = &env.timezone {
+            timezone::TimeZone::load(zone)
+                .map_err(|e| format!("Environment '{}': {}", env_name, e))?;
+        }
+    }
+
+    for path in config.paths.iter_mut() {
+        if let Some(source) = &path.assert_expr {
+            path.parsed_expr = Some(
+                expr::Expr::parse(source)
+                    .map_err(|e| format!("Path '{}': invalid assert_expr: {}", path.path, e))?,
+            );
+        }
+    }
+    for path in &config.paths {
+        let requests = std::iter::once(&path.method)
+            .chain(path.setup.iter().map(|sub| &sub.method))
+            .chain(path.teardown.iter().map(|sub| &sub.method));
+        for method in requests {
+            let method =
+                parse_method(method).map_err(|e| format!("Path '{}': {}", path.path, e))?;
+            if !path.destructive
+                && method != reqwest::Method::GET
+                && method != reqwest::Method::HEAD
+            {
+                return Err(format!(
+                    "Path '{}': {} requests can change data, mark the path destructive = true",
+                    path.path, method
+                )
+                .into());
+            }
+        }
+        for env in config.environments.values() {
+            path_headers(&default_request_headers(&config), &config, env, path)
+                .map_err(|e| format!("Path '{}': {}", path.path, e))?;
+        }
+        if !(path.weight >= 0.0 && path.weight.is_finite()) {
+            return Err(format!(
+                "Path '{}': weight must be a non-negative number, got {}",
+                path.path, path.weight
+            )
+            .into());
+        }
+        if path.baseline_duration_secs.is_some_and(|secs| secs <= 0.0) {
+            return Err(format!(
+                "Path '{}': baseline_duration_secs must be greater than 0",
+                path.path
+            )
+            .into());
+        }
+        if path.regression_pct < 0.0 {
+            return Err(
+                format!("Path '{}': regression_pct must not be negative", path.path).into(),
+            );
+        }
+        if let Some(streaming) = &path.streaming {
+            if !(streaming.max_secs > 0.0 && streaming.max_secs.is_finite()) {
+                return Err(format!(
+                    "Path '{}': streaming max_secs must be a positive number",
+                    path.path
+                )
+                .into());
+            }
+        }
+        if let Some(range) = &path.range {
+            range_length(range).map_err(|e| format!("Path '{}': {}", path.path, e))?;
+        }
+    }
+
+    Ok(config)
+}
+
+/// Merges `default_body` into the body of every path with a matching method. Paths without
+/// a body get the default as is; when both are JSON objects they are deep-merged, the path
+/// winning on conflicts; otherwise the path's body is kept.
+fn apply_default_bodies(config: &mut Config) -> Result<(), String> {
+    for method in config
+        .default_body
+        .keys()
+        .chain(config.default_headers.keys())
+    {
+        parse_method(method).map_err(|e| format!("default_body/default_headers: {}", e))?;
+    }
+    for path in config.paths.iter_mut() {
+        let Some(default) = for_method(&config.default_body, &path.method) else {
+            continue;
+        };
+        path.body = Some(match path.body.take() {
+            None => default.clone(),
+            Some(body) => merge_bodies(default, body),
+        });
+    }
+    Ok(())
+}
+
+/// The entry of a map keyed by HTTP method for `method`, compared case-insensitively.
+fn for_method<'a, T>(by_method: &'a HashMap<String, T>, method: &str) -> Option<&'a T> {
+    by_method
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(method))
+        .map(|(_, value)| value)
+}
+
+fn merge_bodies(default: &str, body: String) -> String {
+    match (
+        serde_json::from_str::<serde_json::Value>(default),
+        serde_json::from_str::<serde_json::Value>(&body),
+    ) {
+        (
+            Ok(mut merged @ serde_json::Value::Object(_)),
+            Ok(overlay @ serde_json::Value::Object(_)),
+        ) => {
+            merge_json(&mut merged, overlay);
+            merged.to_string()
+        }
+        _ => body,
+    }
+}
+
+// Objects are merged key by key; anything else, arrays and null included, replaces the base
+fn merge_json(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_json(base.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Adds the `environment_names` environments and fills in the base URL of every environment
+/// without one from `baseurl_template`. Explicit base URLs are left alone.
+fn expand_baseurl_template(config: &mut Config) -> Result<(), String> {
+    for name in &config.environment_names {
+        config.environments.entry(name.clone()).or_default();
+    }
+    for (name, env) in config.environments.iter_mut() {
+        if !env.baseurl.is_empty() {
+            continue;
+        }
+        match &config.baseurl_template {
+            Some(template) => env.baseurl = template.replace("{env}", name),
+            None => {
+                return Err(format!(
+                    "Environment '{}' has no baseurl and there is no baseurl_template",
+                    name
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The environments named by `--env` or `--group`, if either was given. Fails for a group
+/// that isn't in the config.
+fn selected_environments(config: &Config, args: &Args) -> Result<Option<Vec<String>>, String> {
+    if let Some(env_name) = &args.env {
+        return Ok(Some(vec![env_name.clone()]));
+    }
+    let Some(group) = &args.group else {
+        return Ok(None);
+    };
+    let members = config
+        .groups
+        .get(group)
+        .ok_or_else(|| format!("Group '{}' not found.", group))?;
+    // --lax-config may have dropped some of them
+    Ok(Some(
+        members
+            .iter()
+            .filter(|name| config.environments.contains_key(*name))
+            .cloned()
+            .collect(),
+    ))
+}
+
+/// Prints the config as it will actually run: defaults filled in, narrowed to the `--env`
+/// environment or `--group` environments when given, and with sensitive header values redacted.
+fn print_effective_config(
+    config: &Config,
+    env_filter: Option<Vec<String>>,
+    format: ConfigFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut effective = config.clone();
+    if let Some(env_names) = env_filter {
+        effective
+            .environments
+            .retain(|name, _| env_names.contains(name));
+    }
+
+    let redactor = redact::Redactor::new(config);
+    let redact = |headers: &mut HashMap<String, String>| {
+        for (name, value) in headers.iter_mut() {
+            if redactor.is_redacted_header(name) {
+                *value = "***".to_string();
+            }
+        }
+    };
+    redact(&mut effective.headers);
+    for env in effective.environments.values_mut() {
+        redact(&mut env.headers);
+    }
+    if let Some(stream) = &mut effective.stream {
+        redact(&mut stream.headers);
+    }
+    for path in &mut effective.paths {
+        redact(&mut path.headers);
+        path.path = redactor.redact(&path.path);
+    }
+
+    match format {
+        ConfigFormat::Toml => print!("{}", toml::to_string_pretty(&effective)?),
+        ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&effective)?),
+    }
+    Ok(())
+}
+
+/// Why a path marked `destructive` may not run against `env`, if it may not.
+fn destructive_skip_reason(
+    path: &PathConfig,
+    env: &Environment,
+    allow_destructive: bool,
+) -> Option<&'static str> {
+    (path.destructive && !allow_destructive && !env.allow_destructive).then_some(
+        "destructive path, needs --allow-destructive or allow_destructive = true on the environment",
+    )
+}
+
+/// `--dry-run` and `--dry-run-requests`: prints the requests of the selected environments
+/// (sorted by name, paths in config order) without sending any. Like a run, disabled
+/// environments are left out unless named, but allowed_hours are ignored since the run may
+/// happen at another time.
+fn dry_run(config: &Config, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let requested = |name: &String| {
+        args.env.as_ref() == Some(name)
+            || args
+                .compare_envs
+                .iter()
+                .flatten()
+                .any(|compared| compared == name)
+    };
+    let selected = selected_environments(config, args)?;
+    let mut env_names: Vec<&String> = config
+        .environments
+        .iter()
+        .filter(|(name, env)| match &selected {
+            // A group's disabled environments stay disabled, unlike one named with --env
+            Some(selected) => selected.contains(name) && (env.enabled || args.env.is_some()),
+            None => env.enabled || requested(name),
+        })
+        .map(|(name, _)| name)
+        .collect();
+    env_names.sort();
+
+    let clients = HttpClients::new(ConnectionMode::from_args(args))?;
+    let request_headers = default_request_headers(config);
+    let mut specs = Vec::new();
+    for env_name in env_names {
+        let env_data = &config.environments[env_name];
+        for path_index in 0..config.paths.len() {
+            specs.push(test_spec(
+                config,
+                args,
+                &clients,
+                env_name,
+                env_data,
+                path_index,
+                &request_headers,
+            )?);
+        }
+    }
+    // The token isn't fetched, so the requests show where it would go
+    if config.auth_request.is_some() {
+        let placeholder =
+            reqwest::header::HeaderValue::from_static("Bearer <token from auth_request>");
+        for spec in &mut specs {
+            if !spec.headers.contains_key(reqwest::header::AUTHORIZATION) {
+                spec.headers
+                    .insert(reqwest::header::AUTHORIZATION, placeholder.clone());
+            }
+        }
+    }
+    let skipped = specs
+        .iter()
+        .filter(|spec| {
+            let env_data = &config.environments[&spec.environment_name];
+            destructive_skip_reason(&spec.path, env_data, args.allow_destructive).is_some()
+        })
+        .count();
+    println!(
+        "Dry run: {} requests would be sent and {} skipped as destructive; none were sent.\n",
+        specs.len() - skipped,
+        skipped
+    );
+    let redactor = redact::Redactor::new(config);
+    if args.dry_run_requests {
+        let redactor = (!args.reveal_secrets).then_some(&redactor);
+        explain::print_curl_commands(&specs, config, redactor, args.allow_destructive);
+    } else {
+        explain::print_dry_run(
+            &specs,
+            config,
+            &redactor,
+            args.explain.then_some(args.explain_format),
+            args.allow_destructive,
+        );
+    }
+    if !config.lax_skipped.is_empty() {
+        println!("\nSkipped by --lax-config:");
+        for problem in &config.lax_skipped {
+            println!("  {}", problem);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the environments defined in the config as a table or as JSON.
+fn list_environments(
+    config: &Config,
+    format: ListFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize)]
+    struct EnvironmentListing<'a> {
+        name: &'a str,
+        baseurl: &'a str,
+        paths: usize,
+        enabled: bool,
+        groups: Vec<&'a str>,
+    }
+
+    let mut listings: Vec<EnvironmentListing> = config
+        .environments
+        .iter()
+        .map(|(name, env)| EnvironmentListing {
+            name,
+            baseurl: &env.baseurl,
+            paths: config.paths.len(),
+            enabled: env.enabled,
+            groups: config
+                .groups
+                .iter()
+                .filter(|(_, members)| members.contains(name))
+                .map(|(group, _)| group.as_str())
+                .collect(),
+        })
+        .collect();
+    listings.sort_by(|a, b| a.name.cmp(b.name));
+
+    match format {
+        ListFormat::Json => println!("{}", serde_json::to_string_pretty(&listings)?),
+        ListFormat::Table => {
+            println!(
+                "{: <20} | {: <50} | {: <6} | {: <7} | Groups",
+                "Env", "Base URL", "Paths", "Enabled"
+            );
+            println!("{}", "-".repeat(102));
+            for listing in &listings {
+                println!(
+                    "{: <20} | {: <50} | {: <6} | {: <7} | {}",
+                    listing.name,
+                    listing.baseurl,
+                    listing.paths,
+                    if listing.enabled { "yes" } else { "no" },
+                    listing.groups.join(", ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// File stem a path's sample body is looked up by: the path without its leading slash,
+/// with everything but letters, digits, `-` and `_` replaced by `_` ("root" for "/").
+fn sample_name(path: &str) -> String {
+    let name: String = path
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        "root".to_string()
+    } else {
+        name
+    }
+}
+
+/// The `check-assertions` subcommand: runs the body checks of every path against its
+/// sample file and lists samples and paths that couldn't be paired. Returns whether every
+/// checked sample passed, or None when no path had a sample.
+fn check_assertions(
+    config: &Config,
+    samples_dir: &str,
+) -> Result<Option<bool>, Box<dyn std::error::Error>> {
+    let mut samples: BTreeMap<String, std::path::PathBuf> = BTreeMap::new();
+    let entries = fs::read_dir(samples_dir)
+        .map_err(|e| format!("Failed to read samples directory '{}': {}", samples_dir, e))?;
+    for entry in entries {
+        let path = entry?.path();
+        if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+            if path.is_file() {
+                samples.insert(stem.to_string(), path.clone());
+            }
+        }
+    }
+
+    println!("Checking assertions against samples in {}", samples_dir);
+    println!("{: <6} | {: <40} | Details", "Result", "Path");
+    println!("{}", "-".repeat(84));
+    let mut all_passed = true;
+    let mut used = Vec::new();
+    let mut without_sample = Vec::new();
+    for path in &config.paths {
+        let name = sample_name(&path.path);
+        let Some(sample_path) = samples.get(&name) else {
+            without_sample.push(path.path.as_str());
+            continue;
+        };
+        used.push(name);
+        let body = fs::read_to_string(sample_path)?;
+        let failures: Vec<String> = check_body(
+            &body,
+            &config.app_error_key_to_fail,
+            config
+                .app_error_code_to_fail
+                .as_deref()
+                .filter(|_| !path.skip_app_error_check),
+            &config.soft_error_markers,
+        )
+        .into_iter()
+        .chain(check_path_body(&body, body.len() as u64, path))
+        .chain(check_forbidden(&body, &config.forbidden_patterns))
+        .collect();
+        let (label, details) = if failures.is_empty() {
+            (
+                format!("{}PASS{}  ", COLOR_GREEN, COLOR_RESET),
+                sample_path.display().to_string(),
+            )
+        } else {
+            all_passed = false;
+            (
+                format!("{}FAIL{}  ", COLOR_RED, COLOR_RESET),
+                failures.join("; "),
+            )
+        };
+        println!(
+            "{} | {: <40} | {}",
+            label,
+            truncate_string(&path.path, 40),
+            details
+        );
+    }
+
+    if !without_sample.is_empty() {
+        println!("\nPaths without a sample:");
+        for path in &without_sample {
+            println!("  {} (expected {}.*)", path, sample_name(path));
+        }
+    }
+    let unmatched: Vec<&std::path::PathBuf> = samples
+        .iter()
+        .filter(|(name, _)| !used.contains(name))
+        .map(|(_, path)| path)
+        .collect();
+    if !unmatched.is_empty() {
+        println!("\nSample files matching no path:");
+        for path in unmatched {
+            println!("  {}", path.display());
+        }
+    }
+    Ok((!used.is_empty()).then_some(all_passed))
+}
+
+/// Reads a CSV report written by a previous run. Reports from before the `test_id` column
+/// existed get ids derived with the current config's ignored params.
+fn load_baseline(path: &str, config: &Config) -> Result<Baseline, Box<dyn std::error::Error>> {
+    let mut reader = csv::Reader::from_path(path)
+        .map_err(|e| format!("Failed to open baseline '{}': {}", path, e))?;
+    let mut outcomes = HashMap::new();
+    let mut durations = HashMap::new();
+    let mut config_hashes: Vec<String> = Vec::new();
+    let mut labels: Vec<String> = Vec::new();
+    let mut connection_modes: Vec<String> = Vec::new();
+    let mut throttle_rates: Vec<Option<u32>> = Vec::new();
+    for row in reader.deserialize() {
+        let row: BaselineRow = row.map_err(|e| format!("Invalid baseline '{}': {}", path, e))?;
+        if let Some(hash) = row.config_hash.filter(|hash| !hash.is_empty()) {
+            if !config_hashes.contains(&hash) {
+                config_hashes.push(hash);
+            }
+        }
+        if let Some(label) = row.run_label.filter(|label| !label.is_empty()) {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+        if let Some(mode) = row.connection_mode.filter(|mode| !mode.is_empty()) {
+            if !connection_modes.contains(&mode) {
+                connection_modes.push(mode);
+            }
+        }
+        if !throttle_rates.contains(&row.throttle_kbps) {
+            throttle_rates.push(row.throttle_kbps);
+        }
+        let key = row.test_id.filter(|id| !id.is_empty()).unwrap_or_else(|| {
+            test_id(
+                &row.environment_name,
+                &row.url,
+                &config.test_id_ignored_params,
+            )
+        });
+        if let Some(duration) = row.duration_secs {
+            durations.insert(key.clone(), duration);
+        }
+        outcomes.insert(key, row.passed);
+    }
+    Ok(Baseline {
+        path: path.to_string(),
+        outcomes,
+        durations,
+        config_hashes,
+        labels,
+        connection_modes,
+        throttle_rates,
+    })
+}
+
+/// Runs every configured path against the selected environments and collects the results.
+/// Each result is streamed as soon as it completes when a stream is configured.
+/// Startup delays only apply on the first run, not on later watch iterations. Once
+/// `interrupt` fires nothing more is dispatched, and the results so far make up the run.
+async fn run_tests(
+    config: &Config,
+    args: &Args,
+    clients: &HttpClients,
+    baseline: Option<&Baseline>,
+    result_stream: Option<&stream::ResultStream>,
+    first_run: bool,
+    interrupt: &interrupt::Interrupt,
+) -> Result<TestRun, Box<dyn std::error::Error>> {
+    let mut all_results: Vec<UrlTestResult> = Vec::new();
+    let config_hash = config_fingerprint(config, args);
+    let redactor = redact::Redactor::new(config);
+    let run_label = (!args.label.is_empty()).then(|| args.label.join(", "));
+
+    let total_test_start_time = Instant::now();
+    let usage_start = usage::Counters::now();
+    let mut run_in_flight = concurrency::InFlightStats::default();
+    let started_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    let environments_to_run: HashMap<String, Environment> = if let Some(env_name) = &args.env {
+        let mut filtered_envs = HashMap::new();
+        if let Some(env_data) = config.environments.get(env_name) {
+            filtered_envs.insert(env_name.clone(), env_data.clone());
+            println!("\nRunning tests for specific environment: {}", env_name);
+        } else {
+            return Err(format!("Environment '{}' not found.", env_name).into());
+        }
+        filtered_envs
+    } else if let Some(group) = &args.group {
+        let members = selected_environments(config, args)?.unwrap_or_default();
+        println!(
+            "\nRunning tests for group '{}': {}",
+            group,
+            members.join(", ")
+        );
+        let (enabled, disabled): (Vec<&String>, Vec<&String>) = members
+            .iter()
+            .partition(|name| config.environments[*name].enabled);
+        if !disabled.is_empty() {
+            println!(
+                "Skipping disabled environments: {} (use --env to run one)",
+                disabled
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        enabled
+            .into_iter()
+            .map(|name| (name.clone(), config.environments[name].clone()))
+            .collect()
+    } else {
+        println!("\nRunning tests for ALL environments found in config.");
+        // Environments named in --compare-envs count as explicitly requested
+        let requested = |name: &String| {
+            args.compare_envs
+                .iter()
+                .flatten()
+                .any(|compared| compared == name)
+        };
+        let mut skipped: Vec<&str> = config
+            .environments
+            .iter()
+            .filter(|(name, env)| !env.enabled && !requested(name))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !skipped.is_empty() {
+            skipped.sort();
+            println!(
+                "Skipping disabled environments: {} (use --env to run one)",
+                skipped.join(", ")
+            );
+        }
+        config
+            .environments
+            .iter()
+            .filter(|(name, env)| env.enabled || requested(name))
+            .map(|(name, env)| (name.clone(), env.clone()))
+            .collect()
+    };
+
+    // Environments outside their allowed hours are neither passed nor failed, just left out
+    let mut schedule_skipped = BTreeMap::new();
+    let environments_to_run: HashMap<String, Environment> = if args.ignore_schedule {
+        environments_to_run
+    } else {
+        let mut allowed = HashMap::new();
+        for (env_name, env_data) in environments_to_run {
+            match outside_allowed_hours(&env_data, started_at)? {
+                Some(reason) => {
+                    println!("Skipping environment '{}': {}", env_name, reason);
+                    schedule_skipped.insert(env_name, reason);
+                }
+                None => {
+                    allowed.insert(env_name, env_data);
+                }
+            }
+        }
+        allowed
+    };
+
+    if !config.respect_caches && !args.stats_only {
+        println!(
+            "Note: requests are sent with 'Cache-Control: no-cache' so caches revalidate with the origin (set respect_caches = true to disable)."
+        );
+    }
+    let request_headers = default_request_headers(config);
+    let mut user_agents = UserAgentPool::new(config, args.rotate_user_agent);
+
+    // Progress messages (and the banners, which follow HashMap order) are left out of
+    // --stable-output so two runs only differ where outcomes do, and of --stats-only
+    let progress = !(args.stable_output || args.stats_only);
+
+    // Redacts, completes and streams every result the moment it arrives
+    let mut finish_result = |result: &mut UrlTestResult| {
+        finalize_result(
+            result,
+            config,
+            &config_hash,
+            run_label.as_deref(),
+            &redactor,
+        );
+        if let Some(result_stream) = result_stream {
+            result_stream.send_result(result);
+        }
+    };
+    let mut skipped_paths: BTreeMap<String, Vec<SkippedPath>> = BTreeMap::new();
+    let mut skipped_results = Vec::new();
+    let mut decided_early = BTreeMap::new();
+    let mut queues: Vec<EnvQueue> = Vec::new();
+    for (env_name, env_data) in environments_to_run {
+        if interrupt.is_set() {
+            break;
+        }
+        let total_paths_for_env = config.paths.len();
+        let concurrency = env_data
+            .max_concurrency
+            .or(args.concurrency)
+            .unwrap_or(total_paths_for_env);
+        if progress {
+            println!(
+                "\n--- Testing Environment: {} (Base URL: {}, concurrency {}) ---",
+                env_name, env_data.baseurl, concurrency
+            );
+        }
+
+        let startup_delay = env_data.startup_delay_secs.or(args.initial_delay);
+        if let Some(delay) = startup_delay.filter(|delay| *delay > 0.0 && first_run) {
+            if progress {
+                println!("Waiting {}s before testing '{}'...", delay, env_name);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs_f64(delay)) => {}
+                _ = interrupt.wait() => break,
+            }
+        }
+
+        // Every test would fail the same way if the host doesn't resolve, so find out once
+        let mut dns_failure = None;
+        let mut resolved_addr = None;
+        if !args.no_dns_precheck {
+            if let Some(precheck) = precheck_dns(&env_data.baseurl).await {
+                if progress {
+                    println!(
+                        "DNS precheck for '{}': {} {} in {:.0?}",
+                        env_name,
+                        precheck.host,
+                        if precheck.error.is_none() {
+                            "resolved"
+                        } else {
+                            "failed"
+                        },
+                        precheck.elapsed
+                    );
+                }
+                resolved_addr = precheck.addr;
+                dns_failure = precheck.error.map(|cause| {
+                    format!(
+                        "DNS resolution failed for host {}: {}",
+                        precheck.host, cause
+                    )
+                });
+            }
+        }
+
+        // One request tells whether the environment is up before hundreds fail the same way
+        let mut gate_results = Vec::new();
+        let mut gate_failed = false;
+        if let Some(gate) = env_data
+            .health_gate
+            .as_deref()
+            .filter(|_| dns_failure.is_none())
+        {
+            let spec = health_gate_spec(
+                config,
+                args,
+                clients,
+                &env_name,
+                &env_data,
+                gate,
+                &request_headers,
+            )?
+            .with_user_agent(user_agents.pick())?;
+            let mut result = execute_with_retries(spec).await;
+            result.health_gate = true;
+            finish_result(&mut result);
+            gate_failed = !result.passed;
+            if gate_failed {
+                let line = format!(
+                    "Health gate {} failed for '{}': {}",
+                    result.path,
+                    env_name,
+                    result.error_message.as_deref().unwrap_or("unknown error")
+                );
+                println!("{}", paint(&line, COLOR_RED, args));
+            } else if progress {
+                println!("Health gate {} passed for '{}'.", result.path, env_name);
+            }
+            gate_results.push(result);
+        }
+        let skip_for_gate = gate_failed && !args.ignore_health_gate;
+
+        if args.warmup > 0 && dns_failure.is_none() && !skip_for_gate {
+            // Warmup requests are GETs and HEADs to one host, so the read and per-host limits
+            // apply as well as the environment's own
+            let warmup_concurrency = [Some(concurrency), args.read_concurrency, args.max_per_host]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(concurrency);
+            let sent = warm_up(
+                clients,
+                config,
+                &env_data,
+                args.warmup,
+                warmup_concurrency,
+                &request_headers,
+            )
+            .await;
+            if progress {
+                println!(
+                    "Warmup: sent {} throwaway requests to '{}' ({} per path), results discarded.",
+                    sent, env_name, args.warmup
+                );
+            }
+        }
+
+        if progress {
+            println!("\nInitiating requests for environment '{}'...", env_name);
+        }
+
+        let order = dispatch_order(args.schedule, config, &env_name, &env_data, baseline);
+        if args.verbose && args.schedule != Schedule::Fifo {
+            let redactor = redact::Redactor::new(config);
+            let paths: Vec<String> = order
+                .iter()
+                .map(|&index| redactor.redact(&config.paths[index].path))
+                .collect();
+            println!("Dispatch order: {}", paths.join(", "));
+        }
+
+        let mut pending = Vec::new();
+        for path_index in order {
+            let path = &config.paths[path_index];
+            let skip_reason = if skip_for_gate {
+                Some("skipped: health gate failed")
+            } else {
+                destructive_skip_reason(path, &env_data, args.allow_destructive)
+            };
+            if let Some(reason) = skip_reason {
+                if progress && !skip_for_gate {
+                    println!("Skipping '{}' on '{}': {}", path.path, env_name, reason);
+                }
+                skipped_paths
+                    .entry(env_name.clone())
+                    .or_default()
+                    .push(SkippedPath::new(path, &redactor, reason.to_string()));
+                continue;
+            }
+            pending.push(
+                test_spec(
+                    config,
+                    args,
+                    clients,
+                    &env_name,
+                    &env_data,
+                    path_index,
+                    &request_headers,
+                )?
+                .with_user_agent(user_agents.pick())?,
+            );
+        }
+        if skip_for_gate {
+            println!(
+                "Skipping {} paths of '{}' (use --ignore-health-gate to test them anyway).",
+                skipped_paths.get(&env_name).map_or(0, Vec::len),
+                env_name
+            );
+        }
+        let mut precheck_results = gate_results;
+        if let Some(message) = &dns_failure {
+            println!(
+                "{}Failing {} tests of '{}' without sending requests: {}{}",
+                COLOR_RED,
+                pending.len(),
+                env_name,
+                message,
+                COLOR_RESET
+            );
+            for spec in pending.drain(..) {
+                let mut result = UrlTestResult::for_spec(&spec);
+                result.attempts = 0;
+                result.fail(FailureKind::Dns, message.clone());
+                finish_result(&mut result);
+                precheck_results.push(result);
+            }
+        } else if progress {
+            println!(
+                "Waiting for {} responses from '{}'...",
+                pending.len(),
+                env_name
+            );
+        }
+        let verdict = args.short_circuit.then(|| {
+            verdict::VerdictTracker::new(
+                args.fail_on,
+                pending.iter().map(|spec| spec.path.severity),
+            )
+        });
+        let waiting = dependencies::Waiting::hold_back(&mut pending);
+        let queue = EnvQueue {
+            host: concurrency::host_key(&env_data.baseurl, resolved_addr),
+            env_name,
+            pending: pending.into(),
+            waiting,
+            verdict,
+            limit: concurrency::ConcurrencyLimit::new(
+                concurrency,
+                args.adaptive_concurrency
+                    .then_some(args.adaptive_error_threshold / 100.0),
+            ),
+            in_flight: 0,
+            usage: concurrency::InFlightStats::default(),
+            paused_until: None,
+            results: precheck_results,
+        };
+        if args.parallel_envs {
+            queues.push(queue);
+        } else {
+            let drained = dispatch_queues(
+                vec![queue],
+                None,
+                concurrency::MethodLimits::new(args.read_concurrency, args.write_concurrency),
+                concurrency::HostLimits::new(args.max_per_host),
+                &config.rate_limit,
+                &mut run_in_flight,
+                &mut finish_result,
+                interrupt,
+            )
+            .await?;
+            all_results.extend(collect_queue_results(
+                drained.into_iter(),
+                args,
+                progress,
+                &redactor,
+                &mut skipped_paths,
+                &mut skipped_results,
+                &mut decided_early,
+            ));
+        }
+    }
+    // With --parallel-envs, every environment was only queued so far
+    if !queues.is_empty() {
+        if progress {
+            println!("\nDispatching {} environments in parallel...", queues.len());
+        }
+        let queues = dispatch_queues(
+            queues,
+            args.total_concurrency,
+            concurrency::MethodLimits::new(args.read_concurrency, args.write_concurrency),
+            concurrency::HostLimits::new(args.max_per_host),
+            &config.rate_limit,
+            &mut run_in_flight,
+            &mut finish_result,
+            interrupt,
+        )
+        .await?;
+        all_results.extend(collect_queue_results(
+            queues.into_iter(),
+            args,
+            progress,
+            &redactor,
+            &mut skipped_paths,
+            &mut skipped_results,
+            &mut decided_early,
+        ));
+    }
+
+    let total_test_end_time = Instant::now();
+    let total_duration = total_test_end_time.duration_since(total_test_start_time);
+    if progress {
+        println!("\nTotal Test Duration: {:.2?}", total_duration);
+    }
+
+    if args.reverify && !interrupt.is_set() {
+        reverify_failures(&mut all_results, config, args, clients, &request_headers).await?;
+    }
+    for res in skipped_results.iter_mut() {
+        finalize_result(res, config, &config_hash, run_label.as_deref(), &redactor);
+        // Not run, so neither within nor outside the SLA
+        res.within_sla = None;
+    }
+
+    println!("Config Hash: {}", config_hash);
+
+    let nothing_ran = all_results.is_empty().then(|| {
+        if interrupt.is_set() {
+            "interrupted before any test completed"
+        } else if config.paths.is_empty() {
+            "no paths left in the configuration file"
+        } else if !skipped_paths.is_empty() {
+            "every path was skipped, see Skipped Paths"
+        } else if !schedule_skipped.is_empty() {
+            "every selected environment is outside its allowed_hours"
+        } else {
+            "every environment is disabled"
+        }
+        .to_string()
+    });
+    Ok(TestRun {
+        results: all_results,
+        started_at,
+        duration: total_duration,
+        config_hash,
+        schedule_skipped,
+        skipped_paths,
+        skipped_results,
+        decided_early,
+        nothing_ran,
+        interrupted: interrupt.is_set(),
+        usage: usage::ResourceUsage::since(
+            usage_start,
+            &run_in_flight,
+            total_duration.as_secs_f64(),
+        ),
+    })
+}
+
+/// One environment's tests waiting to be dispatched, and what came back so far.
+struct EnvQueue {
+    env_name: String,
+    // Address or host name of the base URL, for --max-per-host
+    host: String,
+    pending: VecDeque<TestSpec>,
+    // Tests whose `depends_on` path has no result yet
+    waiting: dependencies::Waiting,
+    // Set with --short-circuit; once decided, nothing more is dispatched
+    verdict: Option<verdict::VerdictTracker>,
+    limit: concurrency::ConcurrencyLimit,
+    in_flight: usize,
+    usage: concurrency::InFlightStats,
+    // Set when the rate limit ran out and `pause_when_exhausted` is on
+    paused_until: Option<tokio::time::Instant>,
+    results: Vec<UrlTestResult>,
+}
+
+/// Runs the queued tests of one or more environments until every queue is drained. Each free
+/// slot goes to the environment with the fewest requests in flight, in turn on ties, each
+/// staying within its own limit and all of them within `total_limit`, so a large environment
+/// can't starve the others. An environment whose rate limit ran out gets no slots until its
+/// pause is over, and one whose host has `--max-per-host` requests in flight gets none until
+/// one of them completes. A test with a `depends_on` path is only queued once that path
+/// passed. When the read or write limit is full, an environment sends its next test of the
+/// other kind instead, if it has one. After `interrupt`, only the tests in flight are awaited.
+#[allow(clippy::too_many_arguments)]
+async fn dispatch_queues(
+    mut queues: Vec<EnvQueue>,
+    total_limit: Option<usize>,
+    mut method_limits: concurrency::MethodLimits,
+    mut host_limits: concurrency::HostLimits,
+    rate_limit: &rate_limit::RateLimitConfig,
+    run_in_flight: &mut concurrency::InFlightStats,
+    finish_result: &mut impl FnMut(&mut UrlTestResult),
+    interrupt: &interrupt::Interrupt,
+) -> Result<Vec<EnvQueue>, Box<dyn std::error::Error>> {
+    let slots: usize = queues.iter().map(|queue| queue.limit.limit()).sum();
+    // Tasks report on a channel in completion order, so every result can be handled
+    // (streamed, counted) the moment it arrives rather than once the batch is done
+    let (results_tx, mut results_rx) =
+        tokio::sync::mpsc::channel(total_limit.unwrap_or(slots).max(1));
+    let mut in_flight = 0;
+    let mut next_queue = 0;
+    loop {
+        let now = tokio::time::Instant::now();
+        while total_limit.is_none_or(|max| in_flight < max) && !interrupt.is_set() {
+            let mut blocked_hosts = Vec::new();
+            // The environment with the fewest requests in flight goes next, taking turns on ties
+            let ready = (0..queues.len())
+                .map(|offset| (next_queue + offset) % queues.len())
+                .filter_map(|i| {
+                    let queue = &queues[i];
+                    if !host_limits.has_room(&queue.host) {
+                        if !queue.pending.is_empty() {
+                            blocked_hosts.push(queue.host.clone());
+                        }
+                        return None;
+                    }
+                    if queue.in_flight >= queue.limit.limit()
+                        || queue.paused_until.is_some_and(|until| until > now)
+                        || queue
+                            .verdict
+                            .as_ref()
+                            .is_some_and(verdict::VerdictTracker::is_decided)
+                    {
+                        return None;
+                    }
+                    let next = queue
+                        .pending
+                        .iter()
+                        .position(|spec| method_limits.has_room(&spec.path.method))?;
+                    Some((i, next))
+                })
+                .min_by_key(|&(i, _)| queues[i].in_flight);
+            for host in blocked_hosts {
+                if let Some(max) = host_limits.note_throttled(&host) {
+                    println!(
+                        "{}Per-host limit reached: {} requests in flight to {}, holding back the rest until they complete (--max-per-host){}",
+                        COLOR_YELLOW, max, host, COLOR_RESET
+                    );
+                }
+            }
+            let Some((index, next)) = ready else {
+                break;
+            };
+            let queue = &mut queues[index];
+            let Some(spec) = queue.pending.remove(next) else {
+                break;
+            };
+            let read = concurrency::is_read_method(&spec.path.method);
+            method_limits.acquire(read);
+            host_limits.acquire(&queue.host);
+            let name = spec.path.name.clone();
+            let results_tx = results_tx.clone();
+            tokio::spawn(async move {
+                // The test runs in its own task so a panic still reports back as an error
+                let outcome = tokio::spawn(execute_with_retries(spec)).await;
+                let _ = results_tx.send((index, read, name, outcome)).await;
+            });
+            queue.in_flight += 1;
+            queue.usage.set(queue.in_flight);
+            in_flight += 1;
+            run_in_flight.set(in_flight);
+            next_queue = (index + 1) % queues.len();
+        }
+        let resume_at = queues
+            .iter()
+            .filter(|queue| !queue.pending.is_empty())
+            .filter_map(|queue| queue.paused_until)
+            .filter(|until| *until > now)
+            .min();
+        if in_flight == 0 && interrupt.is_set() {
+            break;
+        }
+        let received = match resume_at {
+            Some(resume_at) if in_flight == 0 => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(resume_at) => {}
+                    _ = interrupt.wait() => {}
+                }
+                continue;
+            }
+            None if in_flight == 0 => break,
+            // A paused environment may resume before anything else completes
+            Some(resume_at) => tokio::select! {
+                received = results_rx.recv() => received,
+                _ = tokio::time::sleep_until(resume_at) => continue,
+            },
+            None => results_rx.recv().await,
+        };
+        let Some((index, read, name, finished)) = received else {
+            break;
+        };
+        method_limits.release(read);
+        in_flight -= 1;
+        run_in_flight.set(in_flight);
+        let queue = &mut queues[index];
+        host_limits.release(&queue.host);
+        queue.in_flight -= 1;
+        queue.usage.set(queue.in_flight);
+        let mut result = finished?;
+        finish_result(&mut result);
+        if let Some(verdict) = &mut queue.verdict {
+            verdict.record(&result);
+        }
+        if let Some(name) = &name {
+            let ready = queue.waiting.resolve(name, result.passed);
+            queue.pending.extend(ready);
+        }
+        // Only errors that point at an overloaded service slow the run down
+        queue.limit.record(matches!(
+            result.failure_kind,
+            Some(FailureKind::Connection | FailureKind::Timeout | FailureKind::ServerError)
+        ));
+        let unix_now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        if let Some(pause) = rate_limit.pause_after(&result, unix_now) {
+            let until = tokio::time::Instant::now() + pause;
+            // Requests already in flight may report exhaustion too; only extend the pause
+            if !pause.is_zero()
+                && !queue.pending.is_empty()
+                && queue.paused_until.is_none_or(|paused| paused < until)
+            {
+                println!(
+                    "{}Rate limit exhausted for '{}': pausing dispatch for {}s{}",
+                    COLOR_YELLOW,
+                    queue.env_name,
+                    pause.as_secs(),
+                    COLOR_RESET
+                );
+                queue.paused_until = Some(until);
+            }
+        }
+        queue.results.push(result);
+    }
+    Ok(queues)
+}
+
+/// Reports how each drained queue's concurrency went and hands back its results. Tests
+/// skipped for their dependency or left out by an early verdict are added to
+/// `skipped_paths`, those skipped for their dependency also as rows to `skipped_results`,
+/// and the early verdicts to `decided_early`.
+fn collect_queue_results(
+    queues: impl Iterator<Item = EnvQueue>,
+    args: &Args,
+    progress: bool,
+    redactor: &redact::Redactor,
+    skipped_paths: &mut BTreeMap<String, Vec<SkippedPath>>,
+    skipped_results: &mut Vec<UrlTestResult>,
+    decided_early: &mut BTreeMap<String, verdict::EarlyVerdict>,
+) -> Vec<UrlTestResult> {
+    let mut all_results = Vec::new();
+    for mut queue in queues {
+        let mut skipped = Vec::new();
+        if let Some(tracker) = queue.verdict.take().filter(|tracker| tracker.is_decided()) {
+            let not_run: Vec<TestSpec> = queue
+                .pending
+                .drain(..)
+                .chain(queue.waiting.take_remaining())
+                .collect();
+            // Decided by the last result, nothing was left out
+            if !not_run.is_empty() {
+                let not_run_count = not_run.len();
+                skipped.extend(
+                    not_run
+                        .into_iter()
+                        .map(|spec| (spec, verdict::NOT_RUN.to_string(), false)),
+                );
+                decided_early.extend(
+                    tracker
+                        .into_early_verdict(not_run_count)
+                        .map(|early| (queue.env_name.clone(), early)),
+                );
+            }
+        }
+        skipped.extend(
+            std::mem::take(&mut queue.waiting)
+                .into_skipped()
+                .into_iter()
+                .map(|(spec, reason)| (spec, reason, true)),
+        );
+        for (spec, reason, for_dependency) in skipped {
+            let entry = SkippedPath::new(&spec.path, redactor, reason);
+            if progress {
+                println!(
+                    "Skipping '{}' on '{}': {}",
+                    entry.path, queue.env_name, entry.reason
+                );
+            }
+            if for_dependency {
+                let mut res = UrlTestResult::for_spec(&spec);
+                res.attempts = 0;
+                res.skip_reason = Some(entry.reason.clone());
+                skipped_results.push(res);
+            }
+            skipped_paths
+                .entry(queue.env_name.clone())
+                .or_default()
+                .push(entry);
+        }
+        if progress {
+            if args.adaptive_concurrency {
+                println!(
+                    "Concurrency for '{}': {}",
+                    queue.env_name,
+                    queue.limit.describe_history()
+                );
+            }
+            // Nothing was dispatched when the DNS precheck failed
+            if queue.usage.peak() > 0 {
+                println!(
+                    "Achieved concurrency for '{}': average {:.1}, peak {}",
+                    queue.env_name,
+                    queue.usage.average(),
+                    queue.usage.peak()
+                );
+            }
+        }
+        // Reports list results in config order whatever order they were dispatched in
+        queue.results.sort_by_key(|res| res.path_index);
+        all_results.append(&mut queue.results);
+    }
+    all_results
+}
+
+/// Redacts a freshly completed result and fills in the fields derived from the config,
+/// before it reaches the stream or any report.
+fn finalize_result(
+    res: &mut UrlTestResult,
+    config: &Config,
+    config_hash: &str,
+    run_label: Option<&str>,
+    redactor: &redact::Redactor,
+) {
+    // Before the test_id, so a rotated secret doesn't change it
+    redactor.redact_result(res);
+    res.config_hash = config_hash.to_string();
+    res.run_label = run_label.map(str::to_string);
+    res.test_id = test_id(
+        &res.environment_name,
+        &res.url,
+        &config.test_id_ignored_params,
+    );
+    // Failures count as outside the SLA however fast they were
+    res.within_sla = config
+        .sla_ms
+        .map(|sla_ms| res.passed && res.duration_secs * 1000.0 <= sla_ms);
+}
+
+/// Decides the order the paths of one environment are dispatched in, as indexes into
+/// `config.paths`.
+fn dispatch_order(
+    schedule: Schedule,
+    config: &Config,
+    env_name: &str,
+    env_data: &Environment,
+    baseline: Option<&Baseline>,
+) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..config.paths.len()).collect();
+    match schedule {
+        Schedule::Fifo => {}
+        Schedule::Shuffle => {
+            // Fisher-Yates
+            let mut rng = Xorshift::from_clock();
+            for i in (1..order.len()).rev() {
+                order.swap(i, rng.below(i + 1));
+            }
+        }
+        Schedule::LongestFirst => {
+            let redactor = redact::Redactor::new(config);
+            let durations: Vec<Option<f64>> = config
+                .paths
+                .iter()
+                .map(|path| {
+                    let url = redactor.redact(&format!("{}{}", env_data.baseurl, path.path));
+                    let key = test_id(env_name, &url, &config.test_id_ignored_params);
+                    baseline.and_then(|baseline| baseline.durations.get(&key).copied())
+                })
+                .collect();
+            // Paths without history sort as if they took the median time
+            let mut known: Vec<f64> = durations.iter().flatten().copied().collect();
+            known.sort_by(f64::total_cmp);
+            let median = known.get(known.len() / 2).copied().unwrap_or(0.0);
+            order.sort_by(|&a, &b| {
+                durations[b]
+                    .unwrap_or(median)
+                    .total_cmp(&durations[a].unwrap_or(median))
+            });
+        }
+    }
+    order
+}
+
+/// Everything a single test needs, detached from the config so it can be moved into a task.
+#[derive(Clone)]
+struct TestSpec {
+    client: reqwest::Client,
+    environment_name: String,
+    base_url: String,
+    path_index: usize,
+    path: PathConfig,
+    headers: reqwest::header::HeaderMap,
+    // The --rotate-user-agent pick, if any
+    user_agent: Option<String>,
+    app_error_key: String,
+    app_error_code: Option<String>,
+    soft_error_markers: Vec<String>,
+    fail_on_redirect_to_hosts: Vec<String>,
+    annotate_from_headers: BTreeMap<String, String>,
+    forbidden_patterns: Vec<ForbiddenPattern>,
+    forbidden_warn_only: bool,
+    max_body_read: Option<usize>,
+    throttle_kbps: Option<u32>,
+    duration_measure: DurationMeasure,
+    keep_failed_body: bool,
+    keep_all_bodies: bool,
+    retries: u32,
+    retry_delay: f64,
+    retry_on_assertion: bool,
+    required_http_version: Option<reqwest::Version>,
+    connection_mode: ConnectionMode,
+    rate_limit: rate_limit::RateLimitConfig,
+    // Sent as `Authorization` unless the path's headers already set one
+    bearer: Option<std::sync::Arc<auth::BearerToken>>,
+    // Set in maintenance mode, replacing the status and body checks
+    maintenance: Option<maintenance::MaintenanceCheck>,
+}
+
+impl TestSpec {
+    /// Sends the request with `user_agent`, replacing any configured User-Agent header.
+    fn with_user_agent(mut self, user_agent: Option<&str>) -> Result<Self, String> {
+        if let Some(user_agent) = user_agent {
+            let value = reqwest::header::HeaderValue::from_str(user_agent)
+                .map_err(|_| format!("invalid user_agents entry '{}'", user_agent))?;
+            self.headers.insert(reqwest::header::USER_AGENT, value);
+            self.user_agent = Some(user_agent.to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// Small xorshift generator seeded from the clock, for choices that needn't be reproducible.
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn from_clock() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Xorshift(seed | 1)
+    }
+
+    // A number in 0..bound
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// Hands out the User-Agent of each request under `--rotate-user-agent`.
+struct UserAgentPool<'a> {
+    agents: &'a [String],
+    order: Option<UserAgentOrder>,
+    next: usize,
+    rng: Xorshift,
+}
+
+impl<'a> UserAgentPool<'a> {
+    fn new(config: &'a Config, order: Option<UserAgentOrder>) -> Self {
+        UserAgentPool {
+            agents: &config.user_agents,
+            order,
+            next: 0,
+            rng: Xorshift::from_clock(),
+        }
+    }
+
+    /// The User-Agent for the next request, or None to keep the configured headers.
+    fn pick(&mut self) -> Option<&'a str> {
+        if self.agents.is_empty() {
+            return None;
+        }
+        let index = match self.order? {
+            UserAgentOrder::RoundRobin => {
+                let index = self.next % self.agents.len();
+                self.next += 1;
+                index
+            }
+            UserAgentOrder::Random => self.rng.below(self.agents.len()),
+        };
+        Some(&self.agents[index])
+    }
+}
+
+/// Builds the spec for the path at `path_index` against one environment.
+fn test_spec(
+    config: &Config,
+    args: &Args,
+    clients: &HttpClients,
+    env_name: &str,
+    env_data: &Environment,
+    path_index: usize,
+    request_headers: &reqwest::header::HeaderMap,
+) -> Result<TestSpec, Box<dyn std::error::Error>> {
+    let path = &config.paths[path_index];
+    spec_for_path(
+        config,
+        args,
+        clients,
+        env_name,
+        env_data,
+        path_index,
+        path,
+        request_headers,
+    )
+}
+
+/// Builds the spec for the environment's `health_gate`: a plain GET that counts as critical,
+/// since its failure hides every other result of the environment.
+fn health_gate_spec(
+    config: &Config,
+    args: &Args,
+    clients: &HttpClients,
+    env_name: &str,
+    env_data: &Environment,
+    gate: &str,
+    request_headers: &reqwest::header::HeaderMap,
+) -> Result<TestSpec, Box<dyn std::error::Error>> {
+    let mut path = PathConfig::simple(gate.to_string());
+    path.severity = Severity::Critical;
+    // Listed ahead of the configured paths
+    spec_for_path(
+        config,
+        args,
+        clients,
+        env_name,
+        env_data,
+        0,
+        &path,
+        request_headers,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spec_for_path(
+    config: &Config,
+    args: &Args,
+    clients: &HttpClients,
+    env_name: &str,
+    env_data: &Environment,
+    path_index: usize,
+    path: &PathConfig,
+    request_headers: &reqwest::header::HeaderMap,
+) -> Result<TestSpec, Box<dyn std::error::Error>> {
+    let client = clients.for_path(env_data, path);
+    Ok(TestSpec {
+        client,
+        environment_name: env_name.to_string(),
+        base_url: env_data.baseurl.clone(),
+        path_index,
+        path: path.clone(),
+        headers: path_headers(request_headers, config, env_data, path)?,
+        user_agent: None,
+        app_error_key: config.app_error_key_to_fail.clone(),
+        app_error_code: config
+            .app_error_code_to_fail
+            .clone()
+            .filter(|_| !path.skip_app_error_check),
+        soft_error_markers: config.soft_error_markers.clone(),
+        fail_on_redirect_to_hosts: config.fail_on_redirect_to_hosts.clone(),
+        annotate_from_headers: config.annotate_from_headers.clone(),
+        forbidden_patterns: config.forbidden_patterns.clone(),
+        forbidden_warn_only: args.forbidden_warn_only,
+        max_body_read: args.max_body_read,
+        throttle_kbps: args.throttle_kbps,
+        duration_measure: config.duration_measure,
+        keep_failed_body: args.verbose,
+        keep_all_bodies: args
+            .compare_envs
+            .as_ref()
+            .is_some_and(|envs| envs.iter().any(|env| env == env_name)),
+        retries: args.retries,
+        retry_delay: args.retry_delay,
+        retry_on_assertion: args.retry_on_body_assertion,
+        required_http_version: env_data
+            .require_http_version
+            .as_deref()
+            .map(parse_http_version)
+            .transpose()?,
+        connection_mode: ConnectionMode::from_args(args),
+        rate_limit: config.rate_limit.clone(),
+        bearer: clients.bearer.clone(),
+        maintenance: (args.maintenance || env_data.maintenance).then(|| config.maintenance.clone()),
+    })
+}
+
+/// Re-requests every failing result once, one at a time so a struggling service isn't
+/// hit with another burst, and records whether the failure persisted.
+async fn reverify_failures(
+    results: &mut [UrlTestResult],
+    config: &Config,
+    args: &Args,
+    clients: &HttpClients,
+    request_headers: &reqwest::header::HeaderMap,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let failing = results.iter().filter(|res| !res.passed).count();
+    if failing == 0 {
+        return Ok(());
+    }
+    if !args.stable_output {
+        println!("Re-verifying {} failing URL(s)...", failing);
+    }
+    for result in results.iter_mut().filter(|res| !res.passed) {
+        let Some(env_data) = config.environments.get(&result.environment_name) else {
+            continue;
+        };
+        let spec = match env_data
+            .health_gate
+            .as_deref()
+            .filter(|_| result.health_gate)
+        {
+            Some(gate) => health_gate_spec(
+                config,
+                args,
+                clients,
+                &result.environment_name,
+                env_data,
+                gate,
+                request_headers,
+            )?,
+            None => test_spec(
+                config,
+                args,
+                clients,
+                &result.environment_name,
+                env_data,
+                result.path_index,
+                request_headers,
+            )?,
+        }
+        // The same User-Agent as the failed request
+        .with_user_agent(result.user_agent.as_deref())?;
+        let retry = execute_test(spec).await;
+        result.reverify_duration_secs = Some(retry.duration_secs);
+        result.reverified = Some(if retry.passed {
+            ReverifyOutcome::RecoveredOnRetry
+        } else {
+            ReverifyOutcome::Confirmed
+        });
+    }
+    Ok(())
+}
+
+/// Runs the checks on the body of a successful response, returning why it fails them.
+fn check_body(
+    body_text: &str,
+    app_error_key: &str,
+    app_error_code: Option<&str>,
+    soft_error_markers: &[String],
+) -> Option<String> {
+    // Check if a specific application error code is configured
+    if let Some(code_to_fail) = app_error_code {
+        // Dynamically construct the search string using both key and code
+        let search_string = format!(r#""{}":"{}""#, app_error_key, code_to_fail);
+        if body_text.contains(&search_string) {
+            // If parsing ApiResponse fails, use the configured key and code in the message
+            return Some(match serde_json::from_str::<ApiResponse>(body_text) {
+                Ok(api_response) => format!(
+                    "App Error ({}: {}): {}",
+                    app_error_key, code_to_fail, api_response.message
+                ),
+                Err(_) => format!(
+                    "App Error ({}: {}): message parsing failed.",
+                    app_error_key, code_to_fail
+                ),
+            });
+        }
+    }
+
+    // Catch-all for error pages served with a 2xx status
+    soft_error_markers
+        .iter()
+        .find(|marker| body_text.contains(marker.as_str()))
+        .map(|marker| format!("Soft error detected: body contains '{}'", marker))
+}
+
+/// Names each forbidden pattern found in the body. The matched text itself is never
+/// included, so reports don't leak what the check is guarding against.
+fn check_forbidden(body_text: &str, patterns: &[ForbiddenPattern]) -> Vec<String> {
+    patterns
+        .iter()
+        .filter(|pattern| pattern.regex.is_match(body_text))
+        .map(|pattern| format!("Response body matches forbidden pattern '{}'", pattern.name))
+        .collect()
+}
+
+// Replaces every forbidden match with "***", for the copies of the body kept in results
+fn mask_forbidden(text: &str, patterns: &[ForbiddenPattern]) -> String {
+    patterns.iter().fold(text.to_string(), |text, pattern| {
+        pattern.regex.replace_all(&text, "***").into_owned()
+    })
+}
+
+/// Runs the body assertions declared on the path itself. `body_bytes` is the size of the body
+/// as received, which the text may differ from once cut off or decoded.
+fn check_path_body(body_text: &str, body_bytes: u64, path: &PathConfig) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(min) = path.min_response_bytes {
+        if body_bytes < min as u64 {
+            failures.push(format!(
+                "Response body is {} bytes, expected at least {}",
+                body_bytes, min
+            ));
+        }
+    }
+
+    let bounds: Vec<(&String, Option<usize>, Option<usize>)> = path
+        .expect_json_array_min
+        .iter()
+        .map(|bound| (&bound.pointer, Some(bound.min), None))
+        .chain(
+            path.expect_json_array_max
+                .iter()
+                .map(|bound| (&bound.pointer, None, Some(bound.max))),
+        )
+        .collect();
+    if !path.require_valid_json
+        && bounds.is_empty()
+        && path.json_equals.is_empty()
+        && path.require_json_keys.is_empty()
+    {
+        return failures;
+    }
+    let json: serde_json::Value = match serde_json::from_str(body_text) {
+        Ok(value) => value,
+        // The parse error gives the position, e.g. "expected value at line 1 column 1"
+        Err(e) if path.require_valid_json => {
+            failures.push(format!("Response is not valid JSON: {}", e));
+            return failures;
+        }
+        Err(_) => {
+            let checked = match bounds.first() {
+                Some((pointer, _, _)) => format!("the array at '{}'", pointer),
+                None => format!(
+                    "'{}'",
+                    path.json_equals
+                        .keys()
+                        .chain(&path.require_json_keys)
+                        .next()
+                        .unwrap_or(&String::new())
+                ),
+            };
+            failures.push(format!(
+                "Response body is not valid JSON, can't check {}",
+                checked
+            ));
+            return failures;
+        }
+    };
+    for (pointer, min, max) in bounds {
+        let Some(node) = json.pointer(pointer) else {
+            failures.push(format!(
+                "JSON pointer '{}' not found in the response",
+                pointer
+            ));
+            continue;
+        };
+        let Some(items) = node.as_array() else {
+            failures.push(format!(
+                "JSON pointer '{}' is not an array, found {}",
+                pointer,
+                json_type_name(node)
+            ));
+            continue;
+        };
+        if let Some(min) = min.filter(|min| items.len() < *min) {
+            failures.push(format!(
+                "JSON array '{}' has {} items, expected at least {}",
+                pointer,
+                items.len(),
+                min
+            ));
+        }
+        if let Some(max) = max.filter(|max| items.len() > *max) {
+            failures.push(format!(
+                "JSON array '{}' has {} items, expected at most {}",
+                pointer,
+                items.len(),
+                max
+            ));
+        }
+    }
+    for (field, expected) in &path.json_equals {
+        let Some(actual) = json.pointer(&json_field_pointer(field)) else {
+            failures.push(format!("JSON '{}' not found in the response", field));
+            continue;
+        };
+        if json_values_equal(actual, expected) {
+            continue;
+        }
+        failures.push(if json_type_name(actual) == json_type_name(expected) {
+            format!("JSON '{}' is {}, expected {}", field, actual, expected)
+        } else {
+            format!(
+                "JSON '{}' is {} ({}), expected {} ({})",
+                field,
+                json_type_name(actual),
+                actual,
+                json_type_name(expected),
+                expected
+            )
+        });
+    }
+    // Listed in one message, so a response missing several fields reads as one problem
+    let missing: Vec<String> = path
+        .require_json_keys
+        .iter()
+        .filter_map(|key| match json.pointer(&json_field_pointer(key)) {
+            None => Some(key.clone()),
+            Some(serde_json::Value::Null) => Some(format!("{} (null)", key)),
+            Some(_) => None,
+        })
+        .collect();
+    if !missing.is_empty() {
+        failures.push(format!(
+            "Required JSON keys missing: {}",
+            missing.join(", ")
+        ));
+    }
+    failures
+}
+
+/// Turns a `json_equals` or `require_json_keys` key into a JSON pointer: "data.items[0].id" becomes
+/// "/data/items/0/id". Keys that already start with '/' are used as they are.
+fn json_field_pointer(field: &str) -> String {
+    if field.starts_with('/') {
+        return field.to_string();
+    }
+    let field = field.trim_start_matches('$').trim_start_matches('.');
+    field
+        .replace('[', ".")
+        .replace(']', "")
+        .split('.')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+// Numbers compare by value, so 1 equals 1.0; everything else must match exactly
+fn json_values_equal(actual: &serde_json::Value, expected: &serde_json::Value) -> bool {
+    match (actual, expected) {
+        (serde_json::Value::Number(a), serde_json::Value::Number(b)) => {
+            match (a.as_i64(), b.as_i64()) {
+                (Some(a), Some(b)) => a == b,
+                _ => a.as_f64() == b.as_f64(),
+            }
+        }
+        _ => actual == expected,
+    }
+}
+
+/// Runs a path's `assert_expr`: a false outcome or an evaluation error fails the test.
+fn check_assert_expr(expr: &expr::Expr, response: &expr::Response) -> Option<String> {
+    match expr.evaluate(response) {
+        Ok(true) => None,
+        Ok(false) => Some(format!("assert_expr is false: {}", expr)),
+        Err(e) => Some(format!("assert_expr error: {}", e)),
+    }
+}
+
+fn json_type_name(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Array(_) => "an array",
+        serde_json::Value::Object(_) => "an object",
+    }
+}
+
+/// Runs a test, repeating it while it fails in a retryable way and retries are left.
+/// Connection errors, timeouts and 5xx responses are retryable; assertion failures only
+/// with --retry-on-body-assertion. The last attempt's result is returned.
+async fn execute_with_retries(spec: TestSpec) -> UrlTestResult {
+    let mut retried_assertion = false;
+    let mut attempt = 1;
+    let (mut sent, mut received) = (None, None);
+    loop {
+        let mut result = execute_test(spec.clone()).await;
+        let add = |total: Option<u64>, bytes: Option<u64>| match (total, bytes) {
+            (Some(total), Some(bytes)) => Some(total + bytes),
+            (total, bytes) => total.or(bytes),
+        };
+        sent = add(sent, result.request_bytes);
+        received = add(received, result.response_bytes);
+        result.request_bytes = sent;
+        result.response_bytes = received;
+        let retryable = match result.failure_kind {
+            Some(
+                FailureKind::Dns
+                | FailureKind::Connection
+                | FailureKind::Timeout
+                | FailureKind::ServerError,
+            ) => true,
+            Some(FailureKind::Assertion) => spec.retry_on_assertion,
+            _ => false,
+        };
+        if result.passed || !retryable || attempt > spec.retries {
+            result.attempts = attempt;
+            result.retried_assertion = retried_assertion;
+            return result;
+        }
+        retried_assertion |= result.failure_kind == Some(FailureKind::Assertion);
+        attempt += 1;
+        tokio::time::sleep(std::time::Duration::from_secs_f64(spec.retry_delay)).await;
+    }
+}
+
+/// Runs one path (setup, main request, checks, teardown) and reports the outcome.
+async fn execute_test(mut spec: TestSpec) -> UrlTestResult {
+    let url = format!("{}{}", spec.base_url, spec.path.path);
+    let auth_error = match &spec.bearer {
+        Some(bearer) => match bearer.authorization().await {
+            Ok(authorization) => {
+                if !spec.headers.contains_key(reqwest::header::AUTHORIZATION) {
+                    spec.headers
+                        .insert(reqwest::header::AUTHORIZATION, authorization);
+                }
+                None
+            }
+            Err(e) => Some(format!("Token refresh failed: {}", e)),
+        },
+        None => None,
+    };
+    let mut result = UrlTestResult::for_spec(&spec);
+
+    // Like a failed setup request, a missing token means the test can't run as intended
+    if let Some(e) = auth_error {
+        result.fail(FailureKind::Setup, e.clone());
+        result.setup_error = Some(e);
+    } else if let Some(setup) = &spec.path.setup {
+        if let Err(e) = send_sub_request(&spec.client, &spec.base_url, setup, &spec.headers).await {
+            result.fail(FailureKind::Setup, format!("Setup failed: {}", e));
+            result.setup_error = Some(e);
+        }
+    }
+
+    // The main request only runs once its precondition is in place
+    let start_time = Instant::now();
+    let response = if result.setup_error.is_none() {
+        let method = parse_method(&spec.path.method).unwrap_or(reqwest::Method::GET);
+        let mut request = build_request(
+            &spec.client,
+            method,
+            &url,
+            spec.path.body.as_deref(),
+            &spec.headers,
+        );
+        if let Some(streaming) = &spec.path.streaming {
+            // The client's timeout covers the whole body, so give streams room to run
+            request = request.timeout(
+                std::time::Duration::from_secs_f64(streaming.max_secs)
+                    + std::time::Duration::from_secs(10),
+            );
+        }
+        if let Some(range) = &spec.path.range {
+            request = request.header(reqwest::header::RANGE, range_header(range));
+        }
+        usage::count_request();
+        let (response, opened_connection) = match request.build() {
+            Ok(request) => {
+                result.request_bytes = Some(request_size(&request));
+                connection::track(spec.client.execute(request)).await
+            }
+            Err(e) => (Err(e), false),
+        };
+        if spec.connection_mode == ConnectionMode::Pooled && response.is_ok() {
+            result.connection_reused = Some(!opened_connection);
+        }
+        Some(response)
+    } else {
+        None
+    };
+
+    let mut headers_received = None;
+    let mut negotiated_version = None;
+    match response {
+        None => {}
+        Some(Ok(response)) => {
+            headers_received = Some(start_time.elapsed());
+            negotiated_version = Some(response.version());
+            result.status_code = Some(response.status().as_u16());
+            result.http_version = Some(format!("{:?}", response.version()));
+            result.remote_addr = response.remote_addr().map(|addr| addr.to_string());
+            let head_bytes = response_head_size(&response);
+            let status = response.status();
+            let final_url = response.url().clone();
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let header_value = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            result.cache_age = header_value("age").and_then(|age| age.parse().ok());
+            result.cache_status =
+                header_value("x-cache").or_else(|| header_value("cf-cache-status"));
+            if let Some(server_timing) = header_value("server-timing") {
+                result.server_timing = parse_server_timing(&server_timing);
+            }
+            result.annotations = spec
+                .annotate_from_headers
+                .iter()
+                .filter_map(|(name, header)| {
+                    let value = header_value(header)?;
+                    let value = value.trim();
+                    (!value.is_empty()).then(|| (name.clone(), value.to_string()))
+                })
+                .collect();
+            result.cookies = response
+                .headers()
+                .get_all(reqwest::header::SET_COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .filter_map(cookies::parse_set_cookie)
+                .collect();
+            let header_number =
+                |name: &str| header_value(name).and_then(|value| value.trim().parse().ok());
+            result.rate_limit_limit = header_number(&spec.rate_limit.limit_header);
+            result.rate_limit_remaining = header_number(&spec.rate_limit.remaining_header);
+            result.rate_limit_reset = header_number(&spec.rate_limit.reset_header);
+            let range_failure = spec.path.range.as_ref().and_then(|_| {
+                let (total, failure) = check_range(
+                    status,
+                    header_value("content-range").as_deref(),
+                    header_number("content-length"),
+                    spec.path.allow_full_response,
+                );
+                result.total_size_bytes = total;
+                failure
+            });
+            // A range never needs more than its own length read, even from a server that
+            // ignores it
+            let body_limit = match spec
+                .path
+                .range
+                .as_deref()
+                .and_then(|range| range_length(range).ok().flatten())
+            {
+                Some(length) => Some(spec.max_body_read.map_or(length, |max| max.min(length))),
+                None => spec.max_body_read,
+            };
+            // Reading the body consumes the response
+            let response_headers = spec
+                .path
+                .assert_expr
+                .is_some()
+                .then(|| response.headers().clone());
+
+            let download_start = Instant::now();
+            let mut stream_matched = None;
+            let body = match &spec.path.streaming {
+                Some(streaming) => {
+                    read_stream(response, streaming, body_limit)
+                        .await
+                        .map(|outcome| {
+                            result.streamed_bytes = Some(outcome.bytes);
+                            result.streamed_lines = Some(outcome.lines);
+                            stream_matched = Some(outcome.matched);
+                            (outcome.text, outcome.truncated, outcome.bytes)
+                        })
+                }
+                None => read_body(response, body_limit, spec.throttle_kbps).await,
+            };
+            result.download_secs = Some(download_start.elapsed().as_secs_f64());
+            let (body_text, body_bytes) = match body {
+                Ok((text, truncated, body_bytes)) => {
+                    result.body_truncated = truncated;
+                    result.response_bytes = Some(head_bytes + body_bytes);
+                    (text, body_bytes)
+                }
+                Err(e) => {
+                    result.response_bytes = Some(head_bytes);
+                    result.response_body_preview = format!("Error reading body: {}", e);
+                    result.fail(
+                        FailureKind::Connection,
+                        format!("Failed to read response body: {}", e),
+                    );
+                    ("".to_string(), 0)
+                }
+            };
+
+            result.response_body_preview = body_text.chars().take(100).collect();
+            result.response_body = Some(body_text.clone());
+
+            // A 304 only comes back for a conditional request, so it isn't a failure either
+            let status_ok = match spec.path.expected_status {
+                Some(expected) => status.as_u16() == expected,
+                // An assert_expr makes its own decision on the status
+                None if spec.path.assert_expr.is_some() => true,
+                None => status.is_success() || status == reqwest::StatusCode::NOT_MODIFIED,
+            };
+
+            if let Some(maintenance) = &spec.maintenance {
+                for message in maintenance.check(status, &body_text) {
+                    result.fail(FailureKind::Assertion, message);
+                }
+            } else if let Some(expected) = &spec.path.expected_location {
+                // A redirect assertion replaces the usual 2xx check
+                match location.as_deref() {
+                    Some(actual) if status.is_redirection() && expected.matches(actual) => {}
+                    Some(actual) => result.fail(
+                        FailureKind::Assertion,
+                        format!(
+                            "Expected redirect to {}, got {} with Location '{}'",
+                            expected, status, actual
+                        ),
+                    ),
+                    None => result.fail(
+                        FailureKind::Assertion,
+                        format!(
+                            "Expected redirect to {}, got {} without a Location header",
+                            expected, status
+                        ),
+                    ),
+                }
+            } else if status_ok {
+                // Every body check runs, so one report shows all that's wrong with a response
+                let messages = check_body(
+                    &body_text,
+                    &spec.app_error_key,
+                    spec.app_error_code.as_deref(),
+                    &spec.soft_error_markers,
+                )
+                .into_iter()
+                .chain(check_path_body(&body_text, body_bytes, &spec.path))
+                .chain(check_stream_match(stream_matched, &spec.path))
+                .chain(range_failure);
+                for message in messages {
+                    result.fail(FailureKind::Assertion, message);
+                }
+            } else if let Some(expected) = spec.path.expected_status {
+                result.fail(
+                    FailureKind::Assertion,
+                    format!("Expected status {}, got {}", expected, status),
+                );
+            } else {
+                // Failed if HTTP status is not 2xx
+                let kind = if status.is_server_error() {
+                    FailureKind::ServerError
+                } else {
+                    FailureKind::ClientError
+                };
+                result.fail(kind, format!("HTTP Status Error: {}", status));
+            }
+            // A 200 from the wrong site is exactly what this catches, so whatever the status
+            if let Some(message) = check_redirect_hosts(
+                &url,
+                &final_url,
+                location.as_deref(),
+                &spec.fail_on_redirect_to_hosts,
+            ) {
+                result.fail(FailureKind::Assertion, message);
+            }
+            // Checked whatever the status, since error pages can leak data too
+            let forbidden = check_forbidden(&body_text, &spec.forbidden_patterns);
+            if !forbidden.is_empty() {
+                let masked = mask_forbidden(&body_text, &spec.forbidden_patterns);
+                result.response_body_preview = masked.chars().take(100).collect();
+                result.response_body = Some(masked);
+            }
+            for message in forbidden {
+                if spec.forbidden_warn_only {
+                    result.add_warning(message);
+                } else {
+                    result.fail(FailureKind::Assertion, message);
+                }
+            }
+            // Also whatever the status: the expression usually decides on it. The maintenance
+            // page isn't the response it was written for
+            if let (Some(expr), Some(headers), None) =
+                (&spec.path.parsed_expr, &response_headers, &spec.maintenance)
+            {
+                let elapsed = match (spec.duration_measure, headers_received) {
+                    (DurationMeasure::Ttfb, Some(ttfb)) => ttfb,
+                    _ => start_time.elapsed(),
+                };
+                let response = expr::Response {
+                    status: status.as_u16(),
+                    duration_ms: elapsed.as_secs_f64() * 1000.0,
+                    body: &body_text,
+                    headers,
+                };
+                if let Some(message) = check_assert_expr(expr, &response) {
+                    result.fail(FailureKind::Assertion, message);
+                }
+            }
+            // Passed if HTTP 2xx and no check failed
+            result.passed = result.failures.is_empty();
+        }
+        Some(Err(e)) => {
+            if let Some(cause) = dns_failure(&e) {
+                let url = reqwest::Url::parse(&result.url).ok();
+                let host = url
+                    .as_ref()
+                    .and_then(|url| url.host_str())
+                    .unwrap_or("unknown host");
+                result.fail(
+                    FailureKind::Dns,
+                    format!("DNS resolution failed for host {}: {}", host, cause),
+                );
+            } else {
+                let kind = if e.is_timeout() {
+                    FailureKind::Timeout
+                } else {
+                    FailureKind::Connection
+                };
+                result.fail(kind, e.to_string());
+            }
+        }
+    }
+    if result.setup_error.is_none() {
+        // Requests that got no response have no first byte, so they report the total
+        let measured = match (spec.duration_measure, headers_received) {
+            (DurationMeasure::Ttfb, Some(ttfb)) => ttfb,
+            _ => start_time.elapsed(),
+        };
+        result.duration_secs = measured.as_secs_f64();
+    }
+
+    // The remaining checks apply to any response, so they add to earlier failures
+    for message in check_server_timing(&result.server_timing, &spec.path.server_timing_max) {
+        result.fail(FailureKind::Assertion, message);
+    }
+
+    if let (Some(required), Some(actual)) = (spec.required_http_version, negotiated_version) {
+        if actual != required {
+            result.fail(
+                FailureKind::Assertion,
+                format!(
+                    "Negotiated {}, required {}",
+                    http_version_label(actual),
+                    http_version_label(required)
+                ),
+            );
+        }
+    }
+
+    if result.status_code.is_some() && spec.maintenance.is_none() {
+        for message in cookies::check_cookies(&result.cookies, &spec.path.expected_cookies) {
+            result.fail(FailureKind::Assertion, message);
+        }
+        if let Some(message) = check_duration_regression(result.duration_secs, &spec.path) {
+            match spec.path.on_regression {
+                RegressionAction::Fail => result.fail(FailureKind::Assertion, message),
+                RegressionAction::Warn => result.add_warning(message),
+            }
+        }
+    }
+
+    // Teardown is always attempted so resources created by setup get cleaned up
+    if let Some(teardown) = &spec.path.teardown {
+        if let Err(e) =
+            send_sub_request(&spec.client, &spec.base_url, teardown, &spec.headers).await
+        {
+            result.fail(FailureKind::Teardown, format!("Teardown failed: {}", e));
+            result.teardown_error = Some(e);
+        }
+    }
+    if !(spec.keep_all_bodies || (spec.keep_failed_body && !result.passed)) {
+        result.response_body = None;
+    }
+    usage::count_transfer(
+        result.request_bytes.unwrap_or(0),
+        result.response_bytes.unwrap_or(0),
+    );
+    result
+}
+
+/// Sends `rounds` throwaway requests per path so TLS sessions and pooled connections are warm
+/// before the measured run. Only GET and HEAD paths are warmed, to avoid repeating side
+/// effects, and setup/teardown requests are skipped. At most `concurrency` are in flight at
+/// once. Returns the number of requests sent.
+async fn warm_up(
+    clients: &HttpClients,
+    config: &Config,
+    env: &Environment,
+    rounds: u32,
+    concurrency: usize,
+    base_headers: &reqwest::header::HeaderMap,
+) -> usize {
+    // Warm-up responses are thrown away, so a token that can't be refreshed is left out
+    let authorization = match &clients.bearer {
+        Some(bearer) => bearer.authorization().await.ok(),
+        None => None,
+    };
+    let slots = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+    let mut tasks = tokio::task::JoinSet::new();
+    for path in &config.paths {
+        let Ok(mut headers) = path_headers(base_headers, config, env, path) else {
+            continue;
+        };
+        if let Some(authorization) = &authorization {
+            if !headers.contains_key(reqwest::header::AUTHORIZATION) {
+                headers.insert(reqwest::header::AUTHORIZATION, authorization.clone());
+            }
+        }
+        let Ok(method) = parse_method(&path.method) else {
+            continue;
+        };
+        if method != reqwest::Method::GET && method != reqwest::Method::HEAD {
+            continue;
+        }
+        let client = clients.for_path(env, path);
+        for _ in 0..rounds {
+            let request = build_request(
+                &client,
+                method.clone(),
+                &format!("{}{}", env.baseurl, path.path),
+                path.body.as_deref(),
+                &headers,
+            );
+            let slots = std::sync::Arc::clone(&slots);
+            tasks.spawn(async move {
+                let Ok(_slot) = slots.acquire_owned().await else {
+                    return;
+                };
+                // Drain the body so the connection goes back to the pool
+                usage::count_request();
+                if let Ok(response) = request.send().await {
+                    if let Ok(body) = response.bytes().await {
+                        usage::count_bytes(body.len());
+                    }
+                }
+            });
+        }
+    }
+    let mut sent = 0;
+    while tasks.join_next().await.is_some() {
+        sent += 1;
+    }
+    sent
+}
+
+/// Computes a stable fingerprint of the effective config, so reports produced by the
+/// same config can be recognised. Map keys are sorted before hashing, so equivalent
+/// configs hash equally regardless of the order entries were written in. The options that
+/// pick what is tested and how requests are sent count too, when given.
+fn config_fingerprint(config: &Config, args: &Args) -> String {
+    let mut value = serde_json::to_value(config).unwrap_or(serde_json::Value::Null);
+    let run_options: serde_json::Map<String, serde_json::Value> = [
+        ("env", serde_json::json!(args.env)),
+        ("group", serde_json::json!(args.group)),
+        ("compare_envs", serde_json::json!(args.compare_envs)),
+        (
+            "path_filter",
+            serde_json::json!(args.path_filter.as_ref().map(regex::Regex::as_str)),
+        ),
+        ("no_keepalive", serde_json::json!(args.no_keepalive)),
+        ("throttle_kbps", serde_json::json!(args.throttle_kbps)),
+    ]
+    .into_iter()
+    .filter(|(_, option)| !option.is_null() && *option != serde_json::Value::Bool(false))
+    .map(|(name, option)| (name.to_string(), option))
+    .collect();
+    if !run_options.is_empty() {
+        value = serde_json::json!({ "config": value, "options": run_options });
+    }
+    let mut canonical = String::new();
+    write_canonical_json(&value, &mut canonical);
+    format!("{:016x}", fnv1a_64(canonical.as_bytes()))
+}
+
+fn write_canonical_json(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical_json(&map[key], out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(item, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+/// Identifies a test across runs: a hash of the environment name and the URL with the
+/// `test_id_ignored_params` query parameters removed, so volatile values don't change it.
+fn test_id(env_name: &str, url: &str, ignored_params: &[String]) -> String {
+    let stable_url = match url.split_once('?') {
+        Some((base, query)) => {
+            let kept: Vec<&str> = query
+                .split('&')
+                .filter(|pair| {
+                    let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+                    !ignored_params.iter().any(|ignored| ignored == name)
+                })
+                .collect();
+            if kept.is_empty() {
+                base.to_string()
+            } else {
+                format!("{}?{}", base, kept.join("&"))
+            }
+        }
+        None => url.to_string(),
+    };
+    let key = format!("{}\n{}", env_name, stable_url);
+    format!("{:016x}", fnv1a_64(key.as_bytes()))
+}
+
+// FNV-1a is used instead of std's hasher because its output must not change between builds
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Prints a summary of URLs whose outcome changed since the previous watch iteration.
+fn print_watch_delta(
+    previous: &HashMap<(String, String), bool>,
+    current: &HashMap<(String, String), bool>,
+    args: &Args,
+) {
+    let mut newly_failing = Vec::new();
+    let mut recovered = Vec::new();
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+
+    for (key, passed) in current {
+        match previous.get(key) {
+            Some(true) if !passed => newly_failing.push(key),
+            Some(false) if *passed => recovered.push(key),
+            Some(_) => {}
+            None => added.push(key),
+        }
+    }
+    for key in previous.keys() {
+        if !current.contains_key(key) {
+            removed.push(key);
+        }
+    }
+
+    if newly_failing.is_empty() && recovered.is_empty() && added.is_empty() && removed.is_empty() {
+        println!("\nNo changes since the previous iteration.");
+        return;
+    }
+
+    println!("\n--- Changes Since Previous Iteration ---");
+    for (label, color, keys) in [
+        ("NEWLY FAILING", COLOR_RED, &mut newly_failing),
+        ("RECOVERED", COLOR_GREEN, &mut recovered),
+        ("ADDED", "", &mut added),
+        ("REMOVED", "", &mut removed),
+    ] {
+        keys.sort();
+        for (env_name, url) in keys.iter() {
+            println!(
+                "{} | {: <10} | {}",
+                paint(&format!("{: <13}", label), color, args),
+                env_name,
+                url
+            );
+        }
+    }
+}
+
+/// Compares the run against the baseline, prints the reports, runs the hooks, and decides
+/// the exit code.
+async fn finish_run(
+    mut run: TestRun,
+    config: &Config,
+    args: &Args,
+    baseline: Option<&Baseline>,
+    result_stream: Option<&stream::ResultStream>,
+) -> Result<ExitDecision, Box<dyn std::error::Error>> {
+    let anomaly_check = match run.nothing_ran {
+        None => anomaly::flag_anomalies(&mut run.results, args, config)?,
+        Some(_) => None,
+    };
+    let comparison = baseline.map(|baseline| compare_with_baseline(baseline, &run.results));
+    let mut decision = match &run.nothing_ran {
+        _ if run.interrupted => ExitDecision {
+            code: interrupt::EXIT_INTERRUPTED,
+            reason: format!(
+                "Exit code {}: interrupted, the report only covers the {} tests that completed.",
+                interrupt::EXIT_INTERRUPTED,
+                run.results.len()
+            ),
+        },
+        Some(reason) => nothing_ran_decision(reason, args.empty_ok),
+        None => decide_exit_code(args.fail_on, &run.results, comparison.as_ref()),
+    };
+
+    let mut summary = run_summary(&run, config, args, decision.code);
+    let run_metadata = metadata::RunMetadata::new(args, &run);
+
+    summary.env_reports = report_results(&run.results, config, args)?;
+    print_statistics(&run.results, args);
+    if args.histogram {
+        print_latency_histograms(&run.results);
+    }
+    if args.group_errors {
+        error_groups::print_error_groups(&run.results, args.group_errors_top);
+    }
+    rate_limit::print_warnings(&run.results, &config.rate_limit);
+    if let Some(check) = &anomaly_check {
+        anomaly::print_anomalies(&run.results, check, args);
+    }
+    if !args.stable_output && run.nothing_ran.is_none() {
+        print_resource_usage(&summary.resource_usage);
+    }
+    if let Some(sla) = &summary.sla {
+        print_sla_summary(sla, args);
+    }
+    print_status_distribution(&summary.status_codes);
+    print_health_scores(&summary.health_scores);
+    print_mixed_states(&summary.mixed_states);
+    print_mixed_annotations(&mixed_annotations(&run.results));
+    verdict::print_early_verdicts(&summary.decided_early);
+    if !summary.skipped_environments.is_empty() {
+        println!("\n--- Skipped Environments ---");
+        for (env_name, reason) in &summary.skipped_environments {
+            println!("{: <10} | {}", truncate_string(env_name, 10), reason);
+        }
+    }
+    if !summary.skipped_paths.is_empty() {
+        println!("\n--- Skipped Paths ---");
+        for (env_name, paths) in &summary.skipped_paths {
+            for skipped in paths {
+                println!(
+                    "{: <10} | {} {} | {}",
+                    truncate_string(env_name, 10),
+                    skipped.method,
+                    skipped.path,
+                    skipped.reason
+                );
+            }
+        }
+    }
+    if let Some(csv_path) = &args.status_summary_csv {
+        csv_report::write_status_summary_csv(csv_path, &summary.status_codes)?;
+        println!("Status code summary saved to: {}", csv_path);
+    }
+    if let Some(csv_path) = &args.state_summary_csv {
+        csv_report::write_state_summary_csv(
+            csv_path,
+            &state_rollups(&run.results, &summary.skipped_paths),
+        )?;
+        println!("State summary saved to: {}", csv_path);
+    }
+
+    let mut sinks = sinks::from_args(args, config, &run_metadata, run.started_at)?;
+    let (passing, failing) = report_order(&run.results, args);
+    for res in passing
+        .into_iter()
+        .chain(failing)
+        .chain(&run.skipped_results)
+    {
+        for sink in sinks.iter_mut() {
+            sink.record(res)?;
+        }
+    }
+    for sink in sinks.iter_mut() {
+        println!("{}", sink.finish(&summary)?);
+    }
+
+    if let (Some(baseline), Some(comparison)) = (baseline, &comparison) {
+        print_baseline_comparison(
+            baseline,
+            comparison,
+            Some(&run.config_hash),
+            &args.label,
+            summary.connection_mode,
+            summary.throttle_kbps,
+        );
+    }
+    if let Some([env_a, env_b]) = args.compare_envs.as_deref() {
+        print_environment_comparison(&run.results, env_a, env_b);
+    }
+    println!("\n{}", decision.reason);
+
+    if let Some(result_stream) = result_stream {
+        result_stream.complete(&summary).await;
+    }
+
+    let hooks = &config.hooks;
+    let failure_hook = hooks.on_failure.as_ref().filter(|_| decision.code != 0);
+    for command in hooks.on_complete.iter().chain(failure_hook) {
+        let hook_ok = run_hook(command, &summary, hooks.timeout_secs).await;
+        if !hook_ok && hooks.hook_failures_fail_run && decision.code == 0 {
+            decision = ExitDecision {
+                code: 1,
+                reason: format!("Exit code 1 due to failed hook '{}'.", command),
+            };
+            println!("{}", decision.reason);
+        }
+    }
+
+    Ok(decision)
+}
+
+/// The summary of a completed run, without the per-environment reports written later.
+fn run_summary(run: &TestRun, config: &Config, args: &Args, exit_code: u8) -> RunSummary {
+    let passed = run.results.iter().filter(|res| res.passed).count();
+    RunSummary {
+        labels: args.label.clone(),
+        total: run.results.len(),
+        passed,
+        failed: run.results.len() - passed,
+        duration_secs: run.duration.as_secs_f64(),
+        config_hash: run.config_hash.clone(),
+        report_path: args.output.first().cloned(),
+        env_reports: BTreeMap::new(),
+        exit_code,
+        interrupted: run.interrupted,
+        sla: summarize_sla(config, &run.results),
+        status_codes: status_distribution(&run.results),
+        health_scores: health_scores(&run.results),
+        mixed_states: mixed_states(&run.results),
+        connection_mode: ConnectionMode::from_args(args),
+        throttle_kbps: args.throttle_kbps,
+        skipped_environments: run.schedule_skipped.clone(),
+        skipped_paths: run.skipped_paths.clone(),
+        decided_early: run.decided_early.clone(),
+        resource_usage: run.usage.clone(),
+    }
+}
+
+/// Runs a hook command through the shell with the run summary in its environment and as
+/// JSON on stdin. Returns whether the hook exited successfully within the timeout.
+async fn run_hook(command: &str, summary: &RunSummary, timeout_secs: u64) -> bool {
+    use tokio::io::AsyncWriteExt;
+
+    let mut process = if cfg!(windows) {
+        let mut process = tokio::process::Command::new("cmd");
+        process.arg("/C").arg(command);
+        process
+    } else {
+        let mut process = tokio::process::Command::new("sh");
+        process.arg("-c").arg(command);
+        process
+    };
+    process
+        .env("TOTAL", summary.total.to_string())
+        .env("PASSED", summary.passed.to_string())
+        .env("FAILED", summary.failed.to_string())
+        .env("DURATION", format!("{:.3}", summary.duration_secs))
+        .env("REPORT_PATH", summary.report_path.as_deref().unwrap_or(""))
+        .env("RUN_LABEL", summary.labels.join(", "))
+        .stdin(std::process::Stdio::piped())
+        .kill_on_drop(true);
+
+    println!("\nRunning hook: {}", command);
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Hook '{}' could not be started: {}", command, e);
+            return false;
+        }
+    };
+
+    let json = serde_json::to_vec(summary).unwrap_or_default();
+    let timeout = std::time::Duration::from_secs(timeout_secs);
+    // The timeout covers the write too: a hook that neither reads its stdin nor exits would
+    // otherwise block it forever once the pipe is full
+    let finished = tokio::time::timeout(timeout, async {
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that doesn't read its stdin is fine, so write errors are ignored
+            let _ = stdin.write_all(&json).await;
+        }
+        child.wait().await
+    })
+    .await;
+    match finished {
+        Ok(Ok(status)) if status.success() => {
+            println!("Hook '{}' finished successfully.", command);
+            true
+        }
+        Ok(Ok(status)) => {
+            eprintln!("Hook '{}' failed with {}.", command, status);
+            false
+        }
+        Ok(Err(e)) => {
+            eprintln!("Hook '{}' failed: {}", command, e);
+            false
+        }
+        Err(_) => {
+            let _ = child.kill().await;
+            eprintln!(
+                "Hook '{}' timed out after {}s and was killed.",
+                command, timeout_secs
+            );
+            false
+        }
+    }
+}
+
+fn compare_with_baseline(baseline: &Baseline, results: &[UrlTestResult]) -> BaselineComparison {
+    let mut regressions = Vec::new();
+    let mut fixed = Vec::new();
+    for res in results {
+        let key = (res.environment_name.clone(), res.url.clone());
+        // URLs missing from the baseline have nothing to regress from
+        match baseline.outcomes.get(&res.test_id) {
+            Some(true) if !res.passed => regressions.push(key),
+            Some(false) if res.passed => fixed.push(key),
+            _ => {}
+        }
+    }
+    regressions.sort();
+    fixed.sort();
+    BaselineComparison { regressions, fixed }
+}
+
+fn print_baseline_comparison(
+    baseline: &Baseline,
+    comparison: &BaselineComparison,
+    config_hash: Option<&str>,
+    labels: &[String],
+    connection_mode: ConnectionMode,
+    throttle_kbps: Option<u32>,
+) {
+    println!("\n--- Comparison With Baseline ({}) ---", baseline.path);
+    let describe = |labels: &[String]| {
+        if labels.is_empty() {
+            "unlabelled".to_string()
+        } else {
+            labels.join(", ")
+        }
+    };
+    println!(
+        "Baseline: {} -> current run: {}",
+        describe(&baseline.labels),
+        describe(labels)
+    );
+    if let Some(current) = config_hash {
+        if baseline.config_hashes.iter().any(|hash| hash != current) {
+            println!(
+                "{}WARNING: the baseline was produced by a different config (hash {}, current {}); differences may come from the config rather than the service.{}",
+                COLOR_RED,
+                baseline.config_hashes.join(", "),
+                current,
+                COLOR_RESET
+            );
+        }
+    }
+    let current_mode = connection_mode.to_string();
+    if baseline
+        .connection_modes
+        .iter()
+        .any(|mode| *mode != current_mode)
+    {
+        println!(
+            "{}WARNING: the baseline used {} connections and this run {}; durations aren't comparable across connection modes.{}",
+            COLOR_RED,
+            baseline.connection_modes.join(", "),
+            current_mode,
+            COLOR_RESET
+        );
+    }
+    if baseline
+        .throttle_rates
+        .iter()
+        .any(|rate| *rate != throttle_kbps)
+    {
+        let rates: Vec<String> = baseline
+            .throttle_rates
+            .iter()
+            .map(|rate| throttle::describe(*rate))
+            .collect();
+        println!(
+            "{}WARNING: the baseline was read {} and this run {} (--throttle-kbps); durations aren't comparable across link speeds.{}",
+            COLOR_RED,
+            rates.join(", "),
+            throttle::describe(throttle_kbps),
+            COLOR_RESET
+        );
+    }
+    for (env_name, url) in &comparison.regressions {
+        println!(
+            "{}REGRESSED{} | {: <10} | {}",
+            COLOR_RED, COLOR_RESET, env_name, url
+        );
     }
-
-    if config.paths.is_empty() {
-        println!("No paths found in the configuration file. Exiting.");
-        return Ok(());
+    for (env_name, url) in &comparison.fixed {
+        println!(
+            "{}FIXED{}     | {: <10} | {}",
+            COLOR_GREEN, COLOR_RESET, env_name, url
+        );
     }
+    println!(
+        "{} regressions, {} fixed since the baseline.",
+        comparison.regressions.len(),
+        comparison.fixed.len()
+    );
+}
 
-    let mut all_results: Vec<UrlTestResult> = Vec::new();
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()?;
-
-    let total_test_start_time = Instant::now();
+/// A failure that recovered when --reverify retried it doesn't fail the run.
+fn counts_as_failure(result: &UrlTestResult) -> bool {
+    !result.passed && result.reverified != Some(ReverifyOutcome::RecoveredOnRetry)
+}
 
-    let environments_to_run: HashMap<String, Environment> = if let Some(env_name) = &args.env {
-        let mut filtered_envs = HashMap::new();
-        if let Some(env_data) = config.environments.get(env_name) {
-            filtered_envs.insert(
-                env_name.clone(),
-                Environment {
-                    baseurl: env_data.baseurl.clone(),
-                },
-            );
-            println!("\nRunning tests for specific environment: {}", env_name);
-        } else {
-            eprintln!(
-                "Error: Environment '{}' not found in config.toml.",
-                env_name
-            );
-            return Err(format!("Environment '{}' not found.", env_name).into());
+/// The single place that turns the `--fail-on` policy and the run's outcome into an exit code.
+fn decide_exit_code(
+    policy: FailOn,
+    results: &[UrlTestResult],
+    comparison: Option<&BaselineComparison>,
+) -> ExitDecision {
+    let (count, what) = match policy {
+        FailOn::Any => (
+            results.iter().filter(|res| counts_as_failure(res)).count(),
+            "failures",
+        ),
+        FailOn::Critical => (
+            results
+                .iter()
+                .filter(|res| counts_as_failure(res) && res.severity == Severity::Critical)
+                .count(),
+            "critical failures",
+        ),
+        FailOn::Regressions => (
+            comparison.map_or(0, |comparison| comparison.regressions.len()),
+            "regressions versus baseline",
+        ),
+        FailOn::Anomalies => (
+            results
+                .iter()
+                .filter(|res| res.anomaly == Some(true))
+                .count(),
+            "latency anomalies",
+        ),
+        FailOn::None => (0, "failures counted (--fail-on none)"),
+    };
+    if count > 0 {
+        ExitDecision {
+            code: 1,
+            reason: format!("Exit code 1 due to {} {}.", count, what),
         }
-        filtered_envs
     } else {
-        println!("\nRunning tests for ALL environments found in config.");
-        config.environments
-    };
-
-    // Clone both the configured key and code for use in the async tasks
-    let configured_app_error_key = config.app_error_key_to_fail.clone();
-    let configured_app_error_code = config.app_error_code_to_fail.clone();
+        ExitDecision {
+            code: 0,
+            reason: format!("Exit code 0: no {}.", what),
+        }
+    }
+}
 
-    for (env_name, env_data) in environments_to_run {
-        println!(
-            "\n--- Testing Environment: {} (Base URL: {}) ---",
-            env_name, env_data.baseurl
-        );
+/// A run without results has nothing to pass or fail: it gets its own exit code, so callers
+/// can tell it apart from a run where everything passed.
+fn nothing_ran_decision(reason: &str, empty_ok: bool) -> ExitDecision {
+    if empty_ok {
+        ExitDecision {
+            code: 0,
+            reason: format!(
+                "Exit code 0: no tests ran ({}), allowed by --empty-ok.",
+                reason
+            ),
+        }
+    } else {
+        ExitDecision {
+            code: EXIT_NOTHING_RAN,
+            reason: format!("Exit code {}: no tests ran ({}).", EXIT_NOTHING_RAN, reason),
+        }
+    }
+}
 
-        let mut handles = Vec::new();
-        let total_paths_for_env = config.paths.len();
+/// Prints, for every path run in both environments, whether the status or the normalized
+/// body differs between them.
+fn print_environment_comparison(results: &[UrlTestResult], env_a: &str, env_b: &str) {
+    // Keyed by position rather than path, since a path may be listed twice (e.g. with
+    // different methods); the health gate comes first, as it ran first
+    let by_path = |env_name: &str| -> BTreeMap<(bool, usize), &UrlTestResult> {
+        results
+            .iter()
+            .filter(|res| res.environment_name == env_name)
+            .map(|res| ((!res.health_gate, res.path_index), res))
+            .collect()
+    };
+    let results_a = by_path(env_a);
+    let results_b = by_path(env_b);
 
-        println!("\nInitiating requests for environment '{}'...", env_name);
-
-        for path in &config.paths {
-            let client = client.clone();
-            let env_name_clone = env_name.clone();
-            let path_clone = path.clone();
-            // Clone configured key and code for each spawned task
-            let app_error_key_for_task = configured_app_error_key.clone();
-            let app_error_code_for_task = configured_app_error_code.clone();
-
-            let state_param = path_clone
-                .split_once("State=")
-                .and_then(|(_, rest)| rest.split_once('&'))
-                .map(|(state, _)| state.to_string())
-                .or_else(|| {
-                    path_clone
-                        .split_once("State=")
-                        .map(|(_, state)| state.to_string())
-                });
+    let mut paths: Vec<((bool, usize), &str)> = results_a
+        .iter()
+        .chain(results_b.iter())
+        .map(|(key, res)| (*key, res.path.as_str()))
+        .collect();
+    paths.sort_by_key(|(key, _)| *key);
+    paths.dedup_by_key(|(key, _)| *key);
 
-            let full_url = format!("{}{}", env_data.baseurl, path_clone);
-            let url_clone = full_url.clone();
-
-            let handle = tokio::spawn(async move {
-                let start_time = Instant::now();
-                let mut result = UrlTestResult {
-                    environment_name: env_name_clone,
-                    url: url_clone.clone(),
-                    status_code: None,
-                    response_body_preview: String::new(),
-                    passed: false,
-                    error_message: None,
-                    duration_secs: 0.0,
-                    state_param: state_param,
-                };
+    println!("\n--- Environment Comparison ({} vs {}) ---", env_a, env_b);
+    println!(
+        "{: <40} | {: <10} | {: <10} | {: <15}",
+        "Path",
+        truncate_string(env_a, 10),
+        truncate_string(env_b, 10),
+        "Result"
+    );
+    println!("{}", "-".repeat(84));
 
-                match client.get(&url_clone).send().await {
-                    Ok(response) => {
-                        result.status_code = Some(response.status().as_u16());
-                        let status = response.status();
-
-                        let body_text = match response.text().await {
-                            Ok(text) => text,
-                            Err(e) => {
-                                result.response_body_preview = format!("Error reading body: {}", e);
-                                result.passed = false;
-                                result.error_message =
-                                    Some(format!("Failed to read response body: {}", e));
-                                "".to_string()
-                            }
-                        };
-
-                        result.response_body_preview = body_text.chars().take(100).collect();
-
-                        if status.is_success() {
-                            let mut app_error_detected = false;
-                            // Check if a specific application error code is configured
-                            if let Some(code_to_fail) = app_error_code_for_task {
-                                let key_to_search = app_error_key_for_task.as_str(); // Use the configured key
-                                                                                     // Dynamically construct the search string using both key and code
-                                let search_string =
-                                    format!(r#""{}":"{}""#, key_to_search, code_to_fail);
-                                if body_text.contains(&search_string) {
-                                    app_error_detected = true;
-                                    // If parsing ApiResponse fails, use the configured key and code in the message
-                                    match serde_json::from_str::<ApiResponse>(&body_text) {
-                                        Ok(api_response) => {
-                                            result.error_message = Some(format!(
-                                                "App Error ({}: {}): {}",
-                                                key_to_search, code_to_fail, api_response.message
-                                            ));
-                                        }
-                                        Err(_) => {
-                                            result.error_message = Some(format!(
-                                                "App Error ({}: {}): message parsing failed.",
-                                                key_to_search, code_to_fail
-                                            ));
-                                        }
-                                    }
-                                }
-                            }
-
-                            if app_error_detected {
-                                result.passed = false; // Mark as failed due to application error
-                            } else {
-                                result.passed = true; // Passed if HTTP 2xx and no configured app error
-                            }
-                        } else {
-                            result.passed = false; // Failed if HTTP status is not 2xx
-                            result.error_message = Some(format!("HTTP Status Error: {}", status));
-                        }
-                    }
-                    Err(e) => {
-                        result.error_message = Some(e.to_string());
-                        result.passed = false;
-                    }
+    let mut differing = 0;
+    for (key, path) in &paths {
+        let status = |res: Option<&&UrlTestResult>| match res {
+            Some(res) => res.status_code.map_or("N/A".to_string(), |s| s.to_string()),
+            None => "not run".to_string(),
+        };
+        let (res_a, res_b) = (results_a.get(key), results_b.get(key));
+        let outcome = match (res_a, res_b) {
+            (Some(a), Some(b)) if a.status_code != b.status_code => "STATUS DIFFERS",
+            (Some(a), Some(b)) => {
+                let body_a = normalize_body(a.response_body.as_deref().unwrap_or(""));
+                let body_b = normalize_body(b.response_body.as_deref().unwrap_or(""));
+                if body_a != body_b {
+                    "BODY DIFFERS"
+                } else {
+                    "same"
                 }
-                result.duration_secs = start_time.elapsed().as_secs_f64();
-                result
-            });
-            handles.push(handle);
-        }
-
+            }
+            _ => "MISSING",
+        };
+        let color = if outcome == "same" {
+            COLOR_GREEN
+        } else {
+            differing += 1;
+            COLOR_RED
+        };
         println!(
-            "Waiting for {} responses from '{}'...",
-            total_paths_for_env, env_name
+            "{: <40} | {: <10} | {: <10} | {}{}{}",
+            truncate_string(path, 40),
+            status(res_a),
+            status(res_b),
+            color,
+            outcome,
+            COLOR_RESET
         );
-        for handle in handles {
-            let result = handle.await?;
-            all_results.push(result);
-        }
     }
+    println!("{} of {} paths differ.", differing, paths.len());
+}
 
-    let total_test_end_time = Instant::now();
-    let total_duration = total_test_end_time.duration_since(total_test_start_time);
+// JSON bodies are compared by value so key order and formatting don't count as differences;
+// other bodies are compared with whitespace collapsed
+fn normalize_body(body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(value) => {
+            let mut canonical = String::new();
+            write_canonical_json(&value, &mut canonical);
+            canonical
+        }
+        Err(_) => body.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
 
-    // --- Separate and print tables for passing and then failing tests ---
-    let mut failing_results: Vec<UrlTestResult> = Vec::new();
-    let mut passing_results: Vec<UrlTestResult> = Vec::new();
+/// Splits the results into passing and failing, each sorted the way the tables and the
+/// report files list them.
+fn report_order<'a>(
+    all_results: &'a [UrlTestResult],
+    args: &Args,
+) -> (Vec<&'a UrlTestResult>, Vec<&'a UrlTestResult>) {
+    let mut failing_results: Vec<&UrlTestResult> = Vec::new();
+    let mut passing_results: Vec<&UrlTestResult> = Vec::new();
 
     for res in all_results {
-        // Consume all_results here by moving elements
         if res.passed {
             passing_results.push(res);
         } else {
@@ -265,79 +5695,786 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    // Sort passing results
+    // Sort passing results; --stable-output orders by URL so every row has a fixed place
+    let secondary = |a: &UrlTestResult, b: &UrlTestResult| {
+        if args.stable_output {
+            a.url.cmp(&b.url)
+        } else {
+            a.state_param.cmp(&b.state_param)
+        }
+    };
     passing_results.sort_by(|a, b| {
         a.environment_name
             .cmp(&b.environment_name)
-            .then_with(|| a.state_param.cmp(&b.state_param))
+            .then_with(|| secondary(a, b))
     });
-    // Sort failing results
+    // Sort failing results, worst failures first when requested
     failing_results.sort_by(|a, b| {
-        a.environment_name
-            .cmp(&b.environment_name)
-            .then_with(|| a.state_param.cmp(&b.state_param))
+        let by_severity = if args.sort_severity {
+            a.failure_kind.cmp(&b.failure_kind)
+        } else {
+            std::cmp::Ordering::Equal
+        };
+        by_severity
+            .then_with(|| a.environment_name.cmp(&b.environment_name))
+            .then_with(|| secondary(a, b))
     });
+    (passing_results, failing_results)
+}
 
-    println!("\nTotal Test Duration: {:.2?}", total_duration);
+/// Prints the passing/failing tables and writes the --output-per-env reports, returning
+/// the files written.
+fn report_results(
+    all_results: &[UrlTestResult],
+    config: &Config,
+    args: &Args,
+) -> Result<BTreeMap<String, csv_report::EnvReport>, Box<dyn std::error::Error>> {
+    // --- Separate and print tables for passing and then failing tests ---
+    let (passing_results, failing_results) = report_order(all_results, args);
 
-    // Print Passing Tests Table FIRST
-    if !passing_results.is_empty() {
-        println!("\n--- Passing Tests Report ({}) ---", passing_results.len());
-        print_report_header();
-        for res in &passing_results {
-            print_test_result_row(res);
+    // Print Passing Tests Table FIRST (both tables are left out by --stats-only)
+    if !args.stats_only {
+        if !passing_results.is_empty() {
+            println!("\n--- Passing Tests Report ({}) ---", passing_results.len());
+            print_report_header(config.duration_measure, false);
+            for res in passing_results.iter().copied() {
+                print_test_result_row(res, args);
+            }
+            println!("\n--- Passing Tests Report End ---");
+        } else {
+            println!("\n--- No Passing Tests Detected ---");
         }
-        println!("\n--- Passing Tests Report End ---");
-    } else {
-        println!("\n--- No Passing Tests Detected ---");
     }
 
     // Print Failing Tests Table SECOND
-    if !failing_results.is_empty() {
+    if !failing_results.is_empty() && !args.stats_only {
         println!("\n--- Failing Tests Report ({}) ---", failing_results.len());
-        print_report_header();
-        for res in &failing_results {
-            print_test_result_row(res);
+        print_report_header(config.duration_measure, args.watch.is_some());
+        let (shown, hidden) = match args.max_failures_shown {
+            Some(max) => sample_failures(&failing_results, max),
+            None => (failing_results.clone(), BTreeMap::new()),
+        };
+        for res in shown {
+            print_test_result_row(res, args);
+        }
+        let output_formats: Vec<sinks::OutputFormat> = args
+            .output
+            .iter()
+            .map(|path| sinks::OutputFormat::from_path(path))
+            .chain(args.json.iter().map(|_| sinks::OutputFormat::Json))
+            .collect();
+        let see = if output_formats.contains(&sinks::OutputFormat::Csv) {
+            " (see CSV)"
+        } else if output_formats.contains(&sinks::OutputFormat::Json) {
+            " (see JSON)"
+        } else if output_formats.contains(&sinks::OutputFormat::Ndjson) {
+            " (see NDJSON)"
+        } else {
+            ""
+        };
+        for (env_name, count) in hidden {
+            println!("… and {} more failures in '{}'{}", count, env_name, see);
         }
         println!("\n--- Failing Tests Report End ---");
+
+        if args.verbose {
+            print_failure_details(&failing_results, args);
+        }
+
+        if args.reverify {
+            print_reverify_report(&failing_results, args);
+        }
     } else {
         // This case will not be hit if there are passing tests but no failing ones,
         // as the "No Passing Tests Detected" message implies total absence.
         // It serves for the scenario where *all* tests failed or none ran.
     }
+    let warnings: Vec<&UrlTestResult> = passing_results
+        .iter()
+        .copied()
+        .filter(|res| res.warning.is_some())
+        .collect();
+    if !warnings.is_empty() && !args.stats_only {
+        println!("\n--- Warnings ({}) ---", warnings.len());
+        for res in warnings {
+            println!(
+                "{} | {: <10} | {} | {}",
+                paint("WARN", COLOR_YELLOW, args),
+                truncate_string(&res.environment_name, 10),
+                res.url,
+                res.warning.as_deref().unwrap_or_default()
+            );
+        }
+    }
     // --- END REPORTING SECTION ---
 
-    if let Some(output_path) = args.output {
-        println!("\nSaving report to CSV: {}", output_path);
-        let file = fs::File::create(&output_path)?;
-        let mut wtr = csv::Writer::from_writer(file);
+    // Reconstruct all_results for CSV output (preserving order for CSV might be less critical,
+    // but ensuring all are written is).
+    let mut all_results_for_csv: Vec<&UrlTestResult> = Vec::new();
+    all_results_for_csv.extend(passing_results); // Add passing first
+    all_results_for_csv.extend(failing_results); // Then add failing
 
-        // Reconstruct all_results for CSV output (preserving order for CSV might be less critical,
-        // but ensuring all are written is).
-        let mut all_results_for_csv: Vec<UrlTestResult> = Vec::new();
-        all_results_for_csv.extend(passing_results); // Add passing first
-        all_results_for_csv.extend(failing_results); // Then add failing
+    let mut env_reports = BTreeMap::new();
+    if let Some(dir) = &args.output_per_env {
+        env_reports = csv_report::write_per_env_csv(
+            dir,
+            &all_results_for_csv,
+            &config.server_timing_metrics,
+            &annotation_names(config),
+        )?;
+        println!("\nPer-environment CSV reports saved to {}:", dir);
+        for (env_name, report) in &env_reports {
+            println!(
+                "{: <10} | {} ({} rows)",
+                truncate_string(env_name, 10),
+                report.path,
+                report.rows
+            );
+        }
+    }
+
+    Ok(env_reports)
+}
 
-        for res in all_results_for_csv {
-            wtr.serialize(res)?;
+/// Picks at most `max` failures per environment for `--max-failures-shown`, taking one of
+/// each failure kind in turn so a flood of one kind doesn't hide the others. The picked
+/// rows keep their order in `failing`; also returns how many were left out per environment.
+fn sample_failures<'a>(
+    failing: &[&'a UrlTestResult],
+    max: usize,
+) -> (Vec<&'a UrlTestResult>, BTreeMap<String, usize>) {
+    // Environment -> failure kind -> indexes into `failing`, in order
+    let mut by_env: BTreeMap<&str, BTreeMap<Option<FailureKind>, Vec<usize>>> = BTreeMap::new();
+    for (index, res) in failing.iter().enumerate() {
+        by_env
+            .entry(&res.environment_name)
+            .or_default()
+            .entry(res.failure_kind)
+            .or_default()
+            .push(index);
+    }
+    let mut picked = vec![false; failing.len()];
+    let mut hidden = BTreeMap::new();
+    for (env_name, kinds) in by_env {
+        let total: usize = kinds.values().map(Vec::len).sum();
+        let mut queues: Vec<std::vec::IntoIter<usize>> =
+            kinds.into_values().map(Vec::into_iter).collect();
+        let mut taken = 0;
+        while taken < max.min(total) {
+            for queue in queues.iter_mut() {
+                if taken == max {
+                    break;
+                }
+                if let Some(index) = queue.next() {
+                    picked[index] = true;
+                    taken += 1;
+                }
+            }
+        }
+        if total > taken {
+            hidden.insert(env_name.to_string(), total - taken);
         }
-        wtr.flush()?;
-        println!("CSV report saved successfully.");
     }
+    let shown = failing
+        .iter()
+        .zip(picked)
+        .filter(|(_, picked)| *picked)
+        .map(|(res, _)| *res)
+        .collect();
+    (shown, hidden)
+}
 
-    Ok(())
+/// Lists each failure with the outcome of its --reverify retry.
+fn print_reverify_report(failing_results: &[&UrlTestResult], args: &Args) {
+    let count = |outcome| {
+        failing_results
+            .iter()
+            .filter(|res| res.reverified == Some(outcome))
+            .count()
+    };
+    let confirmed = count(ReverifyOutcome::Confirmed);
+    let recovered = count(ReverifyOutcome::RecoveredOnRetry);
+    let not_retried = failing_results.len() - confirmed - recovered;
+    println!(
+        "\n--- Re-verification ({} confirmed, {} recovered on retry{}) ---",
+        confirmed,
+        recovered,
+        if not_retried > 0 {
+            format!(", {} not retried", not_retried)
+        } else {
+            String::new()
+        }
+    );
+    for res in failing_results {
+        let (color, outcome) = match res.reverified {
+            Some(ReverifyOutcome::Confirmed) => (COLOR_RED, ReverifyOutcome::Confirmed.to_string()),
+            Some(outcome) => (COLOR_GREEN, outcome.to_string()),
+            None => ("", "not retried".to_string()),
+        };
+        let retry_duration = res
+            .reverify_duration_secs
+            .filter(|_| !(args.stable_output || args.no_duration))
+            .map_or("-".to_string(), |secs| format!("{:.3}s", secs));
+        println!(
+            "{} | {: <10} | retry {: >8} | {}",
+            paint(&format!("{: <18}", outcome), color, args),
+            truncate_string(&res.environment_name, 10),
+            retry_duration,
+            res.url
+        );
+    }
+}
+
+/// How often the `--dashboard` page reloads without --dashboard-refresh or --watch.
+const DEFAULT_DASHBOARD_REFRESH_SECS: u64 = 30;
+
+/// Most time the DNS precheck waits for an environment's host to resolve.
+const DNS_PRECHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Outcome of looking up an environment's host before its tests are dispatched.
+struct DnsPrecheck {
+    host: String,
+    elapsed: std::time::Duration,
+    // Lowest of the addresses found, which --max-per-host groups environments by
+    addr: Option<std::net::IpAddr>,
+    // Why the host didn't resolve
+    error: Option<String>,
+}
+
+/// Looks up the host of `baseurl` once. Nothing is checked for IP addresses, URLs that don't
+/// parse, or hosts reached through a proxy, which resolves them itself (and may be the only
+/// one able to). Requests don't use the addresses found: each one still resolves the host.
+async fn precheck_dns(baseurl: &str) -> Option<DnsPrecheck> {
+    let url = reqwest::Url::parse(baseurl).ok()?;
+    if env_proxy_applies(&url) {
+        return None;
+    }
+    let host = url.host_str()?.to_string();
+    let bare_host = host.trim_start_matches('[').trim_end_matches(']');
+    if bare_host.parse::<std::net::IpAddr>().is_ok() {
+        return None;
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let start = Instant::now();
+    let lookup = tokio::time::timeout(
+        DNS_PRECHECK_TIMEOUT,
+        tokio::net::lookup_host((host.clone(), port)),
+    )
+    .await;
+    let (addr, error) = match lookup {
+        Ok(Ok(addrs)) => match addrs.map(|addr| addr.ip()).min() {
+            Some(addr) => (Some(addr), None),
+            None => (None, Some("no addresses found".to_string())),
+        },
+        Ok(Err(e)) => (None, Some(e.to_string())),
+        Err(_) => (
+            None,
+            Some(format!(
+                "lookup timed out after {}s",
+                DNS_PRECHECK_TIMEOUT.as_secs()
+            )),
+        ),
+    };
+    Some(DnsPrecheck {
+        host,
+        elapsed: start.elapsed(),
+        addr,
+        error,
+    })
+}
+
+/// Whether requests to `url` go through a proxy from the environment, following the HTTP
+/// client's rules: `HTTPS_PROXY` or `HTTP_PROXY` for its scheme, else `ALL_PROXY` (or their
+/// lowercase forms), unless `NO_PROXY` names the host or one of its parent domains.
+fn env_proxy_applies(url: &reqwest::Url) -> bool {
+    let first_env = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    };
+    // CGI scripts get HTTP_PROXY from the request's `Proxy` header, so the client ignores it
+    if std::env::var_os("REQUEST_METHOD").is_some() {
+        return false;
+    }
+    let proxy = match url.scheme() {
+        "https" => first_env(&["HTTPS_PROXY", "https_proxy"]),
+        "http" => first_env(&["HTTP_PROXY", "http_proxy"]),
+        _ => None,
+    }
+    .or_else(|| first_env(&["ALL_PROXY", "all_proxy"]));
+    let Some(host) = url.host_str().filter(|_| proxy.is_some()) else {
+        return false;
+    };
+    let no_proxy = first_env(&["NO_PROXY", "no_proxy"]).unwrap_or_default();
+    !no_proxy.split(',').map(str::trim).any(|entry| {
+        let domain = entry.trim_start_matches('.').to_ascii_lowercase();
+        entry == "*"
+            || (!domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain))))
+    })
+}
+
+/// Detects a failed host lookup in the error's source chain, returning the resolver's
+/// own message (e.g. "failed to lookup address information: Name or service not known").
+/// The connector only marks these errors by their "dns error" message, so that's what
+/// gets matched.
+fn dns_failure(e: &reqwest::Error) -> Option<String> {
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        if err.to_string().starts_with("dns error") {
+            return Some(
+                err.source()
+                    .map(|cause| cause.to_string())
+                    .unwrap_or_else(|| err.to_string()),
+            );
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Reads the body, up to `limit` bytes if given. Returns the text, whether it was cut off,
+/// and how many bytes were received.
+async fn read_body(
+    mut response: reqwest::Response,
+    limit: Option<usize>,
+    throttle_kbps: Option<u32>,
+) -> Result<(String, bool, u64), reqwest::Error> {
+    if limit.is_none() && throttle_kbps.is_none() {
+        // Counted before decoding, which replaces invalid UTF-8 and changes the length
+        let bytes = response.bytes().await?;
+        usage::count_bytes(bytes.len());
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        return Ok((text, false, bytes.len() as u64));
+    }
+
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut pacer = throttle_kbps.map(throttle::Pacer::new);
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut truncated = false;
+    let mut received = 0;
+    while let Some(chunk) = response.chunk().await? {
+        usage::count_bytes(chunk.len());
+        received += chunk.len() as u64;
+        let remaining = limit - bytes.len();
+        if let Some(pacer) = &mut pacer {
+            pacer.pace(chunk.len().min(remaining)).await;
+        }
+        if chunk.len() > remaining {
+            bytes.extend_from_slice(&chunk[..remaining]);
+            truncated = true;
+            break;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok((
+        String::from_utf8_lossy(&bytes).into_owned(),
+        truncated,
+        received,
+    ))
+}
+
+// Request and status lines aren't exposed by reqwest, so they are rebuilt as HTTP/1.1 would
+// send them. HTTP/2 compresses headers, so there these sizes are upper bounds.
+fn header_block_size(headers: &reqwest::header::HeaderMap) -> u64 {
+    headers
+        .iter()
+        // "name: value\r\n"
+        .map(|(name, value)| (name.as_str().len() + value.len() + 4) as u64)
+        .sum::<u64>()
+        + 2
+}
+
+/// Approximate size of a request on the wire: request line, headers (with `Host`) and body.
+fn request_size(request: &reqwest::Request) -> u64 {
+    let url = request.url();
+    let target = url.path().len() + url.query().map_or(0, |query| query.len() + 1);
+    // "GET /path?query HTTP/1.1\r\n"
+    let request_line = request.method().as_str().len() + target + 12;
+    let host = url.host_str().map_or(0, |host| {
+        // "host: example.com:8080\r\n"
+        host.len() + url.port().map_or(0, |port| port.to_string().len() + 1) + 8
+    });
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map_or(0, <[u8]>::len);
+    (request_line + host + body) as u64 + header_block_size(request.headers())
+}
+
+/// Approximate size of a response's status line and headers.
+fn response_head_size(response: &reqwest::Response) -> u64 {
+    // "HTTP/1.1 200 OK\r\n"
+    let status_line = 9 + response.status().to_string().len() + 2;
+    status_line as u64 + header_block_size(response.headers())
+}
+
+/// What was read from a streaming response.
+struct StreamOutcome {
+    text: String,
+    truncated: bool,
+    bytes: u64,
+    lines: u64,
+    matched: bool,
+}
+
+// Streams never end on their own, so without --max-body-read only this much text is kept
+const STREAM_TEXT_LIMIT: usize = 64 * 1024;
+
+/// Reads a streaming body until `max_secs` have passed, the stream ends, or a line matches
+/// `until`. Everything is counted, but only the first bytes are kept as the body text.
+async fn read_stream(
+    mut response: reqwest::Response,
+    streaming: &StreamingCheck,
+    limit: Option<usize>,
+) -> Result<StreamOutcome, reqwest::Error> {
+    let until = streaming.until.as_ref();
+    let deadline =
+        tokio::time::Instant::now() + std::time::Duration::from_secs_f64(streaming.max_secs);
+    let limit = limit.unwrap_or(STREAM_TEXT_LIMIT);
+    let mut kept: Vec<u8> = Vec::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut outcome = StreamOutcome {
+        text: String::new(),
+        truncated: false,
+        bytes: 0,
+        lines: 0,
+        matched: false,
+    };
+
+    'read: while let Ok(chunk) = tokio::time::timeout_at(deadline, response.chunk()).await {
+        let Some(chunk) = chunk? else {
+            break;
+        };
+        outcome.bytes += chunk.len() as u64;
+        usage::count_bytes(chunk.len());
+        let room = limit.saturating_sub(kept.len());
+        kept.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        outcome.truncated |= chunk.len() > room;
+        for byte in chunk.iter().copied() {
+            if byte != b'\n' {
+                // A line longer than `limit` is cut short: its start is kept for `until`
+                if line.len() < limit {
+                    line.push(byte);
+                }
+                continue;
+            }
+            outcome.lines += 1;
+            let text = String::from_utf8_lossy(&line);
+            if until
+                .as_ref()
+                .is_some_and(|re| re.is_match(text.trim_end_matches('\r')))
+            {
+                outcome.matched = true;
+                break 'read;
+            }
+            line.clear();
+        }
+    }
+    outcome.text = String::from_utf8_lossy(&kept).into_owned();
+    Ok(outcome)
+}
+
+/// Fails a response that was redirected to, or itself redirects to, one of the
+/// `fail_on_redirect_to_hosts`. Redirects that were followed show in the final URL; a redirect
+/// that wasn't shows in its `Location` header, resolved against the final URL.
+fn check_redirect_hosts(
+    requested: &str,
+    final_url: &reqwest::Url,
+    location: Option<&str>,
+    hosts: &[String],
+) -> Option<String> {
+    if hosts.is_empty() {
+        return None;
+    }
+    let listed = |url: &reqwest::Url| {
+        url.host_str().is_some_and(|host| {
+            hosts
+                .iter()
+                .any(|listed| listed.trim().eq_ignore_ascii_case(host))
+        })
+    };
+    if final_url.as_str() != requested && listed(final_url) {
+        return Some(format!(
+            "Redirected to {} ({}), listed in fail_on_redirect_to_hosts",
+            final_url.host_str().unwrap_or_default(),
+            final_url
+        ));
+    }
+    let target = location.and_then(|location| final_url.join(location).ok())?;
+    listed(&target).then(|| {
+        format!(
+            "Redirects to {} ({}), listed in fail_on_redirect_to_hosts",
+            target.host_str().unwrap_or_default(),
+            target
+        )
+    })
+}
+
+// Fails a streaming path whose `until` line never showed up
+fn check_stream_match(matched: Option<bool>, path: &PathConfig) -> Option<String> {
+    let streaming = path.streaming.as_ref()?;
+    let until = streaming.until.as_ref()?;
+    (matched == Some(false)).then(|| {
+        format!(
+            "No streamed line matched /{}/ within {}s",
+            until, streaming.max_secs
+        )
+    })
+}
+
+// The `range` option without its optional "bytes=" prefix
+fn range_spec(range: &str) -> &str {
+    let range = range.trim();
+    range.strip_prefix("bytes=").unwrap_or(range).trim()
+}
+
+fn range_header(range: &str) -> String {
+    format!("bytes={}", range_spec(range))
+}
+
+/// Checks a `range` option ("0-1023", "1000-" or "-500") and returns how many bytes it asks
+/// for, or `None` for an open-ended range.
+fn range_length(range: &str) -> Result<Option<usize>, String> {
+    let invalid = || {
+        format!(
+            "invalid range '{}', expected 'first-last', 'first-' or '-suffix_length'",
+            range
+        )
+    };
+    let (first, last) = range_spec(range).split_once('-').ok_or_else(invalid)?;
+    let offset = |text: &str| text.trim().parse::<usize>().map_err(|_| invalid());
+    match (first.trim().is_empty(), last.trim().is_empty()) {
+        (false, false) => {
+            let (first, last) = (offset(first)?, offset(last)?);
+            let span = last.checked_sub(first).ok_or_else(|| {
+                format!(
+                    "invalid range '{}', the last byte comes before the first",
+                    range
+                )
+            })?;
+            span.checked_add(1)
+                .map(Some)
+                .ok_or_else(|| format!("invalid range '{}', it is too long", range))
+        }
+        (false, true) => offset(first).map(|_| None),
+        (true, false) => match offset(last)? {
+            0 => Err(format!("invalid range '{}', the suffix length is 0", range)),
+            length => Ok(Some(length)),
+        },
+        (true, true) => Err(invalid()),
+    }
+}
+
+/// Checks the response to a `range` request: a 206 must say which part it sent in a
+/// `Content-Range` header, and a 200 means the server ignored the range. Returns the
+/// resource's total size, when known, and the failure, if any.
+fn check_range(
+    status: reqwest::StatusCode,
+    content_range: Option<&str>,
+    content_length: Option<u64>,
+    allow_full_response: bool,
+) -> (Option<u64>, Option<String>) {
+    match status {
+        reqwest::StatusCode::PARTIAL_CONTENT => match content_range {
+            // "bytes 0-1023/146515", or "bytes 0-1023/*" when the size is unknown
+            Some(content_range) => {
+                let total = content_range
+                    .trim()
+                    .strip_prefix("bytes ")
+                    .and_then(|rest| rest.split_once('/'))
+                    .and_then(|(_, total)| total.trim().parse().ok());
+                (total, None)
+            }
+            None => (
+                None,
+                Some("Partial response (206) without a Content-Range header".to_string()),
+            ),
+        },
+        reqwest::StatusCode::OK if allow_full_response => (content_length, None),
+        reqwest::StatusCode::OK => (
+            content_length,
+            Some("Server ignored the Range header and sent the full response (200)".to_string()),
+        ),
+        // Any other status fails (or passes) through the usual status checks
+        _ => (None, None),
+    }
+}
+
+/// Parses a `Server-Timing` header (`db;dur=12.3, app;desc="App";dur=48.9`) into durations
+/// keyed by metric name. Entries without a valid `dur` are ignored.
+fn parse_server_timing(header: &str) -> BTreeMap<String, f64> {
+    let mut metrics = BTreeMap::new();
+    for entry in header.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let Some(name) = parts.next().filter(|name| !name.is_empty()) else {
+            continue;
+        };
+        let duration = parts
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("dur"))
+            .and_then(|(_, value)| value.trim().trim_matches('"').parse::<f64>().ok());
+        if let Some(duration) = duration {
+            metrics.entry(name.to_string()).or_insert(duration);
+        }
+    }
+    metrics
+}
+
+// Returns a failure message when a metric exceeds its configured maximum; absent metrics pass
+fn check_server_timing(
+    server_timing: &BTreeMap<String, f64>,
+    limits: &HashMap<String, f64>,
+) -> Vec<String> {
+    let mut limits: Vec<(&String, &f64)> = limits.iter().collect();
+    limits.sort_by(|a, b| a.0.cmp(b.0));
+    limits
+        .into_iter()
+        .filter_map(|(metric, max)| {
+            let actual = server_timing.get(metric)?;
+            (actual > max).then(|| {
+                format!(
+                    "Server-Timing '{}' took {}ms, above the {}ms maximum",
+                    metric, actual, max
+                )
+            })
+        })
+        .collect()
+}
+
+// Returns a message when the duration exceeds the path's baseline by more than regression_pct
+fn check_duration_regression(duration_secs: f64, path: &PathConfig) -> Option<String> {
+    let baseline = path.baseline_duration_secs?;
+    let over_pct = (duration_secs / baseline - 1.0) * 100.0;
+    (over_pct > path.regression_pct).then(|| {
+        format!(
+            "Duration {:.3}s is {:.0}% above the {}s baseline (allowed {}%)",
+            duration_secs, over_pct, baseline, path.regression_pct
+        )
+    })
+}
+
+fn parse_method(method: &str) -> Result<reqwest::Method, String> {
+    reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+        .map_err(|_| format!("invalid HTTP method '{}'", method))
+}
+
+// Headers sent with every request, including setup and teardown requests
+fn default_request_headers(config: &Config) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if !config.respect_caches {
+        let no_cache = reqwest::header::HeaderValue::from_static("no-cache");
+        headers.insert(reqwest::header::CACHE_CONTROL, no_cache.clone());
+        headers.insert(reqwest::header::PRAGMA, no_cache);
+    }
+    headers
+}
+
+/// Merges the header layers for one path: global headers, then the `default_headers` for the
+/// path's method, then environment headers, then path headers, with later layers replacing
+/// earlier ones (names compare case-insensitively).
+fn path_headers(
+    base: &reqwest::header::HeaderMap,
+    config: &Config,
+    env: &Environment,
+    path: &PathConfig,
+) -> Result<reqwest::header::HeaderMap, String> {
+    let mut headers = base.clone();
+    let layers = [
+        Some(&config.headers),
+        for_method(&config.default_headers, &path.method),
+        Some(&env.headers),
+        Some(&path.headers),
+    ];
+    for layer in layers.into_iter().flatten() {
+        // Sorted so the result doesn't depend on map iteration order
+        let mut entries: Vec<(&String, &String)> = layer.iter().collect();
+        entries.sort();
+        for (name, value) in entries {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{}'", name))?;
+            let value = reqwest::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header '{}'", name))?;
+            headers.insert(name, value);
+        }
+    }
+    Ok(headers)
+}
+
+// Header names whose values are never printed
+const SENSITIVE_HEADERS: [&str; 3] = ["authorization", "cookie", "x-api-key"];
+
+// Helper function to render a header value for logs, hiding credentials
+fn display_header_value(
+    name: &reqwest::header::HeaderName,
+    value: &reqwest::header::HeaderValue,
+) -> String {
+    if SENSITIVE_HEADERS.contains(&name.as_str()) {
+        "***".to_string()
+    } else {
+        value.to_str().unwrap_or("<binary>").to_string()
+    }
+}
+
+// Helper function to build a request with an optional body
+fn build_request(
+    client: &reqwest::Client,
+    method: reqwest::Method,
+    url: &str,
+    body: Option<&str>,
+    headers: &reqwest::header::HeaderMap,
+) -> reqwest::RequestBuilder {
+    let mut request = client.request(method, url).headers(headers.clone());
+    if let Some(body) = body {
+        // Bodies that look like JSON are sent with a JSON content type
+        if serde_json::from_str::<serde_json::Value>(body).is_ok() {
+            request = request.header(reqwest::header::CONTENT_TYPE, "application/json");
+        }
+        request = request.body(body.to_string());
+    }
+    request
+}
+
+/// Sends a setup/teardown request, returning a description of the failure if it isn't 2xx.
+async fn send_sub_request(
+    client: &reqwest::Client,
+    base_url: &str,
+    sub: &SubRequest,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<(), String> {
+    let url = if sub.url.starts_with("http://") || sub.url.starts_with("https://") {
+        sub.url.clone()
+    } else {
+        format!("{}{}", base_url, sub.url)
+    };
+    let method = parse_method(&sub.method)?;
+    let label = format!("{} {}", method, url);
+
+    usage::count_request();
+    match build_request(client, method, &url, sub.body.as_deref(), headers)
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!(
+            "{}: HTTP Status Error: {}",
+            label,
+            response.status()
+        )),
+        Err(e) => Err(format!("{}: {}", label, e)),
+    }
 }
 
 // Helper function to print a single test result row
-fn print_test_result_row(res: &UrlTestResult) {
+fn print_test_result_row(res: &UrlTestResult, args: &Args) {
     let env_display = truncate_string(&res.environment_name, 8);
     let status_str = res.status_code.map_or("N/A".to_string(), |s| s.to_string());
 
     let passed_str_raw = if res.passed { "PASS" } else { "FAIL" };
     let colored_passed_str = if res.passed {
-        format!("{}{}{}", COLOR_GREEN, passed_str_raw, COLOR_RESET)
+        paint(passed_str_raw, COLOR_GREEN, args)
     } else {
-        format!("{}{}{}", COLOR_RED, passed_str_raw, COLOR_RESET)
+        paint(passed_str_raw, COLOR_RED, args)
     };
     let passed_padding_needed = 7;
     let current_visible_length = passed_str_raw.len();
@@ -347,38 +6484,359 @@ fn print_test_result_row(res: &UrlTestResult) {
         width = passed_padding_needed + (colored_passed_str.len() - current_visible_length)
     );
 
-    let duration_str = format!("{:.2}s", res.duration_secs);
+    let duration_str = if args.no_duration || args.stable_output {
+        String::new()
+    } else {
+        format!("{:.2}s", res.duration_secs)
+    };
 
     let error_display_message = res.error_message.as_deref().unwrap_or("None").to_string();
 
     let state_display = res.state_param.as_deref().unwrap_or("N/A");
 
+    // Watch mode adds how long a failure has persisted
+    let streak = res.failure_streak.map_or(String::new(), |streak| {
+        format!(" | {}", describe_streak(&streak, args))
+    });
+
     println!(
-        "{: <10} | {: <20} | {: <10} | {} | {: <10} | {: <60}",
+        "{: <10} | {: <20} | {: <10} | {} | {: <10} | {: <60}{}",
         env_display,
         truncate_string(state_display, 18),
         status_str,
         formatted_passed_str,
         duration_str,
-        truncate_string(&error_display_message, 58)
+        truncate_string(&error_display_message, 58),
+        streak
     );
 }
 
+// Helper function to print the full details of failing tests in verbose mode
+fn print_failure_details(failing_results: &[&UrlTestResult], args: &Args) {
+    println!("\n--- Failure Details ---");
+    for res in failing_results {
+        println!("\n[{}] {}", res.environment_name, res.url);
+        if !res.request_headers.is_empty() {
+            println!("  Request Headers:");
+            for (name, value) in &res.request_headers {
+                println!("    {}: {}", name, display_header_value(name, value));
+            }
+        }
+        if let Some(status) = res.status_code {
+            println!("  Status: {}", status);
+        }
+        if let Some(version) = &res.http_version {
+            match &res.remote_addr {
+                Some(addr) => println!("  Protocol: {} from {}", version, addr),
+                None => println!("  Protocol: {}", version),
+            }
+        }
+        if let Some(error) = &res.error_message {
+            println!("  Error: {}", error);
+        }
+        if !res.server_timing.is_empty() && !args.stable_output {
+            let metrics: Vec<String> = res
+                .server_timing
+                .iter()
+                .map(|(name, dur)| format!("{}={}ms", name, dur))
+                .collect();
+            println!(
+                "  Server-Timing: {} (total {:.1}ms, client {:.1}ms)",
+                metrics.join(", "),
+                res.server_timing.values().sum::<f64>(),
+                res.duration_secs * 1000.0
+            );
+        }
+        if let Some(body) = res.response_body.as_deref().filter(|body| !body.is_empty()) {
+            if res.body_truncated {
+                println!("  Body (truncated by --max-body-read):");
+            } else {
+                println!("  Body:");
+            }
+            for line in format_body(body, args.pretty_body).lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+}
+
+// Re-indents JSON bodies when pretty-printing is enabled; anything else is returned untouched
+fn format_body(body: &str, pretty: bool) -> String {
+    if pretty {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(body) {
+            if let Ok(formatted) = serde_json::to_string_pretty(&value) {
+                return formatted;
+            }
+        }
+    }
+    body.to_string()
+}
+
+/// Wraps `text` in `color`, unless the color is empty or --stable-output asks for plain text.
+fn paint(text: &str, color: &str, args: &Args) -> String {
+    if color.is_empty() || args.stable_output {
+        text.to_string()
+    } else {
+        format!("{}{}{}", color, text, COLOR_RESET)
+    }
+}
+
 // Helper function to print the table header
-fn print_report_header() {
+fn print_report_header(duration_measure: DurationMeasure, failing_since: bool) {
+    let duration_label = match duration_measure {
+        DurationMeasure::Ttfb => "TTFB",
+        DurationMeasure::Total => "Duration",
+    };
     println!(
-        "{: <10} | {: <20} | {: <10} | {: <7} | {: <10} | {: <60}",
-        "Env", "State", "Status", "Passed", "Duration", "Error Message"
+        "{: <10} | {: <20} | {: <10} | {: <7} | {: <10} | {: <60}{}",
+        "Env",
+        "State",
+        "Status",
+        "Passed",
+        duration_label,
+        "Error Message",
+        if failing_since { " | Failing For" } else { "" }
     );
     println!("{}", "-".repeat(128));
 }
 
+// Lengths are in characters, so multi-byte text is never cut inside a character
 fn truncate_string(s: &str, max_len: usize) -> String {
-    if s.len() > max_len && max_len > 3 {
-        format!("{}...", &s[..max_len - 3])
-    } else if max_len > 0 && s.len() > max_len {
-        s[..max_len].to_string()
+    let len = s.chars().count();
+    if len > max_len && max_len > 3 {
+        format!("{}...", s.chars().take(max_len - 3).collect::<String>())
+    } else if max_len > 0 && len > max_len {
+        s.chars().take(max_len).collect()
     } else {
         s.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Answers every connection on a random local port with `status` and `body`
+    async fn serve(status: &'static str, body: &'static str) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let response = response.clone();
+                tokio::spawn(async move {
+                    let mut request = [0u8; 4096];
+                    let _ = socket.read(&mut request).await;
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        port
+    }
+
+    // Runs `config` (TOML, with `{baseurl}` filled in) and returns its results
+    async fn run(config: &str, baseurl: &str) -> Vec<UrlTestResult> {
+        let config = parse_config(&config.replace("{baseurl}", baseurl), &[], false).unwrap();
+        let args = Args::try_parse_from(["url_tester", "--config", "test.toml", "--stable-output"])
+            .unwrap();
+        let clients = HttpClients::without_proxy(ConnectionMode::Pooled).unwrap();
+        run_tests(
+            &config,
+            &args,
+            &clients,
+            None,
+            None,
+            true,
+            &interrupt::Interrupt::never(),
+        )
+        .await
+        .unwrap()
+        .results
+    }
+
+    #[tokio::test]
+    async fn every_failed_check_is_recorded_in_order() {
+        let port = serve(
+            "500 Internal Server Error",
+            r#"{"card": "4111111111111111"}"#,
+        )
+        .await;
+        let results = run(
+            r#"
+            paths = [{ path = "/orders", teardown = { url = "/orders/cleanup" } }]
+            forbidden_patterns = [{ name = "card number", regex = "\\d{16}" }]
+            [environments.test]
+            baseurl = "{baseurl}"
+            "#,
+            &format!("http://127.0.0.1:{}", port),
+        )
+        .await;
+
+        let res = &results[0];
+        assert!(!res.passed);
+        assert_eq!(res.failures.len(), 3, "{:?}", res.failures);
+        assert_eq!(
+            res.failures[0],
+            "HTTP Status Error: 500 Internal Server Error"
+        );
+        assert_eq!(
+            res.failures[1],
+            "Response body matches forbidden pattern 'card number'"
+        );
+        assert!(
+            res.failures[2].starts_with("Teardown failed:"),
+            "{}",
+            res.failures[2]
+        );
+        assert_eq!(res.error_message, Some(res.failures.join("; ")));
+        // The server error outranks the assertion and teardown failures recorded after it
+        assert_eq!(res.failure_kind, Some(FailureKind::ServerError));
+    }
+
+    // The merged headers of each path of `config`, for its only environment
+    fn merged_headers(config: &str) -> Vec<reqwest::header::HeaderMap> {
+        let config = parse_config(config, &[], false).unwrap();
+        let env = config.environments.values().next().unwrap();
+        config
+            .paths
+            .iter()
+            .map(|path| {
+                path_headers(&default_request_headers(&config), &config, env, path).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn path_headers_override_environment_and_global_headers() {
+        let headers = merged_headers(
+            r#"
+            headers = { Accept = "text/html", X-Global = "global" }
+            paths = ["/plain", { path = "/api", headers = { Accept = "application/vnd.api+json" } }]
+            [environments.prod]
+            baseurl = "https://example.com"
+            headers = { X-Global = "environment", X-Env = "environment" }
+            "#,
+        );
+        assert_eq!(headers[0]["accept"], "text/html");
+        assert_eq!(headers[0]["x-global"], "environment");
+        assert_eq!(headers[0]["x-env"], "environment");
+        assert_eq!(headers[1]["accept"], "application/vnd.api+json");
+        assert_eq!(headers[1]["x-global"], "environment");
+    }
+
+    #[test]
+    fn header_names_merge_case_insensitively() {
+        let headers = merged_headers(
+            r#"
+            headers = { ACCEPT = "text/html" }
+            paths = [{ path = "/api", headers = { accept = "application/json" } }]
+            [environments.prod]
+            baseurl = "https://example.com"
+            headers = { Accept = "text/plain" }
+            "#,
+        );
+        assert_eq!(headers[0].get_all("accept").iter().count(), 1);
+        assert_eq!(headers[0]["Accept"], "application/json");
+    }
+
+    #[test]
+    fn method_default_headers_sit_between_global_and_environment() {
+        let headers = merged_headers(
+            r#"
+            headers = { Content-Type = "text/plain", X-Layer = "global" }
+            paths = [
+                { path = "/items", method = "post", destructive = true },
+                { path = "/items/1", method = "POST", destructive = true, headers = { Content-Type = "application/xml" } },
+                "/items",
+            ]
+            [default_headers.POST]
+            Content-Type = "application/json"
+            X-Layer = "method"
+            [environments.prod]
+            baseurl = "https://example.com"
+            headers = { X-Layer = "environment" }
+            "#,
+        );
+        assert_eq!(headers[0]["content-type"], "application/json");
+        assert_eq!(headers[0]["x-layer"], "environment");
+        assert_eq!(headers[1]["content-type"], "application/xml");
+        assert_eq!(headers[2]["content-type"], "text/plain");
+    }
+
+    #[test]
+    fn failure_kind_is_the_most_severe_not_the_first() {
+        let mut res = UrlTestResult::default();
+        res.fail(FailureKind::Teardown, "teardown".to_string());
+        res.fail(FailureKind::Assertion, "assertion".to_string());
+        res.fail(FailureKind::Connection, "connection".to_string());
+        res.fail(FailureKind::ClientError, "client error".to_string());
+        assert_eq!(res.failure_kind, Some(FailureKind::Connection));
+        assert_eq!(
+            res.error_message.as_deref(),
+            Some("teardown; assertion; connection; client error")
+        );
+    }
+
+    #[test]
+    fn range_length_rejects_ranges_that_overflow() {
+        assert_eq!(range_length("0-1023"), Ok(Some(1024)));
+        assert_eq!(range_length("-500"), Ok(Some(500)));
+        assert_eq!(range_length("1000-"), Ok(None));
+        let too_long = format!("0-{}", usize::MAX);
+        assert!(range_length(&too_long).unwrap_err().contains("too long"));
+        assert!(range_length("10-5").unwrap_err().contains("comes before"));
+    }
+
+    #[test]
+    fn expected_location_regex_is_compiled_when_the_config_loads() {
+        let config = |location: &str| {
+            format!(
+                "paths = [{{ path = \"/old\", expected_location = {} }}]\n\
+                 [environments.local]\nbaseurl = \"http://127.0.0.1\"\n",
+                location
+            )
+        };
+        let loaded = parse_config(&config("{ regex = \"^/login(\\\\?|$)\" }"), &[], false).unwrap();
+        let matcher = loaded.paths[0].expected_location.as_ref().unwrap();
+        assert!(matcher.matches("/login?next=/"));
+        assert!(!matcher.matches("/logout"));
+
+        let error = parse_config(&config("{ regex = \"^/(login\" }"), &[], false).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("invalid expected_location regex"));
+    }
+
+    #[test]
+    fn streaming_until_regex_is_compiled_when_the_config_loads() {
+        let config = |until: &str| {
+            format!(
+                "paths = [{{ path = \"/events\", streaming = {{ max_secs = 5, until = \"{}\" }} }}]\n\
+                 [environments.local]\nbaseurl = \"http://127.0.0.1\"\n",
+                until
+            )
+        };
+        let loaded = parse_config(&config("^data: done$"), &[], false).unwrap();
+        let streaming = loaded.paths[0].streaming.as_ref().unwrap();
+        assert!(streaming.until.as_ref().unwrap().is_match("data: done"));
+
+        let error = parse_config(&config("^data: (done"), &[], false).unwrap_err();
+        assert!(error.to_string().contains("invalid streaming until regex"));
+    }
+
+    #[test]
+    fn truncate_string_cuts_between_characters() {
+        assert_eq!(truncate_string("production", 10), "production");
+        assert_eq!(truncate_string("production-eu", 10), "product...");
+        assert_eq!(truncate_string("Zürich-Ost-1", 10), "Zürich-...");
+        assert_eq!(truncate_string("日本語のエラー", 5), "日本...");
+        assert_eq!(truncate_string("日本語", 2), "日本");
+    }
+}
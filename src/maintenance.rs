@@ -0,0 +1,63 @@
+//! Maintenance mode (`--maintenance`, or `maintenance = true` on an environment): during
+//! planned downtime the maintenance page is the expected response, and a normal one fails.
+
+use serde::{Deserialize, Serialize};
+
+/// The `[maintenance]` table: what the maintenance page looks like.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceCheck {
+    #[serde(default = "default_maintenance_status")]
+    pub status: u16,
+    // Text the maintenance page must contain, e.g. "scheduled maintenance"
+    #[serde(default)]
+    pub body_contains: Option<String>,
+}
+
+impl Default for MaintenanceCheck {
+    fn default() -> Self {
+        MaintenanceCheck {
+            status: default_maintenance_status(),
+            body_contains: None,
+        }
+    }
+}
+
+fn default_maintenance_status() -> u16 {
+    503
+}
+
+impl MaintenanceCheck {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(100..=599).contains(&self.status) {
+            return Err(format!(
+                "maintenance status {} is not an HTTP status code",
+                self.status
+            ));
+        }
+        Ok(())
+    }
+
+    /// Replaces the usual status and body checks: the response must be the maintenance page,
+    /// and a successful one means maintenance isn't active.
+    pub fn check(&self, status: reqwest::StatusCode, body: &str) -> Vec<String> {
+        if status.is_success() && status.as_u16() != self.status {
+            return vec![format!(
+                "Maintenance not active: got {}, expected the maintenance page ({})",
+                status, self.status
+            )];
+        }
+        let mut failures = Vec::new();
+        if status.as_u16() != self.status {
+            failures.push(format!(
+                "Expected maintenance status {}, got {}",
+                self.status, status
+            ));
+        }
+        if let Some(marker) = &self.body_contains {
+            if !body.contains(marker.as_str()) {
+                failures.push(format!("Maintenance page doesn't contain '{}'", marker));
+            }
+        }
+        failures
+    }
+}
@@ -0,0 +1,85 @@
+//! How a run was invoked, recorded in the JSON report and next to the CSV report so
+//! archived results are self-describing.
+
+use serde::Serialize;
+use std::fs;
+
+use crate::schema::SCHEMA_VERSION;
+use crate::{timestamp, Args, TestRun};
+
+/// Context of one run. Only what is known about the invocation is included, never the
+/// config contents: those are identified by `config_hash` in the summary.
+#[derive(Debug, Serialize)]
+pub struct RunMetadata {
+    pub tool_version: &'static str,
+    // In --time-format, RFC 3339 UTC by default
+    pub started_at: String,
+    pub hostname: Option<String>,
+    pub config_path: String,
+    pub filters: RunFilters,
+    // Command-line arguments, without the program name
+    pub arguments: Vec<String>,
+    pub duration_secs: f64,
+}
+
+/// Command-line options that narrowed down what was tested.
+#[derive(Debug, Serialize)]
+pub struct RunFilters {
+    pub env: Option<String>,
+    pub group: Option<String>,
+    pub compare_envs: Option<Vec<String>>,
+    pub changed_paths_file: Option<String>,
+    pub path_filter: Option<String>,
+}
+
+impl RunMetadata {
+    pub fn new(args: &Args, run: &TestRun) -> Self {
+        RunMetadata {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            started_at: timestamp(args, run.started_at),
+            hostname: hostname(),
+            config_path: args.config.clone().unwrap_or_default(),
+            filters: RunFilters {
+                env: args.env.clone(),
+                group: args.group.clone(),
+                compare_envs: args.compare_envs.clone(),
+                changed_paths_file: args.changed_paths_file.clone(),
+                path_filter: args.path_filter.as_ref().map(|filter| filter.to_string()),
+            },
+            arguments: std::env::args().skip(1).collect(),
+            duration_secs: run.duration.as_secs_f64(),
+        }
+    }
+}
+
+/// `--output` sidecar: the run metadata as JSON in `<csv path>.meta.json`, since a CSV
+/// file has no room for it. Returns the path written.
+pub fn write_csv_sidecar(
+    csv_path: &str,
+    metadata: &RunMetadata,
+) -> Result<String, Box<dyn std::error::Error>> {
+    #[derive(Serialize)]
+    struct Sidecar<'a> {
+        schema_version: u32,
+        metadata: &'a RunMetadata,
+    }
+
+    let path = format!("{}.meta.json", csv_path);
+    let json = serde_json::to_string_pretty(&Sidecar {
+        schema_version: SCHEMA_VERSION,
+        metadata,
+    })?;
+    fs::write(&path, json)?;
+    Ok(path)
+}
+
+// Best effort: the kernel's name on Linux, otherwise what the environment says
+fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .chain(std::env::var("COMPUTERNAME"))
+        .chain(std::env::var("HOSTNAME"))
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())
+}
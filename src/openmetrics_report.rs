@@ -0,0 +1,110 @@
+//! OpenMetrics text output for `--openmetrics`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+use crate::UrlTestResult;
+
+// Upper bounds (seconds) of the duration histogram buckets, before the implicit +Inf
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Writes a duration histogram and pass/fail counters per environment. With `exemplars`,
+/// each histogram bucket carries the `test_id` of the slowest result that landed in it.
+/// Only results that got a response count towards the histogram.
+pub fn write_openmetrics_report(
+    path: &str,
+    results: &[&UrlTestResult],
+    exemplars: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut by_env: BTreeMap<&str, Vec<&UrlTestResult>> = BTreeMap::new();
+    for res in results.iter().copied() {
+        by_env.entry(&res.environment_name).or_default().push(res);
+    }
+
+    let mut out = String::new();
+    out.push_str("# TYPE url_tester_request_duration_seconds histogram\n");
+    out.push_str("# UNIT url_tester_request_duration_seconds seconds\n");
+    out.push_str(
+        "# HELP url_tester_request_duration_seconds Duration of requests that got a response.\n",
+    );
+    for (env_name, env_results) in &by_env {
+        let env_label = escape_label(env_name);
+        let responded: Vec<&UrlTestResult> = env_results
+            .iter()
+            .copied()
+            .filter(|res| res.status_code.is_some())
+            .collect();
+        let mut lower = f64::NEG_INFINITY;
+        for upper in BUCKETS.iter().copied().chain([f64::INFINITY]) {
+            let count = responded
+                .iter()
+                .filter(|res| res.duration_secs <= upper)
+                .count();
+            let le = if upper.is_infinite() {
+                "+Inf".to_string()
+            } else {
+                format!("{:?}", upper)
+            };
+            write!(
+                out,
+                "url_tester_request_duration_seconds_bucket{{environment=\"{}\",le=\"{}\"}} {}",
+                env_label, le, count
+            )?;
+            let slowest = responded
+                .iter()
+                .filter(|res| res.duration_secs > lower && res.duration_secs <= upper)
+                .max_by(|a, b| a.duration_secs.total_cmp(&b.duration_secs));
+            if let Some(res) = slowest.filter(|_| exemplars) {
+                write!(
+                    out,
+                    " # {{test_id=\"{}\"}} {}",
+                    escape_label(&res.test_id),
+                    res.duration_secs
+                )?;
+            }
+            out.push('\n');
+            lower = upper;
+        }
+        writeln!(
+            out,
+            "url_tester_request_duration_seconds_count{{environment=\"{}\"}} {}",
+            env_label,
+            responded.len()
+        )?;
+        writeln!(
+            out,
+            "url_tester_request_duration_seconds_sum{{environment=\"{}\"}} {}",
+            env_label,
+            responded.iter().map(|res| res.duration_secs).sum::<f64>()
+        )?;
+    }
+
+    out.push_str("# TYPE url_tester_tests counter\n");
+    out.push_str("# HELP url_tester_tests Tests run, by outcome.\n");
+    for (env_name, env_results) in &by_env {
+        let passed = env_results.iter().filter(|res| res.passed).count();
+        for (outcome, count) in [("pass", passed), ("fail", env_results.len() - passed)] {
+            writeln!(
+                out,
+                "url_tester_tests_total{{environment=\"{}\",result=\"{}\"}} {}",
+                escape_label(env_name),
+                outcome,
+                count
+            )?;
+        }
+    }
+    out.push_str("# EOF\n");
+
+    fs::write(path, out)?;
+    Ok(())
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
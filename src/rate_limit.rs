@@ -0,0 +1,114 @@
+//! Rate-limit response headers, configured in `[rate_limit]`: captured into every result,
+//! warned about when the budget runs low, and optionally waited out when it runs out.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{truncate_string, UrlTestResult, COLOR_RESET, COLOR_YELLOW};
+
+// Reset values above this are Unix timestamps, below it seconds until the reset
+const RESET_EPOCH_THRESHOLD: u64 = 1_000_000_000;
+
+/// The `[rate_limit]` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_limit_header")]
+    pub limit_header: String,
+    #[serde(default = "default_remaining_header")]
+    pub remaining_header: String,
+    // Either seconds until the budget resets or the Unix time it does
+    #[serde(default = "default_reset_header")]
+    pub reset_header: String,
+    // Warn when an environment's lowest remaining value goes below this
+    #[serde(default)]
+    pub warn_below: Option<u64>,
+    // Stop dispatching to an environment whose remaining value hits 0 until it resets
+    #[serde(default)]
+    pub pause_when_exhausted: bool,
+    // Longest such pause, also used when the response has no reset header
+    #[serde(default = "default_max_pause_secs")]
+    pub max_pause_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            limit_header: default_limit_header(),
+            remaining_header: default_remaining_header(),
+            reset_header: default_reset_header(),
+            warn_below: None,
+            pause_when_exhausted: false,
+            max_pause_secs: default_max_pause_secs(),
+        }
+    }
+}
+
+fn default_limit_header() -> String {
+    "x-ratelimit-limit".to_string()
+}
+
+fn default_remaining_header() -> String {
+    "x-ratelimit-remaining".to_string()
+}
+
+fn default_reset_header() -> String {
+    "x-ratelimit-reset".to_string()
+}
+
+fn default_max_pause_secs() -> u64 {
+    60
+}
+
+impl RateLimitConfig {
+    /// How long to stop dispatching after `result` used up the budget, if it did.
+    pub fn pause_after(&self, result: &UrlTestResult, now_unix: u64) -> Option<Duration> {
+        if !self.pause_when_exhausted || result.rate_limit_remaining != Some(0) {
+            return None;
+        }
+        let secs = match result.rate_limit_reset {
+            Some(reset) if reset > RESET_EPOCH_THRESHOLD => reset.saturating_sub(now_unix),
+            Some(reset) => reset,
+            None => self.max_pause_secs,
+        };
+        Some(Duration::from_secs(secs.min(self.max_pause_secs)))
+    }
+}
+
+/// Prints a warning for every environment whose lowest observed remaining value is below
+/// `warn_below`.
+pub fn print_warnings(results: &[UrlTestResult], config: &RateLimitConfig) {
+    let Some(threshold) = config.warn_below else {
+        return;
+    };
+    // Environment -> (lowest remaining, limit reported with it)
+    let mut lowest: BTreeMap<&str, (u64, Option<u64>)> = BTreeMap::new();
+    for res in results {
+        let Some(remaining) = res.rate_limit_remaining else {
+            continue;
+        };
+        let entry = lowest
+            .entry(&res.environment_name)
+            .or_insert((remaining, res.rate_limit_limit));
+        if remaining < entry.0 {
+            *entry = (remaining, res.rate_limit_limit);
+        }
+    }
+    lowest.retain(|_, (remaining, _)| *remaining < threshold);
+    if lowest.is_empty() {
+        return;
+    }
+    println!("\n--- Rate Limits ---");
+    for (env_name, (remaining, limit)) in lowest {
+        let of_limit = limit.map_or(String::new(), |limit| format!(" of {}", limit));
+        println!(
+            "{}{: <10} | down to {}{} remaining, below warn_below = {}{}",
+            COLOR_YELLOW,
+            truncate_string(env_name, 10),
+            remaining,
+            of_limit,
+            threshold,
+            COLOR_RESET
+        );
+    }
+}
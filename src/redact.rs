@@ -0,0 +1,192 @@
+//! Redaction of secrets (`redact_params` and `redact_headers`) from recorded results.
+
+use regex::Regex;
+
+use crate::{state_param, Config, UrlTestResult, SENSITIVE_HEADERS};
+
+const MASK: &str = "***";
+
+/// Masks configured query parameter values and header values. The real values are still
+/// sent on the wire; only what gets recorded or printed is rewritten.
+pub struct Redactor {
+    // One `[?&]name=value` pattern per redacted parameter
+    params: Vec<Regex>,
+    // Lowercased names of headers whose values are masked
+    headers: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(config: &Config) -> Self {
+        let params = config
+            .redact_params
+            .iter()
+            .filter_map(|name| {
+                Regex::new(&format!(r#"(?i)([?&]{}=)[^&#\s"']*"#, regex::escape(name))).ok()
+            })
+            .collect();
+        let headers = SENSITIVE_HEADERS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(config.redact_headers.iter().map(|name| name.to_lowercase()))
+            .collect();
+        Redactor { params, headers }
+    }
+
+    /// Whether values of the header `name` are masked.
+    pub fn is_redacted_header(&self, name: &str) -> bool {
+        self.headers.contains(&name.to_lowercase())
+    }
+
+    /// Masks the values of redacted query parameters anywhere in `text`, e.g. in a URL or
+    /// in an error message quoting one.
+    pub fn redact(&self, text: &str) -> String {
+        self.params.iter().fold(text.to_string(), |text, re| {
+            re.replace_all(&text, format!("${{1}}{}", MASK))
+                .into_owned()
+        })
+    }
+
+    /// Rewrites every field of a result that can carry a secret. This is the single place
+    /// redaction happens: it runs before any report or console output sees the result.
+    pub fn redact_result(&self, res: &mut UrlTestResult) {
+        // Header values are masked wherever they were echoed back, e.g. in an error body
+        let secrets: Vec<String> = res
+            .request_headers
+            .iter()
+            .filter(|(name, _)| self.is_redacted_header(name.as_str()))
+            .filter_map(|(_, value)| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect();
+        let scrub = |text: &str| {
+            secrets
+                .iter()
+                .fold(self.redact(text), |text, secret| text.replace(secret, MASK))
+        };
+
+        res.url = scrub(&res.url);
+        res.path = scrub(&res.path);
+        // Taken from the path again, so a redacted `State` parameter stays masked
+        if res.state_param.is_some() {
+            res.state_param = state_param(&res.path);
+        }
+        res.response_body_preview = scrub(&res.response_body_preview);
+        for text in [
+            &mut res.error_message,
+            &mut res.warning,
+            &mut res.setup_error,
+            &mut res.teardown_error,
+            &mut res.response_body,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *text = scrub(text);
+        }
+        for failure in res.failures.iter_mut() {
+            *failure = scrub(failure);
+        }
+        // Cookie values are as sensitive as the `Cookie` header that sends them back
+        for cookie in res.cookies.iter_mut() {
+            cookie.value = MASK.to_string();
+        }
+        for (name, value) in res.request_headers.iter_mut() {
+            if self.is_redacted_header(name.as_str()) {
+                *value = reqwest::header::HeaderValue::from_static(MASK);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::RunMetadata;
+    use crate::{run_summary, sinks, Args, TestRun};
+    use clap::Parser;
+    use std::fs;
+
+    const SECRET: &str = "s3cr3t-value";
+
+    #[test]
+    fn no_sink_leaks_a_redacted_value() {
+        let config: Config = toml::from_str(
+            r#"
+            paths = ["/items"]
+            redact_params = ["token", "State"]
+            [environments.prod]
+            baseurl = "https://example.com"
+            "#,
+        )
+        .unwrap();
+        let url = format!("https://example.com/items?token={0}&State={0}", SECRET);
+        let mut res = UrlTestResult {
+            environment_name: "prod".to_string(),
+            url: url.clone(),
+            path: format!("/items?token={0}&State={0}", SECRET),
+            state_param: Some(SECRET.to_string()),
+            response_body_preview: format!("echoed {}", url),
+            error_message: Some(format!("Request to {} failed", url)),
+            failures: vec![format!("Request to {} failed", url)],
+            warning: Some(format!("Slow response from {}", url)),
+            ..Default::default()
+        };
+        Redactor::new(&config).redact_result(&mut res);
+        assert_eq!(res.state_param.as_deref(), Some(MASK));
+
+        let dir = std::env::temp_dir().join(format!("url_tester_redact_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let args = Args::try_parse_from([
+            "url_tester".to_string(),
+            "--config".to_string(),
+            "config.toml".to_string(),
+            "--output".to_string(),
+            file("report.csv"),
+            "--output".to_string(),
+            file("report.ndjson"),
+            "--json".to_string(),
+            file("report.json"),
+            "--html".to_string(),
+            file("report.html"),
+            "--dashboard".to_string(),
+            file("dashboard.html"),
+            "--openmetrics".to_string(),
+            file("metrics.prom"),
+            "--sqlite".to_string(),
+            file("runs.db"),
+        ])
+        .unwrap();
+        let mut run = TestRun::empty(&config, &args, "unused");
+        run.nothing_ran = None;
+        run.results.push(res);
+        let summary = run_summary(&run, &config, &args, 1);
+        let run_metadata = RunMetadata::new(&args, &run);
+        let mut sinks = sinks::from_args(&args, &config, &run_metadata, run.started_at).unwrap();
+        for sink in sinks.iter_mut() {
+            for res in &run.results {
+                sink.record(res).unwrap();
+            }
+        }
+        for sink in sinks.iter_mut() {
+            sink.finish(&summary).unwrap();
+        }
+
+        let mut checked = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let written = fs::read(&path).unwrap();
+            assert!(
+                !written
+                    .windows(SECRET.len())
+                    .any(|window| window == SECRET.as_bytes()),
+                "{} leaks the redacted value",
+                path.display()
+            );
+            checked += 1;
+        }
+        fs::remove_dir_all(&dir).ok();
+        // Every sink wrote its file, the CSV also its metadata sidecar
+        assert!(checked >= 8, "only {} files written", checked);
+    }
+}
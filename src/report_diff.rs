@@ -0,0 +1,280 @@
+//! The `diff` subcommand: compares two saved reports, CSV or JSON, result by result.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::{DiffFormat, COLOR_GREEN, COLOR_RED, COLOR_RESET};
+
+/// The fields of a saved result the comparison looks at.
+#[derive(Debug, Deserialize)]
+struct ReportRow {
+    environment_name: String,
+    url: String,
+    #[serde(default)]
+    status_code: Option<u16>,
+    passed: bool,
+    #[serde(default)]
+    duration_secs: Option<f64>,
+}
+
+/// What changed for one result between the two reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ChangeKind {
+    NewlyFailing,
+    Fixed,
+    StatusChanged,
+    Slower,
+    Faster,
+    OnlyInBefore,
+    OnlyInAfter,
+}
+
+impl ChangeKind {
+    fn label(self) -> &'static str {
+        match self {
+            ChangeKind::NewlyFailing => "NEWLY FAILING",
+            ChangeKind::Fixed => "FIXED",
+            ChangeKind::StatusChanged => "STATUS",
+            ChangeKind::Slower => "SLOWER",
+            ChangeKind::Faster => "FASTER",
+            ChangeKind::OnlyInBefore => "ONLY BEFORE",
+            ChangeKind::OnlyInAfter => "ONLY AFTER",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            ChangeKind::NewlyFailing => COLOR_RED,
+            ChangeKind::Fixed => COLOR_GREEN,
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Change {
+    kind: ChangeKind,
+    environment_name: String,
+    url: String,
+    // Human-readable before → after, e.g. "200 → 503"
+    details: String,
+}
+
+/// `--format json` output.
+#[derive(Debug, Serialize)]
+struct ReportDiff<'a> {
+    before: &'a str,
+    after: &'a str,
+    // Number of changes of each kind
+    counts: BTreeMap<ChangeKind, usize>,
+    changes: Vec<Change>,
+}
+
+/// Compares `before` with `after`, matching results by environment and URL, and prints the
+/// changes. Returns whether any result went from passing to failing.
+pub fn diff_reports(
+    before: &str,
+    after: &str,
+    format: DiffFormat,
+    duration_threshold_pct: f64,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let before_rows = load_rows(before)?;
+    let mut after_rows = load_rows(after)?;
+
+    let mut changes = Vec::new();
+    for (key, old) in before_rows {
+        let (environment_name, url) = key.clone();
+        let change = |kind, details: String| Change {
+            kind,
+            environment_name: environment_name.clone(),
+            url: url.clone(),
+            details,
+        };
+        let Some(new) = after_rows.remove(&key) else {
+            changes.push(change(ChangeKind::OnlyInBefore, outcome(&old)));
+            continue;
+        };
+        if old.passed != new.passed {
+            let kind = if old.passed {
+                ChangeKind::NewlyFailing
+            } else {
+                ChangeKind::Fixed
+            };
+            changes.push(change(
+                kind,
+                format!("{} → {}", outcome(&old), outcome(&new)),
+            ));
+        } else if old.status_code != new.status_code {
+            changes.push(change(
+                ChangeKind::StatusChanged,
+                format!("{} → {}", status(&old), status(&new)),
+            ));
+        }
+        if let (Some(old_secs), Some(new_secs)) = (old.duration_secs, new.duration_secs) {
+            if old_secs > 0.0 {
+                let delta_pct = (new_secs - old_secs) / old_secs * 100.0;
+                if delta_pct.abs() > duration_threshold_pct {
+                    let kind = if delta_pct > 0.0 {
+                        ChangeKind::Slower
+                    } else {
+                        ChangeKind::Faster
+                    };
+                    changes.push(change(
+                        kind,
+                        format!("{:.3}s → {:.3}s ({:+.0}%)", old_secs, new_secs, delta_pct),
+                    ));
+                }
+            }
+        }
+    }
+    for ((environment_name, url), new) in after_rows {
+        changes.push(Change {
+            kind: ChangeKind::OnlyInAfter,
+            environment_name,
+            url,
+            details: outcome(&new),
+        });
+    }
+    changes.sort_by(|a, b| {
+        a.kind
+            .cmp(&b.kind)
+            .then_with(|| a.environment_name.cmp(&b.environment_name))
+            .then_with(|| a.url.cmp(&b.url))
+    });
+
+    let mut counts = BTreeMap::new();
+    for change in &changes {
+        *counts.entry(change.kind).or_insert(0) += 1;
+    }
+    let newly_failing = counts.contains_key(&ChangeKind::NewlyFailing);
+
+    match format {
+        DiffFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&ReportDiff {
+                before,
+                after,
+                counts,
+                changes,
+            })?
+        ),
+        DiffFormat::Markdown => print_markdown(before, after, &changes),
+        DiffFormat::Table => print_table(before, after, &changes, &counts),
+    }
+    Ok(newly_failing)
+}
+
+// Results keyed by (environment, url); the file type is picked by extension
+fn load_rows(
+    path: &str,
+) -> Result<BTreeMap<(String, String), ReportRow>, Box<dyn std::error::Error>> {
+    let is_json = std::path::Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let rows: Vec<ReportRow> = if is_json {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read report '{}': {}", path, e))?;
+        let report: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid report '{}': {}", path, e))?;
+        // Flat reports have one `results` array, nested ones an array per environment
+        let results: Vec<serde_json::Value> = match (
+            report.get("results"),
+            report.get("environments").and_then(|envs| envs.as_object()),
+        ) {
+            (Some(results), _) => results.as_array().cloned().unwrap_or_default(),
+            (None, Some(environments)) => environments
+                .values()
+                .filter_map(|env| env.get("results").and_then(|results| results.as_array()))
+                .flatten()
+                .cloned()
+                .collect(),
+            (None, None) => {
+                return Err(format!("Invalid report '{}': no results found", path).into())
+            }
+        };
+        results
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid report '{}': {}", path, e))?
+    } else {
+        let mut reader = csv::Reader::from_path(path)
+            .map_err(|e| format!("Failed to open report '{}': {}", path, e))?;
+        reader
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid report '{}': {}", path, e))?
+    };
+    Ok(rows
+        .into_iter()
+        .map(|row| ((row.environment_name.clone(), row.url.clone()), row))
+        .collect())
+}
+
+fn outcome(row: &ReportRow) -> String {
+    format!(
+        "{} ({})",
+        if row.passed { "pass" } else { "fail" },
+        status(row)
+    )
+}
+
+fn status(row: &ReportRow) -> String {
+    row.status_code
+        .map_or("no response".to_string(), |code| code.to_string())
+}
+
+fn print_table(
+    before: &str,
+    after: &str,
+    changes: &[Change],
+    counts: &BTreeMap<ChangeKind, usize>,
+) {
+    println!("Comparing {} (before) with {} (after)", before, after);
+    if changes.is_empty() {
+        println!("\nNo changes.");
+        return;
+    }
+    println!();
+    for change in changes {
+        let color = change.kind.color();
+        let reset = if color.is_empty() { "" } else { COLOR_RESET };
+        println!(
+            "{}{: <13}{} | {: <10} | {} | {}",
+            color,
+            change.kind.label(),
+            reset,
+            change.environment_name,
+            change.url,
+            change.details
+        );
+    }
+    let summary: Vec<String> = counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind.label().to_lowercase()))
+        .collect();
+    println!("\n{}", summary.join(", "));
+}
+
+fn print_markdown(before: &str, after: &str, changes: &[Change]) {
+    println!("Comparing `{}` (before) with `{}` (after)\n", before, after);
+    if changes.is_empty() {
+        println!("No changes.");
+        return;
+    }
+    println!("| Change | Environment | URL | Details |");
+    println!("| --- | --- | --- | --- |");
+    // Pipes would end the cell early
+    let cell = |text: &str| text.replace('|', "\\|");
+    for change in changes {
+        println!(
+            "| {} | {} | {} | {} |",
+            change.kind.label(),
+            cell(&change.environment_name),
+            cell(&change.url),
+            cell(&change.details)
+        );
+    }
+}
@@ -0,0 +1,517 @@
+//! Versioned description of the CSV and JSON output fields, for `--print-schema`.
+
+use serde::Serialize;
+
+/// Bumped whenever a field is added, removed, renamed or changes type in the CSV or JSON
+/// output. Written as `schema_version` in both.
+pub const SCHEMA_VERSION: u32 = 24;
+
+/// One output field.
+#[derive(Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub nullable: bool,
+    pub description: &'static str,
+}
+
+const fn field(
+    name: &'static str,
+    kind: &'static str,
+    nullable: bool,
+    description: &'static str,
+) -> Field {
+    Field {
+        name,
+        kind,
+        nullable,
+        description,
+    }
+}
+
+/// Fields of every result, in output order.
+pub const RESULT_FIELDS: &[Field] = &[
+    field(
+        "environment_name",
+        "string",
+        false,
+        "Environment the test ran against",
+    ),
+    field(
+        "url",
+        "string",
+        false,
+        "Requested URL, with redacted parameters masked",
+    ),
+    field(
+        "test_id",
+        "string",
+        false,
+        "Stable id of the test across runs",
+    ),
+    field(
+        "status_code",
+        "integer",
+        true,
+        "HTTP status, empty when no response arrived",
+    ),
+    field(
+        "response_body_preview",
+        "string",
+        false,
+        "First 100 characters of the body",
+    ),
+    field("passed", "boolean", false, "Whether the test passed"),
+    field(
+        "error_message",
+        "string",
+        true,
+        "Why the test failed: every entry of failures, joined with \"; \"",
+    ),
+    field(
+        "failures",
+        "array",
+        false,
+        "Each check that failed, in the order they ran; JSON only",
+    ),
+    field(
+        "warning",
+        "string",
+        true,
+        "Problem that didn't fail the test",
+    ),
+    field(
+        "duration_secs",
+        "number",
+        false,
+        "Measured duration, see duration_measure",
+    ),
+    field(
+        "attempts",
+        "integer",
+        false,
+        "Requests made, including --retries",
+    ),
+    field(
+        "retried_assertion",
+        "boolean",
+        false,
+        "An assertion failure was retried",
+    ),
+    field(
+        "download_secs",
+        "number",
+        true,
+        "Time spent reading the body",
+    ),
+    field(
+        "within_sla",
+        "boolean",
+        true,
+        "Passed within sla_ms, empty without an SLA",
+    ),
+    field(
+        "historical_median_secs",
+        "number",
+        true,
+        "Median duration of the URL's recent passing results, with --anomaly-threshold",
+    ),
+    field(
+        "anomaly",
+        "boolean",
+        true,
+        "Whether the duration exceeded --anomaly-threshold times the historical median",
+    ),
+    field(
+        "state_param",
+        "string",
+        true,
+        "Value of the State query parameter",
+    ),
+    field(
+        "setup_error",
+        "string",
+        true,
+        "Failure of the setup request",
+    ),
+    field(
+        "teardown_error",
+        "string",
+        true,
+        "Failure of the teardown request",
+    ),
+    field(
+        "failure_kind",
+        "string",
+        true,
+        "Most severe failure: dns, connection, timeout, server_error, client_error, setup, assertion or teardown",
+    ),
+    field("severity", "string", false, "critical or normal"),
+    field(
+        "body_truncated",
+        "boolean",
+        false,
+        "Body was cut off by --max-body-read",
+    ),
+    field(
+        "streamed_bytes",
+        "integer",
+        true,
+        "Bytes read from a streaming response",
+    ),
+    field(
+        "streamed_lines",
+        "integer",
+        true,
+        "Complete lines read from a streaming response",
+    ),
+    field(
+        "request_bytes",
+        "integer",
+        true,
+        "Approximate bytes sent by the request, headers included, over every attempt",
+    ),
+    field(
+        "response_bytes",
+        "integer",
+        true,
+        "Approximate bytes received in the response, headers included, over every attempt",
+    ),
+    field(
+        "total_size_bytes",
+        "integer",
+        true,
+        "Total size advertised by the Content-Range of a range response",
+    ),
+    field(
+        "cache_age",
+        "integer",
+        true,
+        "Age response header, in seconds",
+    ),
+    field(
+        "cache_status",
+        "string",
+        true,
+        "X-Cache or CF-Cache-Status response header",
+    ),
+    field("http_version", "string", true, "Negotiated protocol"),
+    field("remote_addr", "string", true, "IP and port of the peer"),
+    field(
+        "connection_reused",
+        "boolean",
+        true,
+        "Best effort: the request used a pooled connection; empty with --no-keepalive",
+    ),
+    field(
+        "user_agent",
+        "string",
+        true,
+        "User-Agent picked by --rotate-user-agent",
+    ),
+    field(
+        "server_timing",
+        "object",
+        false,
+        "Server-Timing durations (ms) by metric; JSON only",
+    ),
+    field(
+        "annotations",
+        "object",
+        false,
+        "Values of the annotate_from_headers headers by annotation name; JSON only",
+    ),
+    field(
+        "cookies",
+        "array",
+        false,
+        "Cookies set by the response (values masked); JSON only",
+    ),
+    field(
+        "rate_limit_limit",
+        "integer",
+        true,
+        "Request budget from the [rate_limit] limit_header",
+    ),
+    field(
+        "rate_limit_remaining",
+        "integer",
+        true,
+        "Requests left from the [rate_limit] remaining_header",
+    ),
+    field(
+        "rate_limit_reset",
+        "integer",
+        true,
+        "Seconds until, or Unix time of, the reset from the [rate_limit] reset_header",
+    ),
+    field(
+        "depends_on",
+        "string",
+        true,
+        "Name of the path this one waited for (depends_on)",
+    ),
+    field(
+        "skip_reason",
+        "string",
+        true,
+        "Why the test didn't run, e.g. \"dependency failed (create)\"; set on the rows of dependents whose dependency failed or didn't run, which count as neither passed nor failed",
+    ),
+    field(
+        "reverified",
+        "string",
+        true,
+        "confirmed or recovered_on_retry under --reverify",
+    ),
+    field(
+        "reverify_duration_secs",
+        "number",
+        true,
+        "Duration of the --reverify retry",
+    ),
+    field("config_hash", "string", false, "Fingerprint of the config"),
+    field(
+        "run_label",
+        "string",
+        true,
+        "The run's --label values, joined with \", \"",
+    ),
+    field(
+        "connection_mode",
+        "string",
+        false,
+        "pooled, or fresh with --no-keepalive",
+    ),
+    field(
+        "throttle_kbps",
+        "integer",
+        true,
+        "Simulated link speed the response body was read at (--throttle-kbps)",
+    ),
+];
+
+/// Top-level fields of the JSON summary object.
+pub const SUMMARY_FIELDS: &[Field] = &[
+    field("labels", "array", false, "The run's --label values"),
+    field("total", "integer", false, "Number of results"),
+    field("passed", "integer", false, "Passing results"),
+    field("failed", "integer", false, "Failing results"),
+    field("duration_secs", "number", false, "Duration of the run"),
+    field("config_hash", "string", false, "Fingerprint of the config"),
+    field("report_path", "string", true, "The --output CSV path"),
+    field(
+        "env_reports",
+        "object",
+        true,
+        "Per environment, the --output-per-env file (path) and its row count (rows); absent without the option",
+    ),
+    field("exit_code", "integer", false, "Exit code of the run"),
+    field(
+        "interrupted",
+        "boolean",
+        false,
+        "Whether Ctrl+C stopped the run, so only the tests that completed before it are reported",
+    ),
+    field(
+        "sla",
+        "object",
+        true,
+        "Share of results within sla_ms, when configured",
+    ),
+    field(
+        "status_codes",
+        "object",
+        false,
+        "Status code counts per environment",
+    ),
+    field(
+        "health_scores",
+        "object",
+        false,
+        "Weighted 0-100 health score per environment",
+    ),
+    field(
+        "mixed_states",
+        "object",
+        false,
+        "Per environment, State values whose paths both passed and failed",
+    ),
+    field(
+        "connection_mode",
+        "string",
+        false,
+        "pooled, or fresh with --no-keepalive",
+    ),
+    field(
+        "throttle_kbps",
+        "integer",
+        true,
+        "The --throttle-kbps rate response bodies were read at, null when unthrottled",
+    ),
+    field(
+        "skipped_environments",
+        "object",
+        false,
+        "Environments left out of the run (e.g. outside allowed_hours), with the reason",
+    ),
+    field(
+        "skipped_paths",
+        "object",
+        false,
+        "Per environment, a list of the paths left out of the run (e.g. destructive without permission), each with path, method and reason",
+    ),
+    field(
+        "decided_early",
+        "object",
+        true,
+        "Per environment settled early by --short-circuit, its verdict (passed or failed), reason and not_run count; absent when none was",
+    ),
+    field(
+        "resource_usage",
+        "object",
+        false,
+        "requests_sent (every attempt, setup, teardown and warmup request), bytes_downloaded, bytes_sent and bytes_received (the tests' requests and responses, approximate), average_in_flight, peak_in_flight and requests_per_sec",
+    ),
+];
+
+/// Fields of the JSON `metadata` object, also written to the CSV report's `.meta.json` sidecar.
+pub const METADATA_FIELDS: &[Field] = &[
+    field("tool_version", "string", false, "Version of url_tester"),
+    field(
+        "started_at",
+        "string",
+        false,
+        "Start of the run, in --time-format (RFC 3339 UTC by default)",
+    ),
+    field("hostname", "string", true, "Machine the run happened on"),
+    field("config_path", "string", false, "The --config path"),
+    field(
+        "filters",
+        "object",
+        false,
+        "The --env, --group, --compare-envs, --changed-paths-file and --path-filter values",
+    ),
+    field(
+        "arguments",
+        "array",
+        false,
+        "Command-line arguments, without the program name",
+    ),
+    field("duration_secs", "number", false, "Duration of the run"),
+];
+
+/// Columns of the `--state-summary-csv` file, in output order.
+pub const STATE_SUMMARY_FIELDS: &[Field] = &[
+    field(
+        "environment_name",
+        "string",
+        false,
+        "Environment the paths ran against",
+    ),
+    field(
+        "state",
+        "string",
+        false,
+        "Value of the State query parameter, UNSPECIFIED for paths without one",
+    ),
+    field(
+        "total",
+        "integer",
+        false,
+        "Tested paths: passed + failed + recovered",
+    ),
+    field("passed", "integer", false, "Tests that passed"),
+    field(
+        "failed",
+        "integer",
+        false,
+        "Failed tests that count towards the exit code",
+    ),
+    field(
+        "recovered",
+        "integer",
+        false,
+        "Failed tests that passed when --reverify retried them",
+    ),
+    field(
+        "skipped",
+        "integer",
+        false,
+        "Paths not tested, e.g. because a health_gate failed",
+    ),
+    field(
+        "worst_status",
+        "integer",
+        true,
+        "Highest status code received, empty when no response arrived",
+    ),
+    field(
+        "max_duration_secs",
+        "number",
+        true,
+        "Longest test duration, empty when every path was skipped",
+    ),
+];
+
+/// `--print-schema csv`: the columns of a CSV report.
+#[derive(Serialize)]
+pub struct CsvSchema {
+    pub schema_version: u32,
+    pub columns: Vec<&'static Field>,
+    // Present once per configured server_timing_metrics and annotate_from_headers entry
+    pub dynamic_columns: Vec<Field>,
+    // Columns of the separate --state-summary-csv file
+    pub state_summary_columns: &'static [Field],
+}
+
+/// `--print-schema json`: the structure of a JSON report.
+#[derive(Serialize)]
+pub struct JsonSchema {
+    pub schema_version: u32,
+    pub metadata: &'static [Field],
+    pub summary: &'static [Field],
+    pub results: &'static [Field],
+}
+
+pub fn csv_schema() -> CsvSchema {
+    CsvSchema {
+        schema_version: SCHEMA_VERSION,
+        columns: RESULT_FIELDS
+            .iter()
+            .filter(|field| field.kind != "object" && field.kind != "array")
+            .chain([&SCHEMA_VERSION_FIELD])
+            .collect(),
+        dynamic_columns: vec![
+            field(
+                "server_timing_<metric>",
+                "number",
+                true,
+                "Server-Timing duration (ms) of one metric listed in server_timing_metrics",
+            ),
+            field(
+                "annotation_<name>",
+                "string",
+                true,
+                "Value of one annotate_from_headers header, empty when missing or empty",
+            ),
+        ],
+        state_summary_columns: STATE_SUMMARY_FIELDS,
+    }
+}
+
+pub fn json_schema() -> JsonSchema {
+    JsonSchema {
+        schema_version: SCHEMA_VERSION,
+        metadata: METADATA_FIELDS,
+        summary: SUMMARY_FIELDS,
+        results: RESULT_FIELDS,
+    }
+}
+
+static SCHEMA_VERSION_FIELD: Field = field(
+    "schema_version",
+    "integer",
+    false,
+    "Version of the output schema",
+);
@@ -0,0 +1,236 @@
+//! URL schemes in the config. Only http and https can be requested, so anything else
+//! (`ftp://`, `mailto:`, scheme-relative `//host/path`) is rejected when the config is
+//! loaded, naming the entry, instead of failing later with an opaque client error.
+
+use crate::Config;
+
+/// A scheme the tester can request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+}
+
+// "ftp" for "ftp://host/file", "mailto" for "mailto:ops@example.com"
+fn scheme_of(text: &str) -> Option<&str> {
+    let (scheme, _) = text.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+/// The scheme of an absolute URL.
+pub fn classify(url: &str) -> Result<Scheme, String> {
+    if url.starts_with("//") {
+        return Err(
+            "scheme-relative URL, write out http:// or https:// so the scheme is explicit"
+                .to_string(),
+        );
+    }
+    match scheme_of(url) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("http") => Ok(Scheme::Http),
+        Some(scheme) if scheme.eq_ignore_ascii_case("https") => Ok(Scheme::Https),
+        Some(scheme) => Err(format!(
+            "unsupported scheme '{}', only http and https are supported",
+            scheme
+        )),
+        None => Err("not an absolute http(s) URL".to_string()),
+    }
+}
+
+/// Checks a path that gets appended to the base URL, which must not be a URL of its own.
+fn check_appended(path: &str) -> Result<(), String> {
+    if path.starts_with("//") {
+        return Err(
+            "scheme-relative URL, but paths are appended to the environment's baseurl".to_string(),
+        );
+    }
+    match scheme_of(path) {
+        None => Ok(()),
+        Some(_) => match classify(path) {
+            Ok(_) => {
+                Err("absolute URL, but paths are appended to the environment's baseurl".to_string())
+            }
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Setup and teardown URLs may be absolute or appended to the base URL.
+fn check_sub_request(url: &str) -> Result<(), String> {
+    match scheme_of(url) {
+        Some(_) => classify(url).map(|_| ()),
+        None => check_appended(url),
+    }
+}
+
+/// Checks the scheme of every base URL, path, setup and teardown URL and health gate. The
+/// first bad entry is an error; with `lax`, each one is dropped with a warning instead (an
+/// environment for its baseurl, a path for itself or its setup or teardown, a health gate
+/// for itself) and listed in `config.lax_skipped`.
+pub fn check_config(config: &mut Config, lax: bool) -> Result<(), String> {
+    let mut problems: Vec<String> = Vec::new();
+    let mut report = |problem: String| -> Result<(), String> {
+        if !lax {
+            return Err(problem);
+        }
+        eprintln!("Warning: skipping {} (--lax-config)", problem);
+        problems.push(problem);
+        Ok(())
+    };
+
+    let mut env_names: Vec<String> = config.environments.keys().cloned().collect();
+    env_names.sort();
+    for env_name in env_names {
+        let env = &config.environments[&env_name];
+        if let Err(e) = classify(&env.baseurl) {
+            report(format!(
+                "environment '{}': baseurl '{}': {}",
+                env_name, env.baseurl, e
+            ))?;
+            config.environments.remove(&env_name);
+            continue;
+        }
+        if let Some(gate) = &env.health_gate {
+            if let Err(e) = check_appended(gate) {
+                report(format!(
+                    "health_gate '{}' of environment '{}': {}",
+                    gate, env_name, e
+                ))?;
+                if let Some(env) = config.environments.get_mut(&env_name) {
+                    env.health_gate = None;
+                }
+            }
+        }
+    }
+
+    let mut kept = Vec::with_capacity(config.paths.len());
+    for path in std::mem::take(&mut config.paths) {
+        let sub_requests = [("setup", &path.setup), ("teardown", &path.teardown)];
+        let problem = check_appended(&path.path)
+            .map_err(|e| format!("path '{}': {}", path.path, e))
+            .and_then(|()| {
+                sub_requests
+                    .iter()
+                    .filter_map(|(kind, sub)| sub.as_ref().map(|sub| (kind, sub)))
+                    .try_for_each(|(kind, sub)| {
+                        check_sub_request(&sub.url).map_err(|e| {
+                            format!("path '{}': {} url '{}': {}", path.path, kind, sub.url, e)
+                        })
+                    })
+            });
+        match problem {
+            Ok(()) => kept.push(path),
+            Err(problem) => report(problem)?,
+        }
+    }
+    config.paths = kept;
+    config.lax_skipped = problems;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lax_config(toml: &str) -> (Config, Result<(), String>) {
+        let mut config: Config = toml::from_str(toml).unwrap();
+        let outcome = check_config(&mut config, true);
+        (config, outcome)
+    }
+
+    #[test]
+    fn classify_accepts_http_and_https() {
+        assert_eq!(classify("http://example.com"), Ok(Scheme::Http));
+        assert_eq!(classify("HTTPS://example.com/a"), Ok(Scheme::Https));
+    }
+
+    #[test]
+    fn classify_rejects_other_schemes() {
+        let ftp = classify("ftp://example.com/file").unwrap_err();
+        assert!(ftp.contains("unsupported scheme 'ftp'"), "{}", ftp);
+        let mailto = classify("mailto:ops@example.com").unwrap_err();
+        assert!(mailto.contains("unsupported scheme 'mailto'"), "{}", mailto);
+        let relative = classify("//example.com/path").unwrap_err();
+        assert!(relative.contains("scheme-relative"), "{}", relative);
+        assert!(classify("/just/a/path").is_err());
+    }
+
+    #[test]
+    fn appended_paths_must_not_be_urls() {
+        assert_eq!(check_appended("/api/items?id=1"), Ok(()));
+        assert!(check_appended("ftp://example.com/file")
+            .unwrap_err()
+            .contains("unsupported scheme 'ftp'"));
+        assert!(check_appended("mailto:ops@example.com")
+            .unwrap_err()
+            .contains("unsupported scheme 'mailto'"));
+        assert!(check_appended("//example.com/path")
+            .unwrap_err()
+            .contains("scheme-relative"));
+        assert!(check_appended("https://example.com/path")
+            .unwrap_err()
+            .contains("absolute URL"));
+    }
+
+    #[test]
+    fn sub_requests_may_be_absolute_http() {
+        assert_eq!(check_sub_request("/items"), Ok(()));
+        assert_eq!(check_sub_request("https://auth.example.com/token"), Ok(()));
+        assert!(check_sub_request("ftp://example.com/file").is_err());
+        assert!(check_sub_request("mailto:ops@example.com").is_err());
+        assert!(check_sub_request("//example.com/path")
+            .unwrap_err()
+            .contains("scheme-relative"));
+    }
+
+    #[test]
+    fn strict_mode_rejects_the_first_bad_entry() {
+        let mut config: Config = toml::from_str(
+            r#"
+            paths = ["/ok", "//cdn.example.com/app.js"]
+            [environments.prod]
+            baseurl = "https://example.com"
+            "#,
+        )
+        .unwrap();
+        let error = check_config(&mut config, false).unwrap_err();
+        assert!(error.contains("//cdn.example.com/app.js"), "{}", error);
+        assert!(config.lax_skipped.is_empty());
+    }
+
+    #[test]
+    fn lax_mode_drops_bad_entries() {
+        let (config, outcome) = lax_config(
+            r#"
+            paths = [
+                "/ok",
+                "ftp://example.com/file",
+                { path = "/with-setup", setup = { url = "mailto:ops@example.com" } },
+            ]
+            [environments.prod]
+            baseurl = "https://example.com"
+            health_gate = "//example.com/health"
+            [environments.legacy]
+            baseurl = "ftp://example.com"
+            "#,
+        );
+        assert_eq!(outcome, Ok(()));
+        let paths: Vec<&str> = config.paths.iter().map(|path| path.path.as_str()).collect();
+        assert_eq!(paths, ["/ok"]);
+        assert!(config.environments.contains_key("prod"));
+        assert!(!config.environments.contains_key("legacy"));
+        assert_eq!(config.environments["prod"].health_gate, None);
+        assert_eq!(config.lax_skipped.len(), 4);
+    }
+}
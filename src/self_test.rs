@@ -0,0 +1,226 @@
+//! `self-test`: runs the normal engine against a small HTTP server started in-process on a
+//! random localhost port, and checks that every scenario comes out as expected. When all of
+//! them pass, the tool works and failures elsewhere come from the network or the service.
+
+use clap::Parser;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    interrupt::Interrupt, paint, parse_config, run_tests, Args, ConnectionMode, HttpClients,
+    UrlTestResult, COLOR_GREEN, COLOR_RED,
+};
+
+/// A path of the generated config and what its result must look like.
+struct Scenario {
+    name: &'static str,
+    // Entry of the config's `paths` list
+    path: &'static str,
+    passed: bool,
+    status: u16,
+    // Text the error message must contain, for scenarios that fail
+    error_contains: Option<&'static str>,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "200 JSON",
+        path: r#"{ path = "/ok", require_valid_json = true, json_equals = { status = "ok" }, expect_json_array_min = { pointer = "/items", min = 3 } }"#,
+        passed: true,
+        status: 200,
+        error_contains: None,
+    },
+    Scenario {
+        name: "500 response",
+        path: r#""/server-error""#,
+        passed: false,
+        status: 500,
+        error_contains: Some("500"),
+    },
+    Scenario {
+        name: "slow response",
+        path: r#"{ path = "/slow", baseline_duration_secs = 0.05, regression_pct = 100 }"#,
+        passed: false,
+        status: 200,
+        error_contains: Some("above the 0.05s baseline"),
+    },
+    Scenario {
+        name: "app error body",
+        path: r#""/app-error""#,
+        passed: false,
+        status: 200,
+        error_contains: Some("App Error (code: E42)"),
+    },
+    Scenario {
+        name: "redirect asserted",
+        path: r#"{ path = "/redirect", expected_location = "/ok" }"#,
+        passed: true,
+        status: 302,
+        error_contains: None,
+    },
+    Scenario {
+        name: "redirect followed",
+        path: r#"{ path = "/redirect", require_valid_json = true }"#,
+        passed: true,
+        status: 200,
+        error_contains: None,
+    },
+];
+
+// How long `/slow` takes to answer
+const SLOW_MS: u64 = 300;
+
+/// Runs every scenario and prints one PASS/FAIL line each. Returns whether all passed.
+pub async fn run() -> Result<bool, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(serve(listener));
+    println!("Self-test server listening on 127.0.0.1:{}", port);
+
+    let paths: Vec<&str> = SCENARIOS.iter().map(|scenario| scenario.path).collect();
+    let config = parse_config(
+        &format!(
+            "paths = [\n    {},\n]\napp_error_code_to_fail = \"E42\"\n\n[environments.self-test]\nbaseurl = \"http://127.0.0.1:{}\"\n",
+            paths.join(",\n    "),
+            port
+        ),
+        &[],
+        false,
+    )?;
+    let args = Args::try_parse_from(["url_tester", "--config", "self-test", "--stable-output"])?;
+    // A configured proxy can't reach the local server, and would be blamed on the tool
+    let clients = HttpClients::without_proxy(ConnectionMode::from_args(&args))?;
+    let run = run_tests(
+        &config,
+        &args,
+        &clients,
+        None,
+        None,
+        true,
+        &Interrupt::never(),
+    )
+    .await?;
+
+    println!("\n--- Self-Test ---");
+    let mut all_passed = true;
+    for (index, scenario) in SCENARIOS.iter().enumerate() {
+        let result = run.results.iter().find(|res| res.path_index == index);
+        let problem = match result {
+            Some(result) => check(scenario, result),
+            None => Some("no result".to_string()),
+        };
+        all_passed &= problem.is_none();
+        match problem {
+            None => println!("{} | {}", paint("PASS", COLOR_GREEN, &args), scenario.name),
+            Some(problem) => println!(
+                "{} | {}: {}",
+                paint("FAIL", COLOR_RED, &args),
+                scenario.name,
+                problem
+            ),
+        }
+    }
+    println!(
+        "\n{}",
+        if all_passed {
+            "Self-test passed: requests and checks work as expected."
+        } else {
+            "Self-test failed: the tool itself misbehaves, independent of the network."
+        }
+    );
+    Ok(all_passed)
+}
+
+/// What's wrong with `result`, if it isn't what the scenario expects.
+fn check(scenario: &Scenario, result: &UrlTestResult) -> Option<String> {
+    let error = result.error_message.as_deref().unwrap_or("");
+    if result.passed != scenario.passed {
+        return Some(format!(
+            "expected the test to {}, it {}{}",
+            if scenario.passed { "pass" } else { "fail" },
+            if result.passed { "passed" } else { "failed" },
+            if error.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", error)
+            }
+        ));
+    }
+    if result.status_code != Some(scenario.status) {
+        return Some(format!(
+            "expected status {}, got {}",
+            scenario.status,
+            result
+                .status_code
+                .map_or("none".to_string(), |status| status.to_string())
+        ));
+    }
+    if let Some(expected) = scenario.error_contains {
+        if !error.contains(expected) {
+            return Some(format!(
+                "expected an error containing '{}', got '{}'",
+                expected, error
+            ));
+        }
+    }
+    None
+}
+
+async fn serve(listener: TcpListener) {
+    while let Ok((socket, _)) = listener.accept().await {
+        tokio::spawn(respond(socket));
+    }
+}
+
+/// Answers one request and closes the connection. Only the request line matters.
+async fn respond(mut socket: TcpStream) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = socket.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let target = head.split_whitespace().nth(1).unwrap_or("/");
+
+    let json = "application/json";
+    let (status, extra_header, content_type, body) = match target {
+        "/ok" => (
+            "200 OK",
+            None,
+            json,
+            r#"{"status": "ok", "items": [1, 2, 3]}"#,
+        ),
+        "/server-error" => (
+            "500 Internal Server Error",
+            None,
+            "text/plain",
+            "simulated failure",
+        ),
+        "/slow" => {
+            tokio::time::sleep(std::time::Duration::from_millis(SLOW_MS)).await;
+            ("200 OK", None, json, r#"{"status": "ok"}"#)
+        }
+        "/app-error" => (
+            "200 OK",
+            None,
+            json,
+            r#"{"code":"E42","message":"simulated application error"}"#,
+        ),
+        "/redirect" => ("302 Found", Some("Location: /ok\r\n"), "text/plain", ""),
+        _ => ("404 Not Found", None, "text/plain", "not found"),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        extra_header.unwrap_or(""),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
@@ -0,0 +1,426 @@
+//! Report files. Every output a run is written to is a `ReportSink`: it is handed each
+//! result in report order, then the completed run summary.
+
+use serde::Serialize;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::metadata::{self, RunMetadata};
+use crate::schema::SCHEMA_VERSION;
+use crate::{
+    annotation_names, csv_report, html_report, json_report, openmetrics_report, sqlite_report,
+    timestamp, Args, Config, JsonShape, RunSummary, UrlTestResult, DEFAULT_DASHBOARD_REFRESH_SECS,
+};
+
+type SinkResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// One output of a run.
+pub trait ReportSink<'a> {
+    /// Receives the next result. Every sink gets the same results in the same order.
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()>;
+
+    /// Completes the output once every result was recorded, returning what to tell the user.
+    fn finish(&mut self, summary: &RunSummary) -> SinkResult<String>;
+}
+
+/// Format of an `--output` file, picked by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+    Ndjson,
+    Html,
+}
+
+impl OutputFormat {
+    /// Anything without a known extension is CSV, which `--output` always wrote before it
+    /// learned other formats.
+    pub fn from_path(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("json") => OutputFormat::Json,
+            Some("ndjson" | "jsonl") => OutputFormat::Ndjson,
+            Some("html" | "htm") => OutputFormat::Html,
+            _ => OutputFormat::Csv,
+        }
+    }
+}
+
+/// Opens a sink for every `--output` file and for each format's own flag (`--json`,
+/// `--html`, `--dashboard`, `--openmetrics`, `--sqlite`), in that order.
+pub fn from_args<'a>(
+    args: &'a Args,
+    config: &'a Config,
+    run_metadata: &'a RunMetadata,
+    started_at: u64,
+) -> SinkResult<Vec<Box<dyn ReportSink<'a> + 'a>>> {
+    let mut sinks: Vec<Box<dyn ReportSink<'a> + 'a>> = Vec::new();
+    let outputs = args
+        .output
+        .iter()
+        .map(|path| (path, OutputFormat::from_path(path)))
+        .chain(args.json.iter().map(|path| (path, OutputFormat::Json)))
+        .chain(args.html.iter().map(|path| (path, OutputFormat::Html)));
+    for (path, format) in outputs {
+        let sink: Box<dyn ReportSink<'a> + 'a> = match format {
+            OutputFormat::Csv => Box::new(CsvSink {
+                path,
+                server_timing_metrics: &config.server_timing_metrics,
+                annotation_names: annotation_names(config),
+                run_metadata,
+                results: Vec::new(),
+            }),
+            OutputFormat::Json => Box::new(JsonSink {
+                path,
+                shape: args.json_shape,
+                run_metadata,
+                results: Vec::new(),
+            }),
+            OutputFormat::Ndjson => Box::new(NdjsonSink::create(path, run_metadata)?),
+            OutputFormat::Html => Box::new(HtmlSink {
+                path,
+                results: Vec::new(),
+            }),
+        };
+        sinks.push(sink);
+    }
+    if let Some(path) = &args.dashboard {
+        let refresh_secs = args
+            .dashboard_refresh
+            .or(args.watch)
+            .unwrap_or(DEFAULT_DASHBOARD_REFRESH_SECS)
+            .max(1);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        sinks.push(Box::new(DashboardSink {
+            path,
+            refresh_secs,
+            updated_at: timestamp(args, now),
+            results: Vec::new(),
+        }));
+    }
+    if let Some(path) = &args.openmetrics {
+        sinks.push(Box::new(OpenMetricsSink {
+            path,
+            exemplars: args.openmetrics_exemplars,
+            results: Vec::new(),
+        }));
+    }
+    if let Some(path) = &args.sqlite {
+        sinks.push(Box::new(SqliteSink {
+            path,
+            started_at,
+            results: Vec::new(),
+        }));
+    }
+    Ok(sinks)
+}
+
+/// CSV report plus its metadata sidecar.
+struct CsvSink<'a> {
+    path: &'a str,
+    server_timing_metrics: &'a [String],
+    annotation_names: Vec<String>,
+    run_metadata: &'a RunMetadata,
+    results: Vec<&'a UrlTestResult>,
+}
+
+impl<'a> ReportSink<'a> for CsvSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(&mut self, _summary: &RunSummary) -> SinkResult<String> {
+        csv_report::write_csv_report(
+            self.path,
+            &self.results,
+            self.server_timing_metrics,
+            &self.annotation_names,
+        )?;
+        let sidecar_path = metadata::write_csv_sidecar(self.path, self.run_metadata)?;
+        Ok(format!(
+            "CSV report saved to: {}\nRun metadata saved to: {}",
+            self.path, sidecar_path
+        ))
+    }
+}
+
+struct JsonSink<'a> {
+    path: &'a str,
+    shape: JsonShape,
+    run_metadata: &'a RunMetadata,
+    results: Vec<&'a UrlTestResult>,
+}
+
+impl<'a> ReportSink<'a> for JsonSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &RunSummary) -> SinkResult<String> {
+        json_report::write_json_report(
+            self.path,
+            self.shape,
+            self.run_metadata,
+            summary,
+            &self.results,
+        )?;
+        Ok(format!("JSON report saved to: {}", self.path))
+    }
+}
+
+/// One line of an NDJSON report, in the same shape as the `[stream]` events.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum NdjsonLine<'a> {
+    Result {
+        result: &'a UrlTestResult,
+    },
+    RunComplete {
+        schema_version: u32,
+        metadata: &'a RunMetadata,
+        summary: &'a RunSummary,
+    },
+}
+
+/// NDJSON report, written a line at a time: each result as it is recorded, then the summary.
+struct NdjsonSink<'a> {
+    path: &'a str,
+    run_metadata: &'a RunMetadata,
+    writer: BufWriter<fs::File>,
+}
+
+impl<'a> NdjsonSink<'a> {
+    fn create(path: &'a str, run_metadata: &'a RunMetadata) -> SinkResult<Self> {
+        let file = fs::File::create(path)
+            .map_err(|e| format!("Failed to create NDJSON report '{}': {}", path, e))?;
+        Ok(NdjsonSink {
+            path,
+            run_metadata,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    fn write_line(&mut self, line: &NdjsonLine) -> SinkResult<()> {
+        serde_json::to_writer(&mut self.writer, line)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl<'a> ReportSink<'a> for NdjsonSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.write_line(&NdjsonLine::Result { result })
+    }
+
+    fn finish(&mut self, summary: &RunSummary) -> SinkResult<String> {
+        self.write_line(&NdjsonLine::RunComplete {
+            schema_version: SCHEMA_VERSION,
+            metadata: self.run_metadata,
+            summary,
+        })?;
+        self.writer.flush()?;
+        Ok(format!("NDJSON report saved to: {}", self.path))
+    }
+}
+
+struct HtmlSink<'a> {
+    path: &'a str,
+    results: Vec<&'a UrlTestResult>,
+}
+
+impl<'a> ReportSink<'a> for HtmlSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &RunSummary) -> SinkResult<String> {
+        html_report::write_html_report(self.path, summary, &self.results)?;
+        Ok(format!("HTML report saved to: {}", self.path))
+    }
+}
+
+struct DashboardSink<'a> {
+    path: &'a str,
+    refresh_secs: u64,
+    updated_at: String,
+    results: Vec<&'a UrlTestResult>,
+}
+
+impl<'a> ReportSink<'a> for DashboardSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &RunSummary) -> SinkResult<String> {
+        html_report::write_dashboard(
+            self.path,
+            summary,
+            &self.results,
+            self.refresh_secs,
+            &self.updated_at,
+        )
+        .map_err(|e| format!("Failed to write dashboard '{}': {}", self.path, e))?;
+        Ok(format!("Dashboard updated: {}", self.path))
+    }
+}
+
+struct OpenMetricsSink<'a> {
+    path: &'a str,
+    exemplars: bool,
+    results: Vec<&'a UrlTestResult>,
+}
+
+impl<'a> ReportSink<'a> for OpenMetricsSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(&mut self, _summary: &RunSummary) -> SinkResult<String> {
+        openmetrics_report::write_openmetrics_report(self.path, &self.results, self.exemplars)?;
+        Ok(format!("OpenMetrics report saved to: {}", self.path))
+    }
+}
+
+struct SqliteSink<'a> {
+    path: &'a str,
+    started_at: u64,
+    results: Vec<&'a UrlTestResult>,
+}
+
+impl<'a> ReportSink<'a> for SqliteSink<'a> {
+    fn record(&mut self, result: &'a UrlTestResult) -> SinkResult<()> {
+        self.results.push(result);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &RunSummary) -> SinkResult<String> {
+        let run_id =
+            sqlite_report::write_sqlite_report(self.path, self.started_at, summary, &self.results)
+                .map_err(|e| format!("Failed to write SQLite database '{}': {}", self.path, e))?;
+        Ok(format!(
+            "Results saved to SQLite: {} (run {})",
+            self.path, run_id
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_summary, TestRun};
+    use clap::Parser;
+
+    #[test]
+    fn every_sink_receives_the_same_results() {
+        let config: Config = toml::from_str(
+            r#"
+            paths = ["/a", "/b", "/c"]
+            [environments.prod]
+            baseurl = "https://example.com"
+            "#,
+        )
+        .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("url_tester_sinks_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let (csv_path, ndjson_path, json_path) = (
+            file("report.csv"),
+            file("report.ndjson"),
+            file("report.json"),
+        );
+        let args = Args::try_parse_from([
+            "url_tester",
+            "--config",
+            "config.toml",
+            "--output",
+            &csv_path,
+            "--output",
+            &ndjson_path,
+            "--json",
+            &json_path,
+        ])
+        .unwrap();
+        let mut run = TestRun::empty(&config, &args, "unused");
+        run.nothing_ran = None;
+        for (path, passed) in [("/a", true), ("/b", false), ("/c", true)] {
+            run.results.push(UrlTestResult {
+                environment_name: "prod".to_string(),
+                url: format!("https://example.com{}", path),
+                test_id: format!("id{}", path),
+                passed,
+                ..Default::default()
+            });
+        }
+
+        let summary = run_summary(&run, &config, &args, 1);
+        let run_metadata = RunMetadata::new(&args, &run);
+        let mut sinks = from_args(&args, &config, &run_metadata, run.started_at).unwrap();
+        assert_eq!(sinks.len(), 3);
+        for res in &run.results {
+            for sink in sinks.iter_mut() {
+                sink.record(res).unwrap();
+            }
+        }
+        for sink in sinks.iter_mut() {
+            sink.finish(&summary).unwrap();
+        }
+
+        let expected: Vec<(String, bool)> = run
+            .results
+            .iter()
+            .map(|res| (res.test_id.clone(), res.passed))
+            .collect();
+        let from_json = |value: &serde_json::Value| {
+            (
+                value["test_id"].as_str().unwrap().to_string(),
+                value["passed"].as_bool().unwrap(),
+            )
+        };
+        let mut csv_reader = csv::Reader::from_path(&csv_path).unwrap();
+        let headers = csv_reader.headers().unwrap().clone();
+        let column = |name: &str| headers.iter().position(|header| header == name).unwrap();
+        let (id_column, passed_column) = (column("test_id"), column("passed"));
+        let csv_rows: Vec<(String, bool)> = csv_reader
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                (
+                    record[id_column].to_string(),
+                    record[passed_column].parse().unwrap(),
+                )
+            })
+            .collect();
+        let ndjson_rows: Vec<(String, bool)> = fs::read_to_string(&ndjson_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|value| value["event"] == "result")
+            .map(|value| from_json(&value["result"]))
+            .collect();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+        let json_rows: Vec<(String, bool)> = json["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(from_json)
+            .collect();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(csv_rows, expected);
+        assert_eq!(ndjson_rows, expected);
+        assert_eq!(json_rows, expected);
+    }
+}
@@ -0,0 +1,91 @@
+//! SQLite output for `--sqlite`, accumulating runs in one database file.
+
+use rusqlite::{params, Connection};
+
+use crate::{RunSummary, UrlTestResult};
+
+// Created on first use; later runs append to the same tables
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at INTEGER NOT NULL,
+    total INTEGER NOT NULL,
+    passed INTEGER NOT NULL,
+    failed INTEGER NOT NULL,
+    duration_secs REAL NOT NULL,
+    config_hash TEXT NOT NULL,
+    exit_code INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS results (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    recorded_at INTEGER NOT NULL,
+    test_id TEXT NOT NULL,
+    environment_name TEXT NOT NULL,
+    url TEXT NOT NULL,
+    status_code INTEGER,
+    passed INTEGER NOT NULL,
+    error_message TEXT,
+    duration_secs REAL NOT NULL,
+    failure_kind TEXT,
+    -- Every field of the result, for columns added after the table was created
+    details TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS results_test_id ON results (test_id, recorded_at);
+";
+
+/// Inserts the run and its results into the database at `path`, creating it if needed.
+/// Timestamps are Unix seconds. Returns the new run's id.
+pub fn write_sqlite_report(
+    path: &str,
+    started_at: u64,
+    summary: &RunSummary,
+    results: &[&UrlTestResult],
+) -> Result<i64, Box<dyn std::error::Error>> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    // One transaction, so a failed write doesn't leave half a run behind
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO runs (started_at, total, passed, failed, duration_secs, config_hash, exit_code)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            started_at as i64,
+            summary.total as i64,
+            summary.passed as i64,
+            summary.failed as i64,
+            summary.duration_secs,
+            summary.config_hash,
+            summary.exit_code,
+        ],
+    )?;
+    let run_id = tx.last_insert_rowid();
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO results (run_id, recorded_at, test_id, environment_name, url, status_code,
+                                  passed, error_message, duration_secs, failure_kind, details)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for res in results {
+            let failure_kind = res
+                .failure_kind
+                .and_then(|kind| serde_json::to_value(kind).ok())
+                .and_then(|kind| kind.as_str().map(str::to_string));
+            insert.execute(params![
+                run_id,
+                started_at as i64,
+                res.test_id,
+                res.environment_name,
+                res.url,
+                res.status_code,
+                res.passed,
+                res.error_message,
+                res.duration_secs,
+                failure_kind,
+                serde_json::to_string(res)?,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(run_id)
+}
@@ -0,0 +1,166 @@
+//! Streaming of results to an HTTP endpoint as they complete, configured in `[stream]`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{RunSummary, UrlTestResult};
+
+/// The `[stream]` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamConfig {
+    // Every POST goes here, with a JSON array of events as the body
+    pub endpoint: String,
+    // Results are sent once this many have been collected...
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    // ...or once the oldest unsent result is this old, whichever comes first
+    #[serde(default)]
+    pub batch_interval_secs: Option<f64>,
+    // Sent with every POST, e.g. an `Authorization` header
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    // Extra attempts for a failed POST before its events are dropped
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+/// One element of a POSTed batch.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Result { result: serde_json::Value },
+    RunComplete { summary: serde_json::Value },
+}
+
+enum Message {
+    Result(serde_json::Value),
+    // Flushes pending results, sends the summary and acknowledges once it was delivered or dropped
+    Complete(serde_json::Value, oneshot::Sender<()>),
+}
+
+/// Handle to the background task delivering events. Sending never blocks the tests, and a
+/// delivery failure is only reported on stderr.
+pub struct ResultStream {
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl ResultStream {
+    /// Starts the delivery task with its own HTTP client, so streaming never competes
+    /// with the tests for pooled connections.
+    pub fn start(config: &StreamConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &config.headers {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("[stream]: invalid header name '{}'", name))?;
+            let value = reqwest::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("[stream]: invalid value for header '{}'", name))?;
+            headers.insert(name, value);
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .default_headers(headers)
+            .build()?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(deliver(client, config.clone(), receiver));
+        Ok(ResultStream { sender })
+    }
+
+    pub fn send_result(&self, result: &UrlTestResult) {
+        if let Ok(value) = serde_json::to_value(result) {
+            let _ = self.sender.send(Message::Result(value));
+        }
+    }
+
+    /// Sends the final "run complete" event and waits until it has been handled.
+    pub async fn complete(&self, summary: &RunSummary) {
+        let Ok(value) = serde_json::to_value(summary) else {
+            return;
+        };
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(Message::Complete(value, ack)).is_ok() {
+            let _ = done.await;
+        }
+    }
+}
+
+async fn deliver(
+    client: reqwest::Client,
+    config: StreamConfig,
+    mut receiver: mpsc::UnboundedReceiver<Message>,
+) {
+    let batch_size = config.batch_size.max(1);
+    let interval = config.batch_interval_secs.map(Duration::from_secs_f64);
+    let mut pending: Vec<Event> = Vec::new();
+    let mut deadline: Option<tokio::time::Instant> = None;
+
+    loop {
+        let message = match deadline {
+            Some(at) => match tokio::time::timeout_at(at, receiver.recv()).await {
+                Ok(message) => message,
+                Err(_) => {
+                    post(&client, &config, std::mem::take(&mut pending)).await;
+                    deadline = None;
+                    continue;
+                }
+            },
+            None => receiver.recv().await,
+        };
+        match message {
+            Some(Message::Result(result)) => {
+                if pending.is_empty() {
+                    deadline = interval.map(|interval| tokio::time::Instant::now() + interval);
+                }
+                pending.push(Event::Result { result });
+                if pending.len() >= batch_size {
+                    post(&client, &config, std::mem::take(&mut pending)).await;
+                    deadline = None;
+                }
+            }
+            Some(Message::Complete(summary, ack)) => {
+                pending.push(Event::RunComplete { summary });
+                post(&client, &config, std::mem::take(&mut pending)).await;
+                deadline = None;
+                let _ = ack.send(());
+            }
+            None => {
+                post(&client, &config, std::mem::take(&mut pending)).await;
+                return;
+            }
+        }
+    }
+}
+
+// POSTs one batch, retrying with a growing delay, and drops it with a warning if every
+// attempt fails
+async fn post(client: &reqwest::Client, config: &StreamConfig, events: Vec<Event>) {
+    if events.is_empty() {
+        return;
+    }
+    let mut last_error = String::new();
+    for attempt in 0..=config.retries {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_millis(500 * u64::from(attempt))).await;
+        }
+        match client.post(&config.endpoint).json(&events).send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => last_error = format!("HTTP Status Error: {}", response.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    eprintln!(
+        "WARNING: dropped {} event(s) for stream endpoint {}: {}",
+        events.len(),
+        config.endpoint,
+        last_error
+    );
+}
@@ -0,0 +1,35 @@
+//! `--throttle-kbps`: simulates a slow link, e.g. a constrained mobile connection, by pacing
+//! how fast each response body is read. Connecting, sending the request and waiting for the
+//! response headers are not slowed down.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Paces the reads of one response body to a rate in kilobits per second.
+pub struct Pacer {
+    bytes_per_sec: f64,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Pacer {
+    pub fn new(kbps: u32) -> Self {
+        Pacer {
+            bytes_per_sec: f64::from(kbps) * 1000.0 / 8.0,
+            started: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Waits until `len` more bytes would have arrived over the simulated link.
+    pub async fn pace(&mut self, len: usize) {
+        self.bytes += len as u64;
+        let due = self.started + Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_sec);
+        tokio::time::sleep_until(due).await;
+    }
+}
+
+/// How the rate shows in the console and in baseline warnings.
+pub fn describe(kbps: Option<u32>) -> String {
+    kbps.map_or("unthrottled".to_string(), |kbps| format!("{} kbps", kbps))
+}
@@ -0,0 +1,579 @@
+//! UTC offsets of IANA time zones, read from the system's zoneinfo database (the TZif
+//! files under `/usr/share/zoneinfo`, or `$TZDIR`), the calendar arithmetic around them,
+//! and the formatting of the timestamps written to the outputs.
+
+use std::fs;
+use std::sync::OnceLock;
+
+/// A time zone loaded from zoneinfo: its transitions, and the rule for times after them.
+pub struct TimeZone {
+    // (transition time, UTC offset from then on), ascending
+    transitions: Vec<(i64, i64)>,
+    // Offset before the first transition
+    initial_offset: i64,
+    // The POSIX TZ footer, which covers times after the last transition
+    rule: Option<PosixRule>,
+}
+
+impl TimeZone {
+    /// Loads `name` (e.g. "Australia/Sydney"). "UTC" works without a zoneinfo database.
+    pub fn load(name: &str) -> Result<TimeZone, String> {
+        if name.eq_ignore_ascii_case("UTC") && zoneinfo_path(name).is_none() {
+            return Ok(TimeZone::fixed(0));
+        }
+        let path = zoneinfo_path(name).ok_or_else(|| format!("unknown timezone '{}'", name))?;
+        let data = fs::read(&path).map_err(|e| format!("unknown timezone '{}': {}", name, e))?;
+        parse_tzif(&data).ok_or_else(|| format!("timezone '{}': unreadable zoneinfo file", name))
+    }
+
+    /// The machine's zone: `$TZ` (a zone name or a POSIX TZ string), else `/etc/localtime`,
+    /// else UTC.
+    pub fn local() -> TimeZone {
+        if let Ok(tz) = std::env::var("TZ") {
+            let name = tz.strip_prefix(':').unwrap_or(&tz);
+            if let Ok(zone) = TimeZone::load(name) {
+                return zone;
+            }
+            if let Some(rule) = PosixRule::parse(name) {
+                return TimeZone {
+                    transitions: Vec::new(),
+                    initial_offset: rule.std_offset,
+                    rule: Some(rule),
+                };
+            }
+        }
+        fs::read("/etc/localtime")
+            .ok()
+            .and_then(|data| parse_tzif(&data))
+            .unwrap_or_else(|| TimeZone::fixed(0))
+    }
+
+    fn fixed(offset: i64) -> TimeZone {
+        TimeZone {
+            transitions: Vec::new(),
+            initial_offset: offset,
+            rule: None,
+        }
+    }
+
+    /// Offset from UTC, in seconds, at the Unix time `at`.
+    pub fn offset_at(&self, at: i64) -> i64 {
+        match self.transitions.iter().rposition(|(time, _)| *time <= at) {
+            // Before the first transition, unless there are none and the rule is all there is
+            None if !self.transitions.is_empty() || self.rule.is_none() => self.initial_offset,
+            Some(index) if index + 1 < self.transitions.len() => self.transitions[index].1,
+            last => match &self.rule {
+                Some(rule) => rule.offset_at(at),
+                None => last.map_or(self.initial_offset, |index| self.transitions[index].1),
+            },
+        }
+    }
+}
+
+/// `--time-format` value selecting RFC 3339 rather than a custom pattern.
+pub const RFC3339: &str = "rfc3339";
+
+// strftime conversions `format_timestamp` understands, after the `%`
+const CONVERSIONS: &[&str] = &[
+    "Y", "y", "m", "d", "H", "M", "S", "j", "F", "T", "z", ":z", "Z", "s", "%",
+];
+
+/// Checks a `--time-format` value: "rfc3339", or a strftime-style pattern using only the
+/// conversions `format_timestamp` supports.
+pub fn parse_time_format(format: &str) -> Result<String, String> {
+    if format.eq_ignore_ascii_case(RFC3339) {
+        return Ok(RFC3339.to_string());
+    }
+    let mut rest = format;
+    while let Some(at) = rest.find('%') {
+        let spec = &rest[at + 1..];
+        let Some(conversion) = CONVERSIONS.iter().find(|c| spec.starts_with(**c)) else {
+            return Err(format!(
+                "unsupported conversion '%{}' (supported: %{})",
+                spec.chars().next().unwrap_or(' '),
+                CONVERSIONS.join(", %")
+            ));
+        };
+        rest = &spec[conversion.len()..];
+    }
+    Ok(format.to_string())
+}
+
+/// Formats a Unix timestamp with a `--time-format` value, in UTC or in the machine's zone.
+pub fn format_timestamp(unix_secs: u64, format: &str, local: bool) -> String {
+    static LOCAL_ZONE: OnceLock<TimeZone> = OnceLock::new();
+    let at = unix_secs as i64;
+    let offset = if local {
+        LOCAL_ZONE.get_or_init(TimeZone::local).offset_at(at)
+    } else {
+        0
+    };
+    let local_secs = at + offset;
+    let (year, month, day) = civil_from_days(local_secs.div_euclid(86_400));
+    let secs_of_day = local_secs.rem_euclid(86_400);
+    let (hour, minute, second) = (
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+    );
+    let numeric_offset = |separator: &str| {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let minutes = offset.abs() / 60;
+        format!(
+            "{}{:02}{}{:02}",
+            sign,
+            minutes / 60,
+            separator,
+            minutes % 60
+        )
+    };
+
+    if format == RFC3339 {
+        let zone = if local {
+            numeric_offset(":")
+        } else {
+            "Z".to_string()
+        };
+        return format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+            year, month, day, hour, minute, second, zone
+        );
+    }
+
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(at) = rest.find('%') {
+        out.push_str(&rest[..at]);
+        let spec = &rest[at + 1..];
+        // Unknown conversions are rejected by `parse_time_format`; kept as-is just in case
+        let Some(conversion) = CONVERSIONS.iter().find(|c| spec.starts_with(**c)) else {
+            out.push('%');
+            rest = spec;
+            continue;
+        };
+        match *conversion {
+            "Y" => out.push_str(&format!("{:04}", year)),
+            "y" => out.push_str(&format!("{:02}", year.rem_euclid(100))),
+            "m" => out.push_str(&format!("{:02}", month)),
+            "d" => out.push_str(&format!("{:02}", day)),
+            "H" => out.push_str(&format!("{:02}", hour)),
+            "M" => out.push_str(&format!("{:02}", minute)),
+            "S" => out.push_str(&format!("{:02}", second)),
+            "j" => {
+                let day_of_year = days_from_civil(year, month, day) - days_from_civil(year, 1, 1);
+                out.push_str(&format!("{:03}", day_of_year + 1));
+            }
+            "F" => out.push_str(&format!("{:04}-{:02}-{:02}", year, month, day)),
+            "T" => out.push_str(&format!("{:02}:{:02}:{:02}", hour, minute, second)),
+            "z" => out.push_str(&numeric_offset("")),
+            ":z" => out.push_str(&numeric_offset(":")),
+            // Zone abbreviations aren't kept, so local times get their offset instead
+            "Z" if local => out.push_str(&numeric_offset(":")),
+            "Z" => out.push_str("UTC"),
+            "s" => out.push_str(&unix_secs.to_string()),
+            _ => out.push('%'),
+        }
+        rest = &spec[conversion.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// Zone names are relative paths into the database; anything that could escape it is refused
+fn zoneinfo_path(name: &str) -> Option<std::path::PathBuf> {
+    if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+        return None;
+    }
+    let dir = std::env::var("TZDIR").unwrap_or_else(|_| "/usr/share/zoneinfo".to_string());
+    let path = std::path::Path::new(&dir).join(name);
+    path.is_file().then_some(path)
+}
+
+fn parse_tzif(data: &[u8]) -> Option<TimeZone> {
+    let header = TzifHeader::parse(data)?;
+    // Version 2+ files repeat the data with 64-bit times after the 32-bit block
+    let (header, body, time_size) = if header.version >= b'2' {
+        let rest = data.get(44 + header.block_len(4)..)?;
+        (TzifHeader::parse(rest)?, rest.get(44..)?, 8)
+    } else {
+        (header, data.get(44..)?, 4)
+    };
+
+    let read_time = |at: usize| -> Option<i64> {
+        let bytes = body.get(at..at + time_size)?;
+        Some(if time_size == 8 {
+            i64::from_be_bytes(bytes.try_into().ok()?)
+        } else {
+            i64::from(i32::from_be_bytes(bytes.try_into().ok()?))
+        })
+    };
+    let types_at = header.timecnt * time_size + header.timecnt;
+    let type_offset = |index: usize| -> Option<i64> {
+        let at = types_at + index * 6;
+        let bytes = body.get(at..at + 4)?;
+        Some(i64::from(i32::from_be_bytes(bytes.try_into().ok()?)))
+    };
+
+    let mut transitions = Vec::with_capacity(header.timecnt);
+    for i in 0..header.timecnt {
+        let time = read_time(i * time_size)?;
+        let type_index = *body.get(header.timecnt * time_size + i)? as usize;
+        transitions.push((time, type_offset(type_index)?));
+    }
+    let initial_offset = type_offset(0)?;
+
+    let rule = if time_size == 8 {
+        let footer = body.get(header.block_len(8)..)?;
+        std::str::from_utf8(footer)
+            .ok()
+            .map(|footer| footer.trim_matches('\n'))
+            .filter(|footer| !footer.is_empty())
+            .and_then(PosixRule::parse)
+    } else {
+        None
+    };
+    Some(TimeZone {
+        transitions,
+        initial_offset,
+        rule,
+    })
+}
+
+struct TzifHeader {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl TzifHeader {
+    fn parse(data: &[u8]) -> Option<TzifHeader> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let count = |index: usize| -> Option<usize> {
+            let at = 20 + index * 4;
+            Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize)
+        };
+        Some(TzifHeader {
+            version: *data.get(4)?,
+            isutcnt: count(0)?,
+            isstdcnt: count(1)?,
+            leapcnt: count(2)?,
+            timecnt: count(3)?,
+            typecnt: count(4)?,
+            charcnt: count(5)?,
+        })
+    }
+
+    // Length of the data block following the header, for times of `time_size` bytes
+    fn block_len(&self, time_size: usize) -> usize {
+        self.timecnt * time_size
+            + self.timecnt
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+/// A POSIX TZ string such as "AEST-10AEDT,M10.1.0,M4.1.0/3".
+struct PosixRule {
+    std_offset: i64,
+    dst: Option<DstRule>,
+}
+
+struct DstRule {
+    offset: i64,
+    start: (RuleDate, i64),
+    end: (RuleDate, i64),
+}
+
+enum RuleDate {
+    // Mm.w.d: day d (0 = Sunday) of week w (5 = last) of month m
+    MonthWeekDay(u32, u32, u32),
+    // Jn: day n (1-365) not counting February 29
+    Julian(u32),
+    // n: day n (0-365) counting February 29
+    ZeroBased(u32),
+}
+
+impl PosixRule {
+    fn parse(text: &str) -> Option<PosixRule> {
+        let mut rest = text;
+        skip_name(&mut rest)?;
+        let std_offset = -parse_hms(&mut rest)?;
+        if rest.is_empty() {
+            return Some(PosixRule {
+                std_offset,
+                dst: None,
+            });
+        }
+        skip_name(&mut rest)?;
+        let dst_offset = if rest.starts_with(',') {
+            std_offset + 3600
+        } else {
+            -parse_hms(&mut rest)?
+        };
+        let (start, end) = rest.strip_prefix(',')?.split_once(',')?;
+        let start = parse_transition(start)?;
+        let end = parse_transition(end)?;
+        Some(PosixRule {
+            std_offset,
+            dst: Some(DstRule {
+                offset: dst_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn offset_at(&self, at: i64) -> i64 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+        let (year, _, _) = civil_from_days((at + self.std_offset).div_euclid(86_400));
+        // Transition times are given in the local time in effect before each transition
+        let start =
+            days_from_civil_date(year, &dst.start.0) * 86_400 + dst.start.1 - self.std_offset;
+        let end = days_from_civil_date(year, &dst.end.0) * 86_400 + dst.end.1 - dst.offset;
+        let in_dst = if start < end {
+            start <= at && at < end
+        } else {
+            // Southern hemisphere: daylight saving time spans the new year
+            !(end <= at && at < start)
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+impl RuleDate {
+    fn parse(text: &str) -> Option<RuleDate> {
+        if let Some(mwd) = text.strip_prefix('M') {
+            let mut parts = mwd.split('.').map(|part| part.parse::<u32>().ok());
+            let (month, week, day) = (parts.next()??, parts.next()??, parts.next()??);
+            return Some(RuleDate::MonthWeekDay(month, week, day));
+        }
+        if let Some(day) = text.strip_prefix('J') {
+            return day.parse().ok().map(RuleDate::Julian);
+        }
+        text.parse().ok().map(RuleDate::ZeroBased)
+    }
+}
+
+// Days since 1970-01-01 of a rule's date in `year`
+fn days_from_civil_date(year: i64, date: &RuleDate) -> i64 {
+    let jan1 = days_from_civil(year, 1, 1);
+    match *date {
+        RuleDate::MonthWeekDay(month, week, weekday) => {
+            let first = days_from_civil(year, month, 1);
+            // 1970-01-01 was a Thursday
+            let first_weekday = (first + 4).rem_euclid(7) as u32;
+            let mut day = first + i64::from((weekday + 7 - first_weekday) % 7);
+            day += 7 * i64::from(week.clamp(1, 5) - 1);
+            let next_month = if month == 12 {
+                days_from_civil(year + 1, 1, 1)
+            } else {
+                days_from_civil(year, month + 1, 1)
+            };
+            while day >= next_month {
+                day -= 7;
+            }
+            day
+        }
+        RuleDate::Julian(n) => {
+            let leap_day = i64::from(is_leap_year(year) && n >= 60);
+            jan1 + i64::from(n) - 1 + leap_day
+        }
+        RuleDate::ZeroBased(n) => jan1 + i64::from(n),
+    }
+}
+
+// Parses "date[/time]" of a rule; the time of day defaults to 02:00
+fn parse_transition(text: &str) -> Option<(RuleDate, i64)> {
+    let (date, mut time) = text.split_once('/').unwrap_or((text, "2"));
+    Some((RuleDate::parse(date)?, parse_hms(&mut time)?))
+}
+
+// Skips a zone abbreviation: letters, or anything quoted in <...>
+fn skip_name(text: &mut &str) -> Option<()> {
+    if let Some(quoted) = text.strip_prefix('<') {
+        *text = &quoted[quoted.find('>')? + 1..];
+    } else {
+        let len = text
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(text.len());
+        if len < 3 {
+            return None;
+        }
+        *text = &text[len..];
+    }
+    Some(())
+}
+
+// Parses "[+-]hh[:mm[:ss]]" into seconds, advancing past it
+fn parse_hms(text: &mut &str) -> Option<i64> {
+    let len = text
+        .find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '+' || c == '-'))
+        .unwrap_or(text.len());
+    let (hms, rest) = text.split_at(len);
+    *text = rest;
+    let (sign, hms) = match hms.strip_prefix('-') {
+        Some(hms) => (-1, hms),
+        None => (1, hms.strip_prefix('+').unwrap_or(hms)),
+    };
+    let mut secs = 0;
+    for (part, scale) in hms.split(':').zip([3600, 60, 1]) {
+        secs += part.parse::<i64>().ok()? * scale;
+    }
+    Some(sign * secs)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Days since 1970-01-01 of a civil date (Howard Hinnant's algorithm).
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Civil date (year, month, day) of a number of days since 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unix time of a UTC date and time
+    fn utc(year: i64, month: u32, day: u32, hour: i64, minute: i64) -> i64 {
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60
+    }
+
+    // The zone a TZif file with no transitions left but this footer would give
+    fn rule_only(rule: &str) -> TimeZone {
+        let rule = PosixRule::parse(rule).unwrap();
+        TimeZone {
+            transitions: Vec::new(),
+            initial_offset: rule.std_offset,
+            rule: Some(rule),
+        }
+    }
+
+    const SYDNEY: &str = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+
+    #[test]
+    fn sydney_offset_changes_at_both_transitions() {
+        let sydney = rule_only(SYDNEY);
+        // 2024-04-07 03:00 AEDT, first Sunday of April: clocks go back to 02:00 AEST
+        let dst_ends = utc(2024, 4, 6, 16, 0);
+        assert_eq!(sydney.offset_at(dst_ends - 1), 11 * 3600);
+        assert_eq!(sydney.offset_at(dst_ends), 10 * 3600);
+        // 2024-10-06 02:00 AEST, first Sunday of October: clocks go forward to 03:00 AEDT
+        let dst_starts = utc(2024, 10, 5, 16, 0);
+        assert_eq!(sydney.offset_at(dst_starts - 1), 10 * 3600);
+        assert_eq!(sydney.offset_at(dst_starts), 11 * 3600);
+        // Daylight saving time spans the new year
+        assert_eq!(sydney.offset_at(utc(2024, 12, 31, 12, 0)), 11 * 3600);
+        assert_eq!(sydney.offset_at(utc(2024, 7, 1, 0, 0)), 10 * 3600);
+    }
+
+    #[test]
+    fn cross_midnight_allowed_hours_follow_the_local_clock() {
+        let sydney = rule_only(SYDNEY);
+        let window = crate::HoursWindow::parse("20:00-06:00").unwrap();
+        let minute_of_day = |at: i64| ((at + sydney.offset_at(at)).rem_euclid(86_400) / 60) as u32;
+        // 2024-01-15 in Sydney is AEDT, UTC+11
+        assert!(window.contains(minute_of_day(utc(2024, 1, 15, 9, 0)))); // 20:00
+        assert!(window.contains(minute_of_day(utc(2024, 1, 15, 12, 59)))); // 23:59
+        assert!(window.contains(minute_of_day(utc(2024, 1, 15, 13, 0)))); // 00:00
+        assert!(window.contains(minute_of_day(utc(2024, 1, 15, 18, 59)))); // 05:59
+        assert!(!window.contains(minute_of_day(utc(2024, 1, 15, 19, 0)))); // 06:00
+        assert!(!window.contains(minute_of_day(utc(2024, 1, 15, 8, 59)))); // 19:59
+                                                                           // The same UTC time is an hour earlier locally in July, under AEST
+        assert!(!window.contains(minute_of_day(utc(2024, 7, 15, 9, 0)))); // 19:00
+    }
+
+    #[test]
+    fn week_five_is_the_last_weekday_of_the_month() {
+        let last_sunday = |year| days_from_civil_date(year, &RuleDate::MonthWeekDay(10, 5, 0));
+        // October 2021 has five Sundays, October 2024 only four
+        assert_eq!(last_sunday(2021), days_from_civil(2021, 10, 31));
+        assert_eq!(last_sunday(2024), days_from_civil(2024, 10, 27));
+
+        let berlin = rule_only("CET-1CEST,M3.5.0,M10.5.0/3");
+        // 2024-03-31 02:00 CET and 2024-10-27 03:00 CEST are both 01:00 UTC
+        let dst_starts = utc(2024, 3, 31, 1, 0);
+        assert_eq!(berlin.offset_at(dst_starts - 1), 3600);
+        assert_eq!(berlin.offset_at(dst_starts), 7200);
+        let dst_ends = utc(2024, 10, 27, 1, 0);
+        assert_eq!(berlin.offset_at(dst_ends - 1), 7200);
+        assert_eq!(berlin.offset_at(dst_ends), 3600);
+    }
+
+    #[test]
+    fn julian_days_skip_february_29_but_zero_based_days_count_it() {
+        // Jn never names February 29, so J60 is March 1 in every year
+        for year in [2023, 2024] {
+            assert_eq!(
+                days_from_civil_date(year, &RuleDate::Julian(60)),
+                days_from_civil(year, 3, 1)
+            );
+        }
+        assert_eq!(
+            days_from_civil_date(2024, &RuleDate::Julian(59)),
+            days_from_civil(2024, 2, 28)
+        );
+        assert_eq!(
+            days_from_civil_date(2024, &RuleDate::ZeroBased(59)),
+            days_from_civil(2024, 2, 29)
+        );
+        assert_eq!(
+            days_from_civil_date(2023, &RuleDate::ZeroBased(59)),
+            days_from_civil(2023, 3, 1)
+        );
+    }
+
+    #[test]
+    fn day_of_year_and_numeric_offset_conversions() {
+        let new_years_eve = |year| utc(year, 12, 31, 23, 59) as u64;
+        assert_eq!(format_timestamp(new_years_eve(2023), "%j", false), "365");
+        assert_eq!(format_timestamp(new_years_eve(2024), "%j", false), "366");
+        assert_eq!(
+            format_timestamp(utc(2024, 1, 1, 0, 0) as u64, "%j %z %:z", false),
+            "001 +0000 +00:00"
+        );
+        assert_eq!(
+            format_timestamp(utc(2024, 3, 1, 12, 30) as u64, "%F %T %Z", false),
+            "2024-03-01 12:30:00 UTC"
+        );
+        assert!(parse_time_format("%Y-%j %z").is_ok());
+        assert!(parse_time_format("%Y %q").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! How hard the runner itself worked: requests sent, bytes downloaded and concurrency.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::concurrency::InFlightStats;
+
+// Every request sent to an environment: each attempt, setup, teardown and warmup request
+static REQUESTS_SENT: AtomicU64 = AtomicU64::new(0);
+// Response body bytes read, as they arrive
+static BYTES_DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+// Approximate size of the tests' own requests and responses, headers included
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+pub fn count_request() {
+    REQUESTS_SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn count_bytes(bytes: usize) {
+    BYTES_DOWNLOADED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn count_transfer(sent: u64, received: u64) {
+    BYTES_SENT.fetch_add(sent, Ordering::Relaxed);
+    BYTES_RECEIVED.fetch_add(received, Ordering::Relaxed);
+}
+
+/// The counters at one point in time. The counters only grow, so a run's usage is the
+/// difference between the snapshots taken at its start and end.
+#[derive(Clone, Copy)]
+pub struct Counters {
+    requests: u64,
+    bytes: u64,
+    sent: u64,
+    received: u64,
+}
+
+impl Counters {
+    pub fn now() -> Self {
+        Counters {
+            requests: REQUESTS_SENT.load(Ordering::Relaxed),
+            bytes: BYTES_DOWNLOADED.load(Ordering::Relaxed),
+            sent: BYTES_SENT.load(Ordering::Relaxed),
+            received: BYTES_RECEIVED.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// `resource_usage` in the JSON summary.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceUsage {
+    pub requests_sent: u64,
+    pub bytes_downloaded: u64,
+    // Sum of the results' request_bytes and response_bytes
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    // Tests in flight, averaged over the time requests were being dispatched
+    pub average_in_flight: f64,
+    pub peak_in_flight: usize,
+    // requests_sent over the duration of the run
+    pub requests_per_sec: f64,
+}
+
+impl ResourceUsage {
+    pub fn since(start: Counters, in_flight: &InFlightStats, duration_secs: f64) -> Self {
+        let end = Counters::now();
+        let requests_sent = end.requests - start.requests;
+        ResourceUsage {
+            requests_sent,
+            bytes_downloaded: end.bytes - start.bytes,
+            bytes_sent: end.sent - start.sent,
+            bytes_received: end.received - start.received,
+            average_in_flight: in_flight.average(),
+            peak_in_flight: in_flight.peak(),
+            requests_per_sec: if duration_secs > 0.0 {
+                requests_sent as f64 / duration_secs
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. "1.5 MiB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
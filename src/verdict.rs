@@ -0,0 +1,114 @@
+//! `--short-circuit`: an environment's verdict under `--fail-on` is often settled before all
+//! of its paths ran, e.g. by a failing critical path. From then on its remaining paths are
+//! no longer dispatched, and are listed as skipped with "not run (verdict decided)".
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{counts_as_failure, truncate_string, Args, FailOn, Severity, UrlTestResult};
+
+/// Reason recorded for the paths an early verdict left out.
+pub const NOT_RUN: &str = "not run (verdict decided)";
+
+/// Only the per-result policies can be decided while the run is going.
+pub fn validate_args(args: &Args) -> Result<(), String> {
+    if args.short_circuit && !matches!(args.fail_on, FailOn::Any | FailOn::Critical) {
+        return Err("--short-circuit needs --fail-on any or --fail-on critical".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Passed,
+    Failed,
+}
+
+/// An environment whose verdict was settled before all of its paths ran.
+#[derive(Debug, Clone, Serialize)]
+pub struct EarlyVerdict {
+    pub verdict: Verdict,
+    // What settled it, e.g. "critical path /login failed"
+    pub reason: String,
+    // Paths left undispatched because of it
+    pub not_run: usize,
+}
+
+/// Follows one environment's results as they come in until its verdict can't change:
+/// with `--fail-on any` at the first failure, with `--fail-on critical` at the first
+/// critical failure or once every critical path passed.
+pub struct VerdictTracker {
+    policy: FailOn,
+    // Critical paths without a result yet
+    critical_left: usize,
+    decided: Option<(Verdict, String)>,
+}
+
+impl VerdictTracker {
+    pub fn new(policy: FailOn, severities: impl Iterator<Item = Severity>) -> Self {
+        VerdictTracker {
+            policy,
+            critical_left: severities
+                .filter(|severity| *severity == Severity::Critical)
+                .count(),
+            decided: None,
+        }
+    }
+
+    pub fn record(&mut self, result: &UrlTestResult) {
+        if self.decided.is_some() {
+            return;
+        }
+        let critical = result.severity == Severity::Critical;
+        self.decided = match self.policy {
+            FailOn::Any if counts_as_failure(result) => {
+                Some((Verdict::Failed, format!("{} failed", result.path)))
+            }
+            FailOn::Critical if critical && counts_as_failure(result) => Some((
+                Verdict::Failed,
+                format!("critical path {} failed", result.path),
+            )),
+            FailOn::Critical if critical => {
+                self.critical_left = self.critical_left.saturating_sub(1);
+                (self.critical_left == 0)
+                    .then(|| (Verdict::Passed, "every critical path passed".to_string()))
+            }
+            _ => None,
+        };
+    }
+
+    pub fn is_decided(&self) -> bool {
+        self.decided.is_some()
+    }
+
+    /// The early verdict, with the number of paths it left out.
+    pub fn into_early_verdict(self, not_run: usize) -> Option<EarlyVerdict> {
+        self.decided.map(|(verdict, reason)| EarlyVerdict {
+            verdict,
+            reason,
+            not_run,
+        })
+    }
+}
+
+/// Lists the environments decided early, so a short run isn't mistaken for a full one.
+pub fn print_early_verdicts(decided: &BTreeMap<String, EarlyVerdict>) {
+    if decided.is_empty() {
+        return;
+    }
+    println!("\n--- Decided Early (--short-circuit) ---");
+    for (env_name, early) in decided {
+        println!(
+            "{: <10} | {} | {}; {} path{} not run",
+            truncate_string(env_name, 10),
+            match early.verdict {
+                Verdict::Passed => "PASSED",
+                Verdict::Failed => "FAILED",
+            },
+            early.reason,
+            early.not_run,
+            if early.not_run == 1 { "" } else { "s" }
+        );
+    }
+}